@@ -0,0 +1,7 @@
+//! Exposes build details to the kernel (see `version_info`)
+
+fn main() {
+  let target = std::env::var("TARGET").unwrap_or_else(|_| "unknown".into());
+  println!("cargo:rustc-env=EMBER_OS_TARGET={}", target);
+  println!("cargo:rerun-if-changed=build.rs");
+}
@@ -1,21 +1,80 @@
 #![allow(dead_code)]
 
+#[cfg(not(any(
+  feature = "use_BumpAllocator",
+  feature = "use_LinkedListAllocator",
+  feature = "use_FixedSizeBlockAllocator",
+  feature = "use_LockedHeapAllocator",
+)))]
+compile_error!(
+  "no allocator selected, enable exactly one of the `use_BumpAllocator`, \
+   `use_LinkedListAllocator`, `use_FixedSizeBlockAllocator` and \
+   `use_LockedHeapAllocator` features"
+);
+
+#[cfg(any(
+  all(feature = "use_BumpAllocator", feature = "use_LinkedListAllocator"),
+  all(feature = "use_BumpAllocator", feature = "use_FixedSizeBlockAllocator"),
+  all(feature = "use_BumpAllocator", feature = "use_LockedHeapAllocator"),
+  all(
+    feature = "use_LinkedListAllocator",
+    feature = "use_FixedSizeBlockAllocator"
+  ),
+  all(
+    feature = "use_LinkedListAllocator",
+    feature = "use_LockedHeapAllocator"
+  ),
+  all(
+    feature = "use_FixedSizeBlockAllocator",
+    feature = "use_LockedHeapAllocator"
+  ),
+))]
+compile_error!(
+  "multiple allocators selected, enable exactly one of the `use_BumpAllocator`, \
+   `use_LinkedListAllocator`, `use_FixedSizeBlockAllocator` and \
+   `use_LockedHeapAllocator` features (with `--no-default-features` to drop \
+   the default `use_FixedSizeBlockAllocator`)"
+);
+
+use crate::serial_println;
+use bootloader::{bootinfo::MemoryRegionType, BootInfo};
 use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
 use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 use x86_64::{
   structures::paging::{
-    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+    mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
   },
   VirtAddr,
 };
 
 pub mod bump;
+pub mod emergency;
 pub mod fixed_size_block;
 pub mod linked_list;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 512 * 1024; // 512 KiB
 pub const HEAP_START_PTR: *mut u8 = HEAP_START as *mut u8;
+/// Upper bound of the heap sized by `init_heap_from_map`
+pub const MAX_HEAP_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+
+/// Size of the currently initialized heap (`0` before initialization)
+static CURRENT_HEAP_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Start of the heap (`HEAP_START` unless initialized by `init_heap_with`)
+static CURRENT_HEAP_START: AtomicUsize = AtomicUsize::new(HEAP_START);
+
+/// Get the size of the currently initialized heap
+pub fn heap_size() -> usize {
+  CURRENT_HEAP_SIZE.load(Ordering::Relaxed)
+}
+
+/// Get the start of the heap
+pub fn heap_start() -> usize {
+  CURRENT_HEAP_START.load(Ordering::Relaxed)
+}
 
 /// `zero-sized` type
 pub struct Dummy;
@@ -48,6 +107,133 @@ impl<T> Locked<T> {
   }
 }
 
+/// Heap usage of an allocator
+pub trait HeapUsage {
+  /// Bytes currently handed out (including padding and bookkeeping)
+  fn used_bytes(&self) -> usize;
+  /// Bytes still available
+  fn free_bytes(&self) -> usize;
+}
+
+/// Returned by `HeapExtend::extend` for a region not adjoining the heap end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotContiguous;
+
+/// Allocators able to take over more memory after `init`
+pub trait HeapExtend {
+  /// Add the `size` bytes at `start` to the heap
+  ///
+  /// # Safety
+  ///
+  /// The caller must ensure that the region is `valid` and `unused`.
+  unsafe fn extend(&mut self, start: usize, size: usize) -> Result<(), NotContiguous>;
+}
+
+/// Snapshot of the usage of `ALLOCATOR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+  pub used: usize,
+  pub free: usize,
+  /// `used + free`
+  pub total: usize,
+}
+
+/// Get the usage of `ALLOCATOR`
+pub fn heap_stats() -> HeapStats {
+  let allocator = ALLOCATOR.lock();
+  let (used, free) = (allocator.used_bytes(), allocator.free_bytes());
+  HeapStats {
+    used,
+    free,
+    total: used + free,
+  }
+}
+
+impl HeapExtend for linked_list_allocator::LockedHeap {
+  /// Only regions right behind the heap can be added
+  unsafe fn extend(&mut self, start: usize, size: usize) -> Result<(), NotContiguous> {
+    let mut heap = self.lock();
+    if start != heap.top() as usize {
+      return Err(NotContiguous);
+    }
+    heap.extend(size);
+    Ok(())
+  }
+}
+
+impl HeapUsage for linked_list_allocator::LockedHeap {
+  fn used_bytes(&self) -> usize {
+    self.lock().used()
+  }
+  fn free_bytes(&self) -> usize {
+    self.lock().free()
+  }
+}
+
+/// Number of allocations rejected for exceeding the whole heap
+static OVERSIZED_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the number of allocations rejected for exceeding the whole heap
+pub fn oversized_allocations() -> usize {
+  OVERSIZED_ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// The callback invoked on a failed allocation, as a pointer (`null` => `report_oom`)
+///
+/// Lock free, so an allocation failing inside an interrupt handler can't spin.
+static OOM_CALLBACK: AtomicPtr<()> = AtomicPtr::new(null_mut());
+
+/// Install the callback invoked with the layout of each failed allocation
+///
+/// It runs after the allocator lock has been released (so it may call
+/// `heap_stats`), right before `null` is returned to the caller.
+pub fn set_oom_callback(callback: fn(Layout)) {
+  OOM_CALLBACK.store(callback as *mut (), Ordering::SeqCst);
+}
+
+/// The default `oom` callback, printing the failing layout over serial
+///
+/// Takes no lock and never allocates: the failing allocation may have been
+/// made with `WRITER` (or `SERIAL1`) held.
+pub fn report_oom(layout: Layout) {
+  use core::fmt::Write;
+  let _ = writeln!(
+    crate::serial::RawWriter,
+    "out of memory: {} bytes (align {})",
+    layout.size(),
+    layout.align()
+  );
+}
+
+fn on_oom(layout: Layout) {
+  let callback = OOM_CALLBACK.load(Ordering::SeqCst);
+  let callback: fn(Layout) = if callback.is_null() {
+    report_oom
+  } else {
+    // SAFETY: a non-null value is only ever stored from a `fn(Layout)` in
+    // `set_oom_callback`, and function pointers round trip through `*mut ()`
+    unsafe { core::mem::transmute::<*mut (), fn(Layout)>(callback) }
+  };
+  callback(layout);
+}
+
+/// Check whether `layout` could never fit into a heap of `heap_size` bytes
+///
+/// Such requests are reported over serial (which never allocates), to tell
+/// them apart from an ordinary `OOM` caused by fragmentation.
+fn exceeds_heap(layout: &Layout, heap_size: usize) -> bool {
+  if layout.size() <= heap_size {
+    return false;
+  }
+  OVERSIZED_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+  serial_println!(
+    "allocation of {} bytes exceeds heap size {}",
+    layout.size(),
+    heap_size
+  );
+  true
+}
+
 /// Align the given address `addr` upwards to alignment `align`.
 #[deprecated]
 #[allow(dead_code)]
@@ -79,17 +265,160 @@ use linked_list::LinkedListAllocator as AllocatorType;
 #[cfg(feature = "use_LockedHeapAllocator")]
 use linked_list_allocator::LockedHeap as AllocatorType;
 
-#[global_allocator]
+/// The main heap allocator
 pub static ALLOCATOR: Locked<AllocatorType> = Locked::new(AllocatorType::new());
 
+/// `ALLOCATOR`, unless `emergency::set_emergency_allocator` has been called
+pub struct KernelAllocator;
+
+/// Name of the allocator compiled in
+pub const ALLOCATOR_NAME: &str = if cfg!(feature = "use_BumpAllocator") {
+  "bump"
+} else if cfg!(feature = "use_LinkedListAllocator") {
+  "linked list"
+} else if cfg!(feature = "use_FixedSizeBlockAllocator") {
+  "fixed size block"
+} else {
+  "linked_list_allocator::LockedHeap"
+};
+
+#[global_allocator]
+static KERNEL_ALLOCATOR: KernelAllocator = KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let ptr = if emergency::in_emergency_mode() {
+      emergency::EMERGENCY_ARENA.alloc(layout.size(), layout.align())
+    } else {
+      ALLOCATOR.alloc(layout)
+    };
+    if ptr.is_null() {
+      on_oom(layout);
+    }
+    ptr
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    // the arena is never freed, and the main heap is left alone once it is
+    // suspected to be corrupt
+    if !emergency::EMERGENCY_ARENA.contains(ptr) && !emergency::in_emergency_mode() {
+      ALLOCATOR.dealloc(ptr, layout)
+    }
+  }
+
+  /// Resized by `ALLOCATOR` (in place if it can), blocks of the emergency
+  /// arena (or any block in emergency mode) are moved instead
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    if emergency::EMERGENCY_ARENA.contains(ptr) || emergency::in_emergency_mode() {
+      let new_ptr = self.alloc(new_layout);
+      if !new_ptr.is_null() {
+        core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+        self.dealloc(ptr, layout);
+      }
+      return new_ptr;
+    }
+    let new_ptr = ALLOCATOR.realloc(ptr, layout, new_size);
+    if new_ptr.is_null() {
+      on_oom(new_layout);
+    }
+    new_ptr
+  }
+}
+
+const PAGE_SIZE: usize = 4096;
+
+/// Why the heap could not be initialized
+#[derive(Debug)]
+pub enum InitHeapError {
+  /// The start of the heap is not page-aligned
+  UnalignedStart(usize),
+  EmptyHeap,
+  Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for InitHeapError {
+  fn from(err: MapToError<Size4KiB>) -> Self {
+    InitHeapError::Map(err)
+  }
+}
+
+impl fmt::Display for InitHeapError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      InitHeapError::UnalignedStart(start) => {
+        write!(f, "heap start {:#x} is not page-aligned", start)
+      }
+      InitHeapError::EmptyHeap => write!(f, "heap size is zero"),
+      InitHeapError::Map(err) => write!(f, "mapping the heap failed: {:?}", err),
+    }
+  }
+}
+
+/// Check that a heap of `size` bytes could start at `start`
+pub fn check_heap_region(start: usize, size: usize) -> Result<(), InitHeapError> {
+  if align_up(start, PAGE_SIZE) != start {
+    return Err(InitHeapError::UnalignedStart(start));
+  }
+  if size == 0 {
+    return Err(InitHeapError::EmptyHeap);
+  }
+  Ok(())
+}
+
+/// Map `[HEAP_START, HEAP_START + HEAP_SIZE)` and initialize `ALLOCATOR` on it
+///
+/// The fixed size keeps the heap layout deterministic (e.g. for tests).
 pub fn init_heap(
   mapper: &mut impl Mapper<Size4KiB>,
   frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), InitHeapError> {
+  init_heap_with(HEAP_START, HEAP_SIZE, mapper, frame_allocator)
+}
+
+/// Map a heap sized after the usable physical memory, then initialize `ALLOCATOR` on it
+///
+/// Sizing policy (see `heap_size_for`):
+///
+/// - take a quarter of all `Usable` regions in the memory map
+/// - round it down to whole pages
+/// - clamp it into `[HEAP_SIZE, MAX_HEAP_SIZE]`
+pub fn init_heap_from_map(
+  boot_info: &'static BootInfo,
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), InitHeapError> {
+  let usable_bytes = boot_info
+    .memory_map
+    .iter()
+    .filter(|r| r.region_type == MemoryRegionType::Usable)
+    .map(|r| r.range.end_addr() - r.range.start_addr())
+    .sum();
+  init_heap_with(
+    HEAP_START,
+    heap_size_for(usable_bytes),
+    mapper,
+    frame_allocator,
+  )
+}
+
+/// Heap size chosen by `init_heap_from_map` for `usable_bytes` of physical memory
+pub fn heap_size_for(usable_bytes: u64) -> usize {
+  let quarter = (usable_bytes / 4).min(MAX_HEAP_SIZE as u64) as usize;
+  (quarter - quarter % PAGE_SIZE).clamp(HEAP_SIZE, MAX_HEAP_SIZE)
+}
+
+/// Map `[start, start + size)` to fresh physical frames
+fn map_heap_pages(
+  start: usize,
+  size: usize,
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
   // get page_range
   let page_range = {
-    let heap_start = VirtAddr::new(HEAP_START as u64);
-    let heap_end = heap_start + HEAP_SIZE as u64 - 1u64;
+    let heap_start = VirtAddr::new(start as u64);
+    let heap_end = heap_start + size as u64 - 1u64;
     let heap_start_page = Page::containing_address(heap_start);
     let heap_end_page = Page::containing_address(heap_end);
     Page::range_inclusive(heap_start_page, heap_end_page)
@@ -103,11 +432,152 @@ pub fn init_heap(
     let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
     unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
   }
+  Ok(())
+}
+
+/// Map `[start, start + size)` and initialize `ALLOCATOR` on it
+///
+/// `start` must be page-aligned and `size` nonzero (see `check_heap_region`).
+pub fn init_heap_with(
+  start: usize,
+  size: usize,
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), InitHeapError> {
+  check_heap_region(start, size)?;
+  map_heap_pages(start, size, mapper, frame_allocator)?;
 
   // init `ALLOCATOR`
   unsafe {
-    ALLOCATOR.lock().init(HEAP_START_PTR, HEAP_SIZE);
+    #[cfg(any(feature = "use_BumpAllocator", feature = "use_LinkedListAllocator"))]
+    ALLOCATOR.lock().init(start, size);
+    #[cfg(not(any(feature = "use_BumpAllocator", feature = "use_LinkedListAllocator")))]
+    ALLOCATOR.lock().init(start as *mut u8, size);
+  }
+  CURRENT_HEAP_START.store(start, Ordering::Relaxed);
+  CURRENT_HEAP_SIZE.store(size, Ordering::Relaxed);
+
+  #[cfg(debug_assertions)]
+  if let Err(conflict) = crate::memory::check_address_space() {
+    panic!("address space conflict: {}\n", conflict);
   }
 
   Ok(())
 }
+
+/// Why `grow_heap` failed
+#[derive(Debug)]
+pub enum GrowHeapError {
+  /// The grown heap would overlap another region
+  Conflict(crate::memory::Conflict),
+  Map(MapToError<Size4KiB>),
+  /// The allocator can't take over the new pages
+  NotContiguous,
+}
+
+/// Unmap the mapped pages of `[start, start + size)` and free their frames
+fn unmap_heap_pages<A>(
+  start: usize,
+  size: usize,
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut A,
+) where
+  A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+  let first = Page::containing_address(VirtAddr::new(start as u64));
+  let last = Page::containing_address(VirtAddr::new((start + size - 1) as u64));
+  for page in Page::range_inclusive(first, last) {
+    if let Ok(frame) = crate::memory::unmap_page(mapper, page) {
+      unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+  }
+}
+
+/// Map at least `additional_bytes` (rounded up to whole pages) right above
+/// the heap, and hand them to `ALLOCATOR`
+///
+/// On failure, the pages mapped so far are unmapped and their frames freed
+/// again, so a later call can retry.
+pub fn grow_heap<A>(
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut A,
+  additional_bytes: usize,
+) -> Result<(), GrowHeapError>
+where
+  A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+  let additional = align_up(additional_bytes, PAGE_SIZE);
+  if additional == 0 {
+    return Ok(());
+  }
+  crate::memory::check_heap_extension(additional).map_err(GrowHeapError::Conflict)?;
+
+  let heap_end = heap_start() + heap_size();
+  let result = map_heap_pages(heap_end, additional, mapper, frame_allocator)
+    .map_err(GrowHeapError::Map)
+    .and_then(|()| {
+      unsafe { ALLOCATOR.lock().extend(heap_end, additional) }
+        .map_err(|NotContiguous| GrowHeapError::NotContiguous)
+    });
+  if let Err(err) = result {
+    unmap_heap_pages(heap_end, additional, mapper, frame_allocator);
+    return Err(err);
+  }
+  CURRENT_HEAP_SIZE.fetch_add(additional, Ordering::Relaxed);
+  Ok(())
+}
+
+#[test_case]
+fn test_allocation_exceeding_heap() {
+  let layout = Layout::from_size_align(1024 * 1024, 8).unwrap(); // 1 MiB > 512 KiB
+  let oversized_before = oversized_allocations();
+  let ptr = unsafe { ALLOCATOR.alloc(layout) };
+  assert!(ptr.is_null());
+  assert_eq!(oversized_allocations(), oversized_before + 1);
+}
+
+#[test_case]
+fn test_oom_callback() {
+  static FAILED_SIZE: AtomicUsize = AtomicUsize::new(0);
+  fn record(layout: Layout) {
+    // the lock must be free again
+    assert!(heap_stats().total > 0);
+    FAILED_SIZE.store(layout.size(), Ordering::SeqCst);
+  }
+
+  set_oom_callback(record);
+  let layout = Layout::from_size_align(2 * heap_size(), 8).unwrap();
+  let ptr = unsafe { alloc::alloc::alloc(layout) };
+  set_oom_callback(report_oom);
+  assert!(ptr.is_null());
+  assert_eq!(FAILED_SIZE.load(Ordering::SeqCst), 2 * heap_size());
+}
+
+#[test_case]
+fn test_check_heap_region() {
+  assert!(check_heap_region(HEAP_START, 16 * 1024).is_ok());
+  assert!(matches!(
+    check_heap_region(HEAP_START + 8, 16 * 1024),
+    Err(InitHeapError::UnalignedStart(start)) if start == HEAP_START + 8
+  ));
+  assert!(matches!(
+    check_heap_region(HEAP_START, 0),
+    Err(InitHeapError::EmptyHeap)
+  ));
+}
+
+#[cfg(feature = "use_LinkedListAllocator")]
+#[test_case]
+fn test_global_vec_resizes_in_place() {
+  use alloc::vec::Vec;
+
+  let mut vec = Vec::<u8>::with_capacity(2048);
+  let ptr = vec.as_ptr();
+  // shrinking gives the tail back, right behind `vec`
+  vec.shrink_to(64);
+  assert_eq!(vec.as_ptr(), ptr);
+  // so growing can take it again
+  vec.reserve_exact(1024);
+  assert_eq!(vec.as_ptr(), ptr);
+  assert!(vec.capacity() >= 1024);
+}
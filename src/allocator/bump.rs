@@ -1,6 +1,6 @@
 #![allow(deprecated)]
 
-use super::{align_up, Locked};
+use super::{align_up, exceeds_heap, HeapExtend, HeapUsage, Locked, NotContiguous};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::{self};
 
@@ -47,11 +47,35 @@ impl Default for BumpAllocator {
   }
 }
 
+impl HeapExtend for BumpAllocator {
+  /// Only regions right behind the heap can be added
+  unsafe fn extend(&mut self, start: usize, size: usize) -> Result<(), NotContiguous> {
+    if start != self.heap_end {
+      return Err(NotContiguous);
+    }
+    self.heap_end += size;
+    Ok(())
+  }
+}
+
+impl HeapUsage for BumpAllocator {
+  fn used_bytes(&self) -> usize {
+    self.next - self.heap_start
+  }
+  fn free_bytes(&self) -> usize {
+    self.heap_end - self.next
+  }
+}
+
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
   /// Allocate on the global bump allocator
   unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
     let mut bump = self.lock();
 
+    if exceeds_heap(&layout, bump.heap_end - bump.heap_start) {
+      return ptr::null_mut();
+    }
+
     let alloc_start = align_up(bump.next, layout.align());
     let alloc_end = match alloc_start.checked_add(layout.size()) {
       Some(end) => end,
@@ -82,3 +106,14 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     }
   }
 }
+
+#[test_case]
+fn test_extend_requires_contiguous_region() {
+  let mut bump = BumpAllocator::new();
+  unsafe {
+    bump.init(0x1000, 0x1000);
+    assert_eq!(bump.extend(0x3000, 0x1000), Err(NotContiguous));
+    assert_eq!(bump.extend(0x2000, 0x1000), Ok(()));
+  }
+  assert_eq!(bump.free_bytes(), 0x2000);
+}
@@ -0,0 +1,102 @@
+//! Allocator used by the panic handler once the main heap can't be trusted
+//!
+//! Once emergency mode is on, every allocation comes from a small static
+//! arena which is never freed. There is no way back: the system must halt
+//! after entering it.
+
+use super::align_up;
+use core::cell::UnsafeCell;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Size of the emergency arena
+pub const EMERGENCY_ARENA_SIZE: usize = 4096;
+
+/// Whether allocations are routed to the emergency arena
+static EMERGENCY_MODE: AtomicBool = AtomicBool::new(false);
+
+/// A lock-free bump allocator on a static arena
+///
+/// Lock-free, so that it still works when the panic happened while holding
+/// a lock.
+pub struct EmergencyArena {
+  arena: UnsafeCell<[u8; EMERGENCY_ARENA_SIZE]>,
+  /// offset of the first free byte
+  next: AtomicUsize,
+}
+
+// the arena is only handed out in disjoint chunks
+unsafe impl Sync for EmergencyArena {}
+
+pub static EMERGENCY_ARENA: EmergencyArena = EmergencyArena {
+  arena: UnsafeCell::new([0; EMERGENCY_ARENA_SIZE]),
+  next: AtomicUsize::new(0),
+};
+
+impl EmergencyArena {
+  fn start(&self) -> usize {
+    self.arena.get() as usize
+  }
+
+  /// Check whether `ptr` points into the arena
+  pub fn contains(&self, ptr: *const u8) -> bool {
+    (self.start()..self.start() + EMERGENCY_ARENA_SIZE).contains(&(ptr as usize))
+  }
+
+  /// Bump-allocate `size` bytes aligned to `align`, or return null if the arena is exhausted
+  pub fn alloc(&self, size: usize, align: usize) -> *mut u8 {
+    let start = self.start();
+    let mut next = self.next.load(Ordering::Relaxed);
+    loop {
+      let alloc_start = align_up(start + next, align);
+      let alloc_end = match alloc_start.checked_add(size) {
+        Some(end) if end <= start + EMERGENCY_ARENA_SIZE => end,
+        _ => return null_mut(),
+      };
+      match self.next.compare_exchange_weak(
+        next,
+        alloc_end - start,
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+      ) {
+        Ok(_) => return alloc_start as *mut u8,
+        Err(current) => next = current,
+      }
+    }
+  }
+}
+
+/// Route all further allocations to the emergency arena
+///
+/// Meant for the panic handler, when the main heap may be corrupt. Memory
+/// of the main heap is leaked from then on, the system must halt afterwards.
+pub fn set_emergency_allocator() {
+  EMERGENCY_MODE.store(true, Ordering::SeqCst);
+}
+
+/// Check whether `set_emergency_allocator` has been called
+pub fn in_emergency_mode() -> bool {
+  EMERGENCY_MODE.load(Ordering::SeqCst)
+}
+
+/// Leave emergency mode, only sound for tests which never freed an arena pointer
+#[cfg(test)]
+fn leave_emergency_mode() {
+  EMERGENCY_MODE.store(false, Ordering::SeqCst);
+}
+
+#[test_case]
+fn test_emergency_allocation_uses_arena() {
+  use alloc::boxed::Box;
+
+  set_emergency_allocator();
+  let value = Box::new(0xdead_beef_u64);
+  let in_arena = EMERGENCY_ARENA.contains(&*value as *const u64 as *const u8);
+  // freeing arena memory is a no-op
+  drop(value);
+  leave_emergency_mode();
+
+  assert!(in_arena);
+  let value = Box::new(0xdead_beef_u64);
+  assert!(!EMERGENCY_ARENA.contains(&*value as *const u64 as *const u8));
+}
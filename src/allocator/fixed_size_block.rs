@@ -1,4 +1,4 @@
-use super::Locked;
+use super::{exceeds_heap, HeapExtend, HeapUsage, Locked, NotContiguous};
 use core::alloc::{GlobalAlloc, Layout};
 use core::{
   mem,
@@ -59,6 +59,78 @@ impl FixedSizeBlockAllocator {
   }
 }
 
+impl FixedSizeBlockAllocator {
+  /// Total size of the blocks waiting in the lists
+  fn listed_bytes(&self) -> usize {
+    let mut listed = 0;
+    for (head, &block_size) in self.list_heads.iter().zip(BLOCK_SIZES) {
+      let mut current = head;
+      while let Some(node) = current {
+        listed += block_size;
+        current = &node.next;
+      }
+    }
+    listed
+  }
+}
+
+impl HeapExtend for FixedSizeBlockAllocator {
+  /// Only regions right behind the heap can be added (to the fallback allocator)
+  unsafe fn extend(&mut self, start: usize, size: usize) -> Result<(), NotContiguous> {
+    if start != self.fallback_allocator.top() as usize {
+      return Err(NotContiguous);
+    }
+    self.fallback_allocator.extend(size);
+    Ok(())
+  }
+}
+
+impl HeapUsage for FixedSizeBlockAllocator {
+  /// Blocks handed out plus fallback allocations
+  fn used_bytes(&self) -> usize {
+    self.fallback_allocator.used() - self.listed_bytes()
+  }
+  /// Free fallback memory plus the listed blocks
+  fn free_bytes(&self) -> usize {
+    self.fallback_allocator.free() + self.listed_bytes()
+  }
+}
+
+/// Byte filling freed blocks (behind their `ListNode`) with `alloc_debug`
+#[cfg(feature = "alloc_debug")]
+pub const POISON: u8 = 0xDE;
+
+#[cfg(feature = "alloc_debug")]
+impl FixedSizeBlockAllocator {
+  /// Panic if `ptr` is already in the list of `index`
+  fn check_double_free(&self, ptr: *mut u8, index: usize) {
+    let mut current = &self.list_heads[index];
+    while let Some(node) = current {
+      if &**node as *const ListNode as *const u8 == ptr {
+        panic!(
+          "double free of {}-byte block at {:p}\n",
+          BLOCK_SIZES[index], ptr
+        );
+      }
+      current = &node.next;
+    }
+  }
+
+  /// Panic unless the freed block at `ptr` still carries the poison
+  fn check_poison(ptr: *mut u8, index: usize) {
+    let start = mem::size_of::<ListNode>();
+    let block = unsafe { core::slice::from_raw_parts(ptr, BLOCK_SIZES[index]) };
+    if let Some(offset) = block[start..].iter().position(|&byte| byte != POISON) {
+      panic!(
+        "use after free: {}-byte block at {:p} was written at offset {}\n",
+        BLOCK_SIZES[index],
+        ptr,
+        start + offset
+      );
+    }
+  }
+}
+
 /// Choose an appropriate block size for the given layout.
 ///
 /// Returns an index into the `BLOCK_SIZES` array.
@@ -70,10 +142,16 @@ fn list_index(layout: &Layout) -> Option<usize> {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
   unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
     let mut allocator = self.lock();
+    if exceeds_heap(&layout, allocator.fallback_allocator.size()) {
+      return ptr::null_mut();
+    }
     if let Some(index) = list_index(&layout) {
       if let Some(node) = allocator.list_heads[index].take() {
         allocator.list_heads[index] = node.next.take();
-        node as *mut ListNode as *mut u8
+        let ptr = node as *mut ListNode as *mut u8;
+        #[cfg(feature = "alloc_debug")]
+        FixedSizeBlockAllocator::check_poison(ptr, index);
+        ptr
       } else {
         // no block exists in list => allocate new block
         let block_size = BLOCK_SIZES[index];
@@ -91,6 +169,12 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
   unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
     let mut allocator = self.lock();
     if let Some(index) = list_index(&layout) {
+      #[cfg(feature = "alloc_debug")]
+      {
+        allocator.check_double_free(ptr, index);
+        ptr.write_bytes(POISON, BLOCK_SIZES[index]);
+      }
+
       let new_node = ListNode {
         next: allocator.list_heads[index].take(),
       };
@@ -1,4 +1,4 @@
-use super::{align_up, Locked};
+use super::{align_up, exceeds_heap, HeapExtend, HeapUsage, Locked, NotContiguous};
 use core::alloc::{GlobalAlloc, Layout};
 use core::{mem, ptr};
 
@@ -24,8 +24,29 @@ impl ListNode {
   }
 }
 
+/// How `LinkedListAllocator` picks the free region of an allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitPolicy {
+  /// The first region large enough (fast)
+  FirstFit,
+  /// The smallest region large enough (less fragmentation)
+  BestFit,
+}
+
+impl FitPolicy {
+  /// `BestFit` with the `linked_list_best_fit` feature, `FirstFit` otherwise
+  pub const DEFAULT: Self = if cfg!(feature = "linked_list_best_fit") {
+    FitPolicy::BestFit
+  } else {
+    FitPolicy::FirstFit
+  };
+}
+
 pub struct LinkedListAllocator {
   head: ListNode,
+  /// Total size of the managed heap
+  heap_size: usize,
+  policy: FitPolicy,
 }
 
 impl LinkedListAllocator {
@@ -33,9 +54,21 @@ impl LinkedListAllocator {
   pub const fn new() -> Self {
     Self {
       head: ListNode::new(0),
+      heap_size: 0,
+      policy: FitPolicy::DEFAULT,
     }
   }
 
+  /// Current region selection policy
+  pub fn fit_policy(&self) -> FitPolicy {
+    self.policy
+  }
+
+  /// Change the region selection policy, affecting later allocations only
+  pub fn set_fit_policy(&mut self, policy: FitPolicy) {
+    self.policy = policy;
+  }
+
   /// Initialize the allocator with the given heap bounds.
   ///
   /// # Safety
@@ -45,6 +78,7 @@ impl LinkedListAllocator {
   ///
   /// This method must be called `only once`.
   pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    self.heap_size = heap_size;
     self.add_free_region(heap_start, heap_size);
   }
 
@@ -82,6 +116,17 @@ impl LinkedListAllocator {
   ///
   /// Returns a tuple of the `list node` and the `start address` of the allocation.
   fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+    match self.policy {
+      FitPolicy::FirstFit => self.find_first_fit(size, align),
+      FitPolicy::BestFit => self.find_best_fit(size, align),
+    }
+  }
+
+  fn find_first_fit(
+    &mut self,
+    size: usize,
+    align: usize,
+  ) -> Option<(&'static mut ListNode, usize)> {
     // reference to current list node, updated for each iteration
     let mut current = &mut self.head;
 
@@ -102,6 +147,34 @@ impl LinkedListAllocator {
     None
   }
 
+  fn find_best_fit(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+    // scan the whole list for the smallest suitable region
+    let mut best: Option<(usize, usize)> = None;
+    let mut current = &self.head;
+    while let Some(ref region) = current.next {
+      let smaller = best.is_none_or(|(_, best_size)| region.size < best_size);
+      if smaller && Self::alloc_from_region(region, size, align).is_ok() {
+        best = Some((region.start_addr(), region.size));
+      }
+      current = region;
+    }
+    let (best_addr, _) = best?;
+
+    // then unlink it
+    let mut current = &mut self.head;
+    while let Some(ref mut region) = current.next {
+      if region.start_addr() == best_addr {
+        let alloc_start = Self::alloc_from_region(region, size, align).ok()?;
+        let next = region.next.take();
+        let ret = Some((current.next.take().unwrap(), alloc_start));
+        current.next = next;
+        return ret;
+      }
+      current = current.next.as_mut().unwrap();
+    }
+    None
+  }
+
   /// Try to use the given region for an allocation
   /// with given size and alignment.
   ///
@@ -129,6 +202,50 @@ impl LinkedListAllocator {
 }
 
 impl LinkedListAllocator {
+  /// Try to resize the allocation at `addr` from `old_size` to `new_size`
+  /// (both adjusted by `size_align`) without moving it.
+  ///
+  /// Growing takes the front of the free region right behind the allocation,
+  /// shrinking returns the tail to the list. Fails rather than leaving a
+  /// remainder too small for a `ListNode`.
+  unsafe fn resize_in_place(&mut self, addr: usize, old_size: usize, new_size: usize) -> bool {
+    let old_end = addr + old_size;
+    let new_end = addr + new_size;
+    if new_size <= old_size {
+      let excess_size = old_size - new_size;
+      if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+        return false;
+      }
+      if excess_size > 0 {
+        self.add_free_region(new_end, excess_size);
+      }
+      return true;
+    }
+
+    // look for the free region starting right at the end of the allocation
+    let mut current = &mut self.head;
+    while let Some(ref mut region) = current.next {
+      if region.start_addr() == old_end {
+        if region.end_addr() < new_end {
+          return false;
+        }
+        let excess_size = region.end_addr() - new_end;
+        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+          return false;
+        }
+        // take the region out of the list, giving back what is not needed
+        let next = region.next.take();
+        current.next = next;
+        if excess_size > 0 {
+          self.add_free_region(new_end, excess_size);
+        }
+        return true;
+      }
+      current = current.next.as_mut().unwrap();
+    }
+    false
+  }
+
   /// Adjust the given layout so that the resulting allocated memory
   /// region is also capable of storing a `ListNode`.
   ///
@@ -143,12 +260,41 @@ impl LinkedListAllocator {
   }
 }
 
+impl HeapExtend for LinkedListAllocator {
+  /// Any region works, it is simply added to the free list
+  unsafe fn extend(&mut self, start: usize, size: usize) -> Result<(), NotContiguous> {
+    self.heap_size += size;
+    self.add_free_region(start, size);
+    Ok(())
+  }
+}
+
+impl HeapUsage for LinkedListAllocator {
+  /// Everything not in the free list, so alignment padding counts as used
+  fn used_bytes(&self) -> usize {
+    self.heap_size - self.free_bytes()
+  }
+  fn free_bytes(&self) -> usize {
+    let mut free = 0;
+    let mut current = &self.head;
+    while let Some(ref region) = current.next {
+      free += region.size;
+      current = region;
+    }
+    free
+  }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
   unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
     // perform layout adjustments
     let (size, align) = LinkedListAllocator::size_align(layout);
     let mut allocator = self.lock();
 
+    if exceeds_heap(&layout, allocator.heap_size) {
+      return ptr::null_mut();
+    }
+
     // try to find available region
     if let Some((region, alloc_start)) = allocator.find_region(size, align) {
       let alloc_end = alloc_start.checked_add(size).expect("overflow!\n");
@@ -169,4 +315,179 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
 
     self.lock().add_free_region(ptr as usize, size);
   }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    let (old_size, _) = LinkedListAllocator::size_align(layout);
+    let (adjusted_size, _) = LinkedListAllocator::size_align(new_layout);
+    if self
+      .lock()
+      .resize_in_place(ptr as usize, old_size, adjusted_size)
+    {
+      return ptr;
+    }
+
+    // fall back to allocate, copy and free
+    let new_ptr = self.alloc(new_layout);
+    if !new_ptr.is_null() {
+      ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+      self.dealloc(ptr, layout);
+    }
+    new_ptr
+  }
+}
+
+#[cfg(test)]
+mod test_fit_policy {
+  use super::*;
+
+  #[repr(align(16))]
+  struct Memory([u8; 256]);
+
+  /// Free regions of 64 and (listed second) 32 bytes, then 32 and 64 byte
+  /// allocations
+  fn allocate_into_holes(policy: FitPolicy) -> [bool; 2] {
+    let mut memory = Memory([0; 256]);
+    let start = memory.0.as_mut_ptr() as usize;
+    let allocator = Locked::new(LinkedListAllocator::new());
+    {
+      let mut inner = allocator.lock();
+      inner.heap_size = 96;
+      inner.set_fit_policy(policy);
+      unsafe {
+        inner.add_free_region(start + 128, 32);
+        inner.add_free_region(start, 64);
+      }
+    }
+    [32, 64].map(|size| {
+      let layout = Layout::from_size_align(size, 8).unwrap();
+      !unsafe { allocator.alloc(layout) }.is_null()
+    })
+  }
+
+  #[test_case]
+  fn first_fit_fragments() {
+    // the 32 bytes split the 64 byte region, leaving no room for 64 bytes
+    assert_eq!(allocate_into_holes(FitPolicy::FirstFit), [true, false]);
+  }
+
+  #[test_case]
+  fn best_fit_uses_the_smallest_region() {
+    assert_eq!(allocate_into_holes(FitPolicy::BestFit), [true, true]);
+  }
+
+  #[test_case]
+  fn extend_with_a_separate_region() {
+    let mut memory = Memory([0; 256]);
+    let start = memory.0.as_mut_ptr() as usize;
+    let mut allocator = LinkedListAllocator::new();
+    unsafe {
+      allocator.init(start, 64);
+      assert_eq!(allocator.extend(start + 128, 128), Ok(()));
+    }
+    assert_eq!(allocator.free_bytes(), 192);
+    // only fits into the new region
+    let (_, alloc_start) = allocator.find_region(96, 8).unwrap();
+    assert_eq!(alloc_start, start + 128);
+  }
+
+  #[test_case]
+  fn best_fit_exact_fit_leaves_no_remainder() {
+    let mut memory = Memory([0; 256]);
+    let start = memory.0.as_mut_ptr() as usize;
+    let mut allocator = LinkedListAllocator::new();
+    allocator.set_fit_policy(FitPolicy::BestFit);
+    unsafe {
+      allocator.add_free_region(start + 128, 32);
+      allocator.add_free_region(start, 64);
+    }
+    let (region, alloc_start) = allocator.find_region(32, 8).unwrap();
+    assert_eq!(alloc_start, start + 128);
+    assert_eq!(region.end_addr(), alloc_start + 32);
+    // only the 64 byte region is left
+    let rest = allocator.head.next.as_ref().unwrap();
+    assert_eq!((rest.start_addr(), rest.size), (start, 64));
+    assert!(rest.next.is_none());
+  }
+}
+
+#[cfg(test)]
+mod test_realloc {
+  use super::*;
+  use alloc::vec::Vec;
+  use core::alloc::{AllocError, Allocator};
+  use core::ptr::NonNull;
+
+  #[repr(align(16))]
+  struct Memory([u8; 1024]);
+
+  /// Lets a `Vec` use a local allocator, growing through `realloc`
+  struct ByRealloc<'a>(&'a Locked<LinkedListAllocator>);
+
+  unsafe impl Allocator for ByRealloc<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+      let ptr = NonNull::new(unsafe { self.0.alloc(layout) }).ok_or(AllocError)?;
+      Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+      self.0.dealloc(ptr.as_ptr(), layout)
+    }
+
+    unsafe fn grow(
+      &self,
+      ptr: NonNull<u8>,
+      old_layout: Layout,
+      new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+      let ptr = self.0.realloc(ptr.as_ptr(), old_layout, new_layout.size());
+      let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+      Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+  }
+
+  fn local_allocator(memory: &mut Memory) -> Locked<LinkedListAllocator> {
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(memory.0.as_mut_ptr() as usize, memory.0.len()) };
+    Locked::new(allocator)
+  }
+
+  #[test_case]
+  fn growing_vec_stays_in_place() {
+    let mut memory = Memory([0; 1024]);
+    let allocator = local_allocator(&mut memory);
+    let mut vec = Vec::new_in(ByRealloc(&allocator));
+    let mut moves = 0;
+    let mut stable = 0;
+    for i in 0..64u64 {
+      let before = vec.as_ptr();
+      vec.push(i);
+      if vec.len() > 1 && vec.as_ptr() == before {
+        stable += 1;
+      } else if vec.len() > 1 {
+        moves += 1;
+      }
+    }
+    assert!(stable > 0);
+    assert_eq!(moves, 0);
+    assert!(vec.iter().copied().eq(0..64));
+  }
+
+  #[test_case]
+  fn shrinking_returns_the_tail() {
+    let mut memory = Memory([0; 1024]);
+    let allocator = local_allocator(&mut memory);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    unsafe {
+      let ptr = allocator.alloc(layout);
+      let free_before = allocator.lock().free_bytes();
+      assert_eq!(allocator.realloc(ptr, layout, 64), ptr);
+      assert_eq!(allocator.lock().free_bytes(), free_before + 192);
+      // a sliver smaller than a `ListNode` can't be returned, so it moves
+      let layout = Layout::from_size_align(64, 8).unwrap();
+      let moved = allocator.realloc(ptr, layout, 56);
+      assert_ne!(moved, ptr);
+      allocator.dealloc(moved, Layout::from_size_align(56, 8).unwrap());
+    }
+  }
 }
@@ -0,0 +1,261 @@
+//! Local APIC + IO APIC, replacing the 8259 PICs (`use_apic` feature)
+//!
+//! The LAPIC timer takes over the timer interrupt, calibrated against the PIT
+//! so the tick rate (`time::timer_frequency_mhz`) stays the same. The legacy
+//! IRQs (keyboard, COM1, mouse) are routed through the IO APIC, found at its
+//! default address (there is no ACPI parsing). `time::set_timer_frequency`
+//! only reprograms the PIT, so it has no effect once the APIC is in charge.
+
+use crate::{
+  interrupts::{InterruptIndex, PICS, PIC_1_OFFSET},
+  memory::{self, Conflict, Region},
+  park_until, time,
+};
+use core::{
+  fmt,
+  sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::{
+  registers::model_specific::Msr,
+  structures::paging::{
+    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+  },
+  PhysAddr, VirtAddr,
+};
+
+/// Vector of the spurious interrupt (needs no `EOI`)
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// `IA32_APIC_BASE` model specific register
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+/// Global enable bit of `IA32_APIC_BASE`
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// Physical base bits of `IA32_APIC_BASE`
+const APIC_BASE_MASK: u64 = 0x000f_ffff_ffff_f000;
+/// Default physical address of the IO APIC
+const IO_APIC_BASE: u64 = 0xfec0_0000;
+
+/// Where the LAPIC (first page) and the IO APIC (second page) get mapped
+const APIC_REGION: Region = Region::new("apic", 0x_7777_0000_0000, 2 * 4096);
+
+// LAPIC registers (offsets into its MMIO page)
+const LAPIC_ID: usize = 0x20;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SPURIOUS: usize = 0xf0;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_LVT_LINT0: usize = 0x350;
+const LAPIC_TIMER_INITIAL: usize = 0x380;
+const LAPIC_TIMER_CURRENT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+/// Software enable bit of the spurious interrupt vector register
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+/// Mask bit of the LVT entries (and of the IO APIC redirection entries)
+const LVT_MASKED: u32 = 1 << 16;
+/// Periodic mode of the LVT timer entry
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide the timer input clock by 16
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+// IO APIC registers: select with `IOREGSEL`, then access through `IOWIN`
+const IO_APIC_REGSEL: usize = 0x00;
+const IO_APIC_WINDOW: usize = 0x10;
+/// First register of the redirection table (two per entry)
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+
+/// PIT ticks the LAPIC timer is measured against
+const CALIBRATION_TICKS: u64 = 2;
+
+/// Whether `init` has switched from the PICs to the APIC
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum ApicError {
+  /// `cpuid` reports no local APIC
+  NotSupported,
+  /// The virtual range of the registers is taken
+  Conflict(Conflict),
+  Map(MapToError<Size4KiB>),
+}
+
+impl From<Conflict> for ApicError {
+  fn from(conflict: Conflict) -> Self {
+    ApicError::Conflict(conflict)
+  }
+}
+
+impl From<MapToError<Size4KiB>> for ApicError {
+  fn from(err: MapToError<Size4KiB>) -> Self {
+    ApicError::Map(err)
+  }
+}
+
+impl fmt::Display for ApicError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ApicError::NotSupported => write!(f, "no local APIC"),
+      ApicError::Conflict(conflict) => write!(f, "{}", conflict),
+      ApicError::Map(err) => write!(f, "mapping the APIC registers failed: {:?}", err),
+    }
+  }
+}
+
+fn lapic_base() -> VirtAddr {
+  VirtAddr::new(APIC_REGION.start)
+}
+
+fn io_apic_base() -> VirtAddr {
+  VirtAddr::new(APIC_REGION.start + 4096)
+}
+
+fn lapic_read(register: usize) -> u32 {
+  unsafe {
+    (lapic_base() + register as u64)
+      .as_ptr::<u32>()
+      .read_volatile()
+  }
+}
+
+fn lapic_write(register: usize, value: u32) {
+  unsafe {
+    (lapic_base() + register as u64)
+      .as_mut_ptr::<u32>()
+      .write_volatile(value)
+  }
+}
+
+fn io_apic_write(register: u32, value: u32) {
+  let base = io_apic_base();
+  unsafe {
+    (base + IO_APIC_REGSEL as u64)
+      .as_mut_ptr::<u32>()
+      .write_volatile(register);
+    (base + IO_APIC_WINDOW as u64)
+      .as_mut_ptr::<u32>()
+      .write_volatile(value);
+  }
+}
+
+/// Deliver legacy `irq` as `vector` to the local APIC with id `lapic_id`
+/// (fixed delivery, edge triggered, active high)
+fn route_irq(irq: u8, vector: u8, lapic_id: u32) {
+  let entry = IO_APIC_REDIRECTION_TABLE + 2 * irq as u32;
+  io_apic_write(entry + 1, lapic_id << 24);
+  io_apic_write(entry, vector as u32);
+}
+
+/// Map the (uncached) register page at `phys` to `page`
+fn map_registers(
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+  page: Page<Size4KiB>,
+  phys: u64,
+) -> Result<(), MapToError<Size4KiB>> {
+  let frame = PhysFrame::containing_address(PhysAddr::new(phys));
+  let flags = PageTableFlags::PRESENT
+    | PageTableFlags::WRITABLE
+    | PageTableFlags::NO_CACHE
+    | PageTableFlags::WRITE_THROUGH;
+  unsafe { mapper.map_to(page, frame, flags, frame_allocator) }?.flush();
+  Ok(())
+}
+
+/// LAPIC timer counts (divided by 16) per timer tick, measured against the PIT
+///
+/// Needs the PIT timer interrupt to be running.
+fn calibrate_timer() -> u32 {
+  lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+  lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+
+  // start on a tick boundary
+  let start = time::ticks();
+  park_until(|| time::ticks() > start);
+  lapic_write(LAPIC_TIMER_INITIAL, u32::MAX);
+  let start = time::ticks();
+  park_until(|| time::ticks() >= start + CALIBRATION_TICKS);
+  let elapsed = u32::MAX - lapic_read(LAPIC_TIMER_CURRENT);
+  lapic_write(LAPIC_TIMER_INITIAL, 0);
+  (elapsed / CALIBRATION_TICKS as u32).max(1)
+}
+
+/// Switch from the PICs to the local APIC and the IO APIC
+///
+/// Must run once, with interrupts enabled and the PICs initialized (the PIT
+/// is needed to calibrate the LAPIC timer). Afterwards every PIC line is
+/// masked.
+pub fn init(
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), ApicError> {
+  let features = core::arch::x86_64::__cpuid(1);
+  if features.edx & (1 << 9) == 0 {
+    return Err(ApicError::NotSupported);
+  }
+
+  memory::reserve_region(APIC_REGION)?;
+  let mut apic_base_msr = Msr::new(IA32_APIC_BASE_MSR);
+  let apic_base = unsafe { apic_base_msr.read() };
+  unsafe { apic_base_msr.write(apic_base | APIC_BASE_ENABLE) };
+  map_registers(
+    mapper,
+    frame_allocator,
+    Page::containing_address(lapic_base()),
+    apic_base & APIC_BASE_MASK,
+  )?;
+  map_registers(
+    mapper,
+    frame_allocator,
+    Page::containing_address(io_apic_base()),
+    IO_APIC_BASE,
+  )?;
+
+  lapic_write(LAPIC_SPURIOUS, SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+  let counts_per_tick = calibrate_timer();
+
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    // the PICs are remapped already (by `ChainedPics::initialize`), silence them
+    unsafe { PICS.lock().write_masks(0xff, 0xff) };
+    lapic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+
+    let lapic_id = lapic_read(LAPIC_ID) >> 24;
+    for index in [
+      InterruptIndex::Keyboard,
+      InterruptIndex::Serial1,
+      InterruptIndex::Mouse,
+    ] {
+      let vector = index as u8;
+      route_irq(vector - PIC_1_OFFSET, vector, lapic_id);
+    }
+
+    lapic_write(
+      LAPIC_LVT_TIMER,
+      LVT_TIMER_PERIODIC | InterruptIndex::Timer as u32,
+    );
+    lapic_write(LAPIC_TIMER_INITIAL, counts_per_tick);
+    ENABLED.store(true, Ordering::SeqCst);
+  });
+  Ok(())
+}
+
+/// Whether interrupts are delivered by the APIC (instead of the PICs)
+pub fn is_enabled() -> bool {
+  ENABLED.load(Ordering::SeqCst)
+}
+
+/// Signal the end of the current interrupt to the local APIC
+pub fn end_of_interrupt() {
+  lapic_write(LAPIC_EOI, 0);
+}
+
+#[test_case]
+fn test_lapic_timer_ticks() {
+  assert!(is_enabled());
+  let start = time::ticks();
+  for _ in 0..1000 {
+    x86_64::instructions::hlt();
+    if time::ticks() > start {
+      return;
+    }
+  }
+  panic!("no tick from the LAPIC timer\n");
+}
@@ -0,0 +1,172 @@
+//! Cycle-counting micro benchmarks
+
+use crate::{allocator::ALLOCATOR_NAME, println, utils::table::Table};
+use alloc::{
+  alloc::{alloc, dealloc, Layout},
+  format,
+  vec::Vec,
+};
+use core::arch::x86_64::_rdtsc;
+
+/// Current value of the time stamp counter
+#[inline]
+pub fn cycles() -> u64 {
+  unsafe { _rdtsc() }
+}
+
+/// Cycles spent by a workload, and how many operations it performed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurement {
+  pub cycles: u64,
+  pub operations: u64,
+}
+
+impl Measurement {
+  /// Cycles spent per operation (`0` if there was none)
+  pub fn per_operation(&self) -> u64 {
+    self.cycles.checked_div(self.operations).unwrap_or(0)
+  }
+}
+
+/// Run `workload` once, measuring the cycles it spent
+///
+/// `workload` returns how many operations it performed.
+pub fn measure(workload: impl FnOnce() -> u64) -> Measurement {
+  let start = cycles();
+  let operations = workload();
+  Measurement {
+    cycles: cycles().wrapping_sub(start),
+    operations,
+  }
+}
+
+/// A benchmark workload, returning how many operations it performed
+type Workload = fn() -> u64;
+
+/// Number of live allocations per workload
+const SLOTS: usize = 256;
+
+fn allocate(size: usize) -> *mut u8 {
+  let ptr = unsafe { alloc(Layout::from_size_align(size, 8).unwrap()) };
+  assert!(!ptr.is_null(), "allocation of {} bytes failed!\n", size);
+  ptr
+}
+
+fn deallocate(ptr: *mut u8, size: usize) {
+  unsafe { dealloc(ptr, Layout::from_size_align(size, 8).unwrap()) }
+}
+
+/// `SLOTS` allocations of 64 bytes, then freeing them all
+fn sequential() -> u64 {
+  let mut slots = [core::ptr::null_mut(); SLOTS];
+  for slot in slots.iter_mut() {
+    *slot = allocate(64);
+  }
+  for &slot in slots.iter() {
+    deallocate(slot, 64);
+  }
+  2 * SLOTS as u64
+}
+
+/// Freeing each allocation right away
+fn interleaved() -> u64 {
+  for i in 0..SLOTS {
+    let size = 16 << (i % 4);
+    deallocate(allocate(size), size);
+  }
+  2 * SLOTS as u64
+}
+
+/// Mixed sizes, freeing every other one, then allocating bigger blocks into the holes
+fn fragmentation() -> u64 {
+  let size_of = |i: usize| 8 << (i % 5);
+  let mut slots = [core::ptr::null_mut(); SLOTS];
+  for (i, slot) in slots.iter_mut().enumerate() {
+    *slot = allocate(size_of(i));
+  }
+  for i in (0..SLOTS).step_by(2) {
+    deallocate(slots[i], size_of(i));
+    slots[i] = allocate(2 * size_of(i));
+  }
+  for (i, &slot) in slots.iter().enumerate() {
+    let size = if i % 2 == 0 {
+      2 * size_of(i)
+    } else {
+      size_of(i)
+    };
+    deallocate(slot, size);
+  }
+  (2 * SLOTS + SLOTS) as u64
+}
+
+/// One large block, then many small ones
+fn large_then_small() -> u64 {
+  const LARGE: usize = 64 * 1024;
+  let large = allocate(LARGE);
+  let mut slots = [core::ptr::null_mut(); SLOTS];
+  for slot in slots.iter_mut() {
+    *slot = allocate(32);
+  }
+  deallocate(large, LARGE);
+  for &slot in slots.iter() {
+    deallocate(slot, 32);
+  }
+  2 * (SLOTS + 1) as u64
+}
+
+/// Run the standard allocator workloads, returning the measurement of each
+///
+/// Only the compiled-in allocator (`ALLOCATOR_NAME`) is measured, compare
+/// builds with different `use_*Allocator` features to compare allocators.
+pub fn allocator_workloads() -> Vec<(&'static str, Measurement)> {
+  let workloads: [(&str, Workload); 4] = [
+    ("sequential", sequential),
+    ("interleaved", interleaved),
+    ("fragmentation", fragmentation),
+    ("large then small", large_then_small),
+  ];
+  workloads
+    .into_iter()
+    .map(|(name, workload)| (name, measure(workload)))
+    .collect()
+}
+
+/// Print a table of `allocator_workloads` for the compiled-in allocator
+pub fn allocator_comparison() {
+  let mut table = Table::new().with_header(["workload", "cycles/op"]);
+  for (name, measurement) in allocator_workloads() {
+    table.push_row([name.into(), format!("{}", measurement.per_operation())]);
+  }
+  println!("allocator: {}\n{}", ALLOCATOR_NAME, table);
+}
+
+#[test_case]
+fn test_allocator_workloads() {
+  let results = allocator_workloads();
+  let operations = results
+    .iter()
+    .map(|(_, m)| m.operations)
+    .collect::<Vec<_>>();
+  let slots = SLOTS as u64;
+  assert_eq!(
+    operations,
+    [2 * slots, 2 * slots, 3 * slots, 2 * (slots + 1)]
+  );
+  for (name, measurement) in results {
+    // an allocation (or deallocation) takes far more than one cycle
+    assert!(
+      measurement.per_operation() >= 1,
+      "`{}` took {} cycles for {} operations\n",
+      name,
+      measurement.cycles,
+      measurement.operations
+    );
+  }
+}
+
+#[test_case]
+fn test_measure_without_operations() {
+  let measurement = measure(|| 0);
+  assert_eq!(measurement.operations, 0);
+  assert_eq!(measurement.per_operation(), 0);
+}
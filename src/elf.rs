@@ -0,0 +1,198 @@
+//! Parsing of 64-bit little-endian x86-64 ELF executables
+//!
+//! Only the file header and the program headers are read, nothing gets loaded.
+
+use alloc::vec::Vec;
+
+/// Size of the ELF64 file header
+const HEADER_SIZE: usize = 64;
+/// Size of an ELF64 program header
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const MAGIC: [u8; 4] = *b"\x7fELF";
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const MACHINE_X86_64: u16 = 0x3e;
+/// `p_type` of a loadable segment
+const PT_LOAD: u32 = 1;
+
+/// Segment is executable (`p_flags`)
+pub const PF_X: u32 = 1 << 0;
+/// Segment is writable (`p_flags`)
+pub const PF_W: u32 = 1 << 1;
+/// Segment is readable (`p_flags`)
+pub const PF_R: u32 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+  /// The data ends before a header does
+  Truncated,
+  BadMagic,
+  /// Not a 64-bit ELF
+  NotElf64,
+  NotLittleEndian,
+  /// Not built for x86-64
+  WrongMachine,
+  /// A program header has an unexpected size, or a segment lies outside the file
+  BadProgramHeader,
+}
+
+/// A `PT_LOAD` segment: `file_size` bytes at `offset` in the file, mapped at
+/// `vaddr` and zero-filled up to `mem_size`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadSegment {
+  pub vaddr: u64,
+  pub offset: u64,
+  pub file_size: u64,
+  pub mem_size: u64,
+  /// `PF_R` | `PF_W` | `PF_X`
+  pub flags: u32,
+}
+
+/// What is needed to load an executable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfInfo {
+  pub entry: u64,
+  pub segments: Vec<LoadSegment>,
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+  u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+  u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+  u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Parse the file header and the loadable segments of `bytes`
+pub fn parse(bytes: &[u8]) -> Result<ElfInfo, ElfError> {
+  if bytes.len() < HEADER_SIZE {
+    return Err(ElfError::Truncated);
+  }
+  if bytes[..4] != MAGIC {
+    return Err(ElfError::BadMagic);
+  }
+  if bytes[4] != CLASS_64 {
+    return Err(ElfError::NotElf64);
+  }
+  if bytes[5] != DATA_LITTLE_ENDIAN {
+    return Err(ElfError::NotLittleEndian);
+  }
+  if read_u16(bytes, 18) != MACHINE_X86_64 {
+    return Err(ElfError::WrongMachine);
+  }
+
+  let entry = read_u64(bytes, 24);
+  let ph_offset = read_u64(bytes, 32);
+  let ph_entry_size = read_u16(bytes, 54) as usize;
+  let ph_count = read_u16(bytes, 56) as usize;
+  if ph_count > 0 && ph_entry_size != PROGRAM_HEADER_SIZE {
+    return Err(ElfError::BadProgramHeader);
+  }
+  let table_end = usize::try_from(ph_offset)
+    .ok()
+    .and_then(|start| start.checked_add(ph_count * PROGRAM_HEADER_SIZE))
+    .ok_or(ElfError::Truncated)?;
+  if table_end > bytes.len() {
+    return Err(ElfError::Truncated);
+  }
+
+  let mut segments = Vec::new();
+  for i in 0..ph_count {
+    let header = &bytes[ph_offset as usize + i * PROGRAM_HEADER_SIZE..][..PROGRAM_HEADER_SIZE];
+    if read_u32(header, 0) != PT_LOAD {
+      continue;
+    }
+    let segment = LoadSegment {
+      flags: read_u32(header, 4),
+      offset: read_u64(header, 8),
+      vaddr: read_u64(header, 16),
+      file_size: read_u64(header, 32),
+      mem_size: read_u64(header, 40),
+    };
+    let in_file = segment
+      .offset
+      .checked_add(segment.file_size)
+      .is_some_and(|end| end <= bytes.len() as u64);
+    if !in_file || segment.file_size > segment.mem_size {
+      return Err(ElfError::BadProgramHeader);
+    }
+    segments.push(segment);
+  }
+
+  Ok(ElfInfo { entry, segments })
+}
+
+#[cfg(test)]
+mod test_elf {
+  use super::*;
+  use alloc::vec;
+
+  /// A header with a `PT_LOAD` (r-x) and a `PT_NOTE` program header, followed
+  /// by 16 bytes of "code"
+  fn sample() -> Vec<u8> {
+    let mut bytes = vec![0; HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE + 16];
+    bytes[..4].copy_from_slice(&MAGIC);
+    bytes[4] = CLASS_64;
+    bytes[5] = DATA_LITTLE_ENDIAN;
+    bytes[6] = 1;
+    bytes[16..18].copy_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    bytes[18..20].copy_from_slice(&MACHINE_X86_64.to_le_bytes());
+    bytes[24..32].copy_from_slice(&0x40_1000u64.to_le_bytes());
+    bytes[32..40].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    bytes[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    bytes[56..58].copy_from_slice(&2u16.to_le_bytes());
+
+    let load = &mut bytes[HEADER_SIZE..][..PROGRAM_HEADER_SIZE];
+    load[0..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+    load[4..8].copy_from_slice(&(PF_R | PF_X).to_le_bytes());
+    load[8..16].copy_from_slice(&176u64.to_le_bytes());
+    load[16..24].copy_from_slice(&0x40_1000u64.to_le_bytes());
+    load[32..40].copy_from_slice(&16u64.to_le_bytes());
+    load[40..48].copy_from_slice(&0x1000u64.to_le_bytes());
+
+    let note = &mut bytes[HEADER_SIZE + PROGRAM_HEADER_SIZE..][..PROGRAM_HEADER_SIZE];
+    note[0..4].copy_from_slice(&4u32.to_le_bytes()); // PT_NOTE
+    bytes
+  }
+
+  #[test_case]
+  fn parses_header_and_load_segments() {
+    let info = parse(&sample()).expect("parsing failed!\n");
+    assert_eq!(info.entry, 0x40_1000);
+    assert_eq!(
+      info.segments,
+      [LoadSegment {
+        vaddr: 0x40_1000,
+        offset: 176,
+        file_size: 16,
+        mem_size: 0x1000,
+        flags: PF_R | PF_X,
+      }]
+    );
+  }
+
+  #[test_case]
+  fn rejects_malformed_headers() {
+    let bytes = sample();
+    assert_eq!(parse(&bytes[..40]), Err(ElfError::Truncated));
+    // program headers cut off
+    assert_eq!(parse(&bytes[..100]), Err(ElfError::Truncated));
+
+    let mut wrong_magic = bytes.clone();
+    wrong_magic[1] = b'X';
+    assert_eq!(parse(&wrong_magic), Err(ElfError::BadMagic));
+
+    let mut elf32 = bytes.clone();
+    elf32[4] = 1;
+    assert_eq!(parse(&elf32), Err(ElfError::NotElf64));
+
+    let mut arm = bytes;
+    arm[18] = 0xb7;
+    assert_eq!(parse(&arm), Err(ElfError::WrongMachine));
+  }
+}
@@ -1,3 +1,6 @@
+use crate::{hlt_loop, io, serial_println};
+use spin::Mutex;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -5,10 +8,92 @@ pub enum QemuExitCode {
   Failed = 0x11,
 }
 
+/// Maximum number of `atexit` hooks
+const MAX_ATEXIT_HOOKS: usize = 8;
+
+type AtexitHooks = [Option<fn()>; MAX_ATEXIT_HOOKS];
+
+/// Registered `atexit` hooks, in registration order
+static ATEXIT_HOOKS: Mutex<AtexitHooks> = Mutex::new([None; MAX_ATEXIT_HOOKS]);
+
+/// Register `hook` to run before `exit_qemu`, `reboot` or `shutdown`
+///
+/// Hooks run once, in reverse registration order. Once `MAX_ATEXIT_HOOKS`
+/// hooks are registered, further ones are ignored (with a warning).
+pub fn register_atexit(hook: fn()) {
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    let mut hooks = ATEXIT_HOOKS.lock();
+    match hooks.iter_mut().find(|slot| slot.is_none()) {
+      Some(slot) => *slot = Some(hook),
+      None => {
+        serial_println!("WARNING: too many atexit hooks, ignoring one");
+      }
+    }
+  });
+}
+
+/// Run (and unregister) the `atexit` hooks
+///
+/// Skipped if the hook table is locked, e.g. when panicking inside
+/// `register_atexit`.
+pub(crate) fn run_atexit_hooks() {
+  let hooks = x86_64::instructions::interrupts::without_interrupts(|| {
+    ATEXIT_HOOKS
+      .try_lock()
+      .map(|mut hooks| core::mem::replace(&mut *hooks, [None; MAX_ATEXIT_HOOKS]))
+  });
+  if let Some(hooks) = hooks {
+    for hook in hooks.iter().rev().flatten() {
+      hook();
+    }
+  }
+}
+
 pub fn exit_qemu(exit_code: QemuExitCode) {
-  use x86_64::instructions::port::Port;
-  unsafe {
-    let mut port = Port::new(0xf4);
-    port.write(exit_code as u32);
+  run_atexit_hooks();
+  // SAFETY: the `isa-debug-exit` device, which ends the (QEMU) machine
+  unsafe { io::outl(0xf4, exit_code as u32) };
+}
+
+/// Reset the cpu through the keyboard controller
+pub fn reboot() -> ! {
+  run_atexit_hooks();
+  // pulse the reset line
+  unsafe { io::outb(0x64, 0xFE) };
+  hlt_loop()
+}
+
+/// Power off the (QEMU) machine through its ACPI PM1a control register
+pub fn shutdown() -> ! {
+  run_atexit_hooks();
+  unsafe { io::outw(0x604, 0x2000) };
+  hlt_loop()
+}
+
+#[cfg(test)]
+mod test_atexit {
+  use super::*;
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  static ORDER: AtomicUsize = AtomicUsize::new(0);
+
+  #[test_case]
+  fn hooks_run_in_reverse_order() {
+    fn first() {
+      // `second` must have run already
+      assert_eq!(ORDER.fetch_add(1, Ordering::SeqCst), 1);
+    }
+    fn second() {
+      assert_eq!(ORDER.fetch_add(1, Ordering::SeqCst), 0);
+    }
+
+    register_atexit(first);
+    register_atexit(second);
+    run_atexit_hooks();
+    assert_eq!(ORDER.load(Ordering::SeqCst), 2);
+
+    // hooks only run once
+    run_atexit_hooks();
+    assert_eq!(ORDER.load(Ordering::SeqCst), 2);
   }
 }
@@ -1,18 +1,42 @@
-use core::ptr::addr_of;
+use core::{ops::Range, ptr::addr_of};
 
 use lazy_static::lazy_static;
 use x86_64::{structures::tss::TaskStateSegment, VirtAddr};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+pub const NMI_IST_INDEX: u16 = 2;
+/// Size of the stack the double fault handler runs on
+pub const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+/// Size of the stack the page fault handler runs on
+pub const PAGE_FAULT_STACK_SIZE: usize = 4096 * 5;
+/// Size of the stack the NMI handler runs on
+pub const NMI_STACK_SIZE: usize = 4096 * 2;
+/// Size of the stack the cpu switches to on an interrupt from ring 3
+pub const PRIVILEGE_STACK_SIZE: usize = 4096 * 5;
 
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+            static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
             let stack_start = VirtAddr::from_ptr(addr_of!(STACK));
-            stack_start + STACK_SIZE as u64 // stack_end
+            stack_start + DOUBLE_FAULT_STACK_SIZE as u64 // stack_end
+        };
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            static mut STACK: [u8; PAGE_FAULT_STACK_SIZE] = [0; PAGE_FAULT_STACK_SIZE];
+            let stack_start = VirtAddr::from_ptr(addr_of!(STACK));
+            stack_start + PAGE_FAULT_STACK_SIZE as u64 // stack_end
+        };
+        tss.interrupt_stack_table[NMI_IST_INDEX as usize] = {
+            static mut STACK: [u8; NMI_STACK_SIZE] = [0; NMI_STACK_SIZE];
+            let stack_start = VirtAddr::from_ptr(addr_of!(STACK));
+            stack_start + NMI_STACK_SIZE as u64 // stack_end
+        };
+        tss.privilege_stack_table[0] = {
+            static mut STACK: [u8; PRIVILEGE_STACK_SIZE] = [0; PRIVILEGE_STACK_SIZE];
+            let stack_start = VirtAddr::from_ptr(addr_of!(STACK));
+            stack_start + PRIVILEGE_STACK_SIZE as u64 // stack_end
         };
         tss
     };
@@ -22,6 +46,10 @@ use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector
 
 struct Selectors {
   code_selector: SegmentSelector,
+  /// RPL 3
+  user_data_selector: SegmentSelector,
+  /// RPL 3
+  user_code_selector: SegmentSelector,
   tss_selector: SegmentSelector,
 }
 
@@ -29,17 +57,101 @@ lazy_static! {
   static ref GDT: (GlobalDescriptorTable, Selectors) = {
     let mut gdt = GlobalDescriptorTable::new();
     let code_selector = gdt.append(Descriptor::kernel_code_segment());
+    let user_data_selector = gdt.append(Descriptor::user_data_segment());
+    let user_code_selector = gdt.append(Descriptor::user_code_segment());
     let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
     (
       gdt,
       Selectors {
         code_selector,
+        user_data_selector,
+        user_code_selector,
         tss_selector,
       },
     )
   };
 }
 
+/// Address range of the double fault stack
+pub fn double_fault_stack() -> Range<VirtAddr> {
+  let end = TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize];
+  end - DOUBLE_FAULT_STACK_SIZE as u64..end
+}
+
+/// Address range of the stack used on interrupts from ring 3 (`rsp0` of the TSS)
+pub fn privilege_stack() -> Range<VirtAddr> {
+  let end = TSS.privilege_stack_table[0];
+  end - PRIVILEGE_STACK_SIZE as u64..end
+}
+
+/// Number of stacks in `kernel_stacks`
+pub const KERNEL_STACKS: usize = 4;
+
+/// Names and address ranges of the stacks set up in the TSS
+pub fn kernel_stacks() -> [(&'static str, Range<VirtAddr>); KERNEL_STACKS] {
+  let ist = |index: u16, size: usize| {
+    let end = TSS.interrupt_stack_table[index as usize];
+    end - size as u64..end
+  };
+  [
+    ("double fault stack", double_fault_stack()),
+    (
+      "page fault stack",
+      ist(PAGE_FAULT_IST_INDEX, PAGE_FAULT_STACK_SIZE),
+    ),
+    ("nmi stack", ist(NMI_IST_INDEX, NMI_STACK_SIZE)),
+    ("privilege stack", privilege_stack()),
+  ]
+}
+
+/// Selector of the ring 3 code segment
+pub fn user_code_selector() -> SegmentSelector {
+  GDT.1.user_code_selector
+}
+
+/// Selector of the ring 3 data (and stack) segment
+pub fn user_data_selector() -> SegmentSelector {
+  GDT.1.user_data_selector
+}
+
+/// Drop to ring 3, continuing at `entry` with the stack pointer at `stack`
+///
+/// Builds the interrupt return frame by hand (`SS`, `RSP`, `RFLAGS` with
+/// interrupts enabled, `CS`, `RIP`) and executes `iretq`.
+///
+/// # Safety
+///
+/// - `init` must have run, so that the user segments and the TSS (whose
+///   `rsp0` is the stack used by interrupts raised in ring 3) are loaded
+/// - `entry` and the pages below `stack` must be mapped with
+///   `USER_ACCESSIBLE` (in every level of the page tables)
+/// - `stack` must be 16-byte aligned
+/// - the user code must only leave ring 3 through interrupts or exceptions,
+///   as this never returns and nothing of the current stack is kept alive
+pub unsafe fn jump_to_usermode(entry: VirtAddr, stack: VirtAddr) -> ! {
+  /// `RFLAGS` with only `IF` (and the always-one bit 1) set
+  const USER_RFLAGS: u64 = 0x202;
+
+  let data = u64::from(user_data_selector().0);
+  let code = u64::from(user_code_selector().0);
+  core::arch::asm!(
+    "mov ds, {data:x}",
+    "mov es, {data:x}",
+    "push {data}",
+    "push {stack}",
+    "push {rflags}",
+    "push {code}",
+    "push {entry}",
+    "iretq",
+    data = in(reg) data,
+    stack = in(reg) stack.as_u64(),
+    rflags = in(reg) USER_RFLAGS,
+    code = in(reg) code,
+    entry = in(reg) entry.as_u64(),
+    options(noreturn)
+  )
+}
+
 pub fn init() {
   use x86_64::instructions::{
     segmentation::{Segment, CS},
@@ -51,3 +163,32 @@ pub fn init() {
     load_tss(GDT.1.tss_selector);
   }
 }
+
+#[test_case]
+fn test_user_segments() {
+  use x86_64::PrivilegeLevel;
+
+  assert_eq!(user_code_selector().rpl(), PrivilegeLevel::Ring3);
+  assert_eq!(user_data_selector().rpl(), PrivilegeLevel::Ring3);
+  let stack = privilege_stack();
+  assert_eq!(
+    stack.end.as_u64() - stack.start.as_u64(),
+    PRIVILEGE_STACK_SIZE as u64
+  );
+  assert!(stack.end != double_fault_stack().end);
+}
+
+#[test_case]
+fn test_kernel_stacks_are_distinct() {
+  let stacks = kernel_stacks();
+  for (i, (name, stack)) in stacks.iter().enumerate() {
+    for (other_name, other) in &stacks[i + 1..] {
+      assert!(
+        stack.end <= other.start || other.end <= stack.start,
+        "{} overlaps {}\n",
+        name,
+        other_name
+      );
+    }
+  }
+}
@@ -1,20 +1,185 @@
-use crate::{gdt, hlt_loop, print, println, vga_buffer::WRITER};
+use crate::{gdt, hlt_loop, print, println, time, vga_buffer::WRITER};
+use core::{
+  fmt,
+  sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+};
 use lazy_static::lazy_static;
 use pc_keyboard::KeyCode;
 use pic8259::ChainedPics;
 use spin::Mutex;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::{
+  instructions::interrupts::without_interrupts,
+  registers::rflags::RFlags,
+  structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue, PageFaultErrorCode,
+  },
+  VirtAddr,
+};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+/// Data port of the PS/2 keyboard controller (the mouse sends its bytes there too)
+const KEYBOARD_DATA_PORT: u16 = 0x60;
+
 /// Intel 8259 Compatible PIC
 pub static PICS: Mutex<ChainedPics> =
   Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+/// Whether `describe_frame` appends the raw `{:#?}` dump of the frame
+static VERBOSE_FRAMES: AtomicBool = AtomicBool::new(false);
+
+/// Also print the raw stack frames in exception reports
+pub fn set_verbose_frames(verbose: bool) {
+  VERBOSE_FRAMES.store(verbose, Ordering::Relaxed);
+}
+
+/// Short names of the decoded `RFLAGS` bits, lowest bit first
+const RFLAGS_NAMES: [(RFlags, &str); 10] = [
+  (RFlags::CARRY_FLAG, "CF"),
+  (RFlags::PARITY_FLAG, "PF"),
+  (RFlags::AUXILIARY_CARRY_FLAG, "AF"),
+  (RFlags::ZERO_FLAG, "ZF"),
+  (RFlags::SIGN_FLAG, "SF"),
+  (RFlags::TRAP_FLAG, "TF"),
+  (RFlags::INTERRUPT_FLAG, "IF"),
+  (RFlags::DIRECTION_FLAG, "DF"),
+  (RFlags::OVERFLOW_FLAG, "OF"),
+  (RFlags::ALIGNMENT_CHECK, "AC"),
+];
+
+/// Compact, labeled rendering of an exception stack frame, see `describe_frame`
+pub struct FrameDescription<'a>(&'a InterruptStackFrameValue);
+
+/// Describe `frame` as
+///
+/// ```txt
+/// RIP: 0x20a3c5  CS: 0x8  RSP: 0x10000201a40  SS: 0x0
+/// RFLAGS: 0x246 [PF ZF IF]
+/// ```
+pub fn describe_frame(frame: &InterruptStackFrameValue) -> FrameDescription {
+  FrameDescription(frame)
+}
+
+impl fmt::Display for FrameDescription<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let frame = self.0;
+    writeln!(
+      f,
+      "RIP: {:#x}  CS: {:#x}  RSP: {:#x}  SS: {:#x}",
+      frame.instruction_pointer.as_u64(),
+      frame.code_segment.0,
+      frame.stack_pointer.as_u64(),
+      frame.stack_segment.0
+    )?;
+    write!(f, "RFLAGS: {:#x} [", frame.cpu_flags.bits())?;
+    let set = RFLAGS_NAMES
+      .iter()
+      .filter(|(flag, _)| frame.cpu_flags.contains(*flag));
+    for (i, (_, name)) in set.enumerate() {
+      if i > 0 {
+        f.write_str(" ")?;
+      }
+      f.write_str(name)?;
+    }
+    f.write_str("]")?;
+    if VERBOSE_FRAMES.load(Ordering::Relaxed) {
+      write!(f, "\n{:#?}", frame)?;
+    }
+    Ok(())
+  }
+}
+
+/// General-purpose registers, see `capture_registers`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+  /// `rax`, `rbx`, `rcx`, `rdx`, `rsi`, `rdi`, `rbp`, `rsp`, then `r8` to `r15`
+  pub gprs: [u64; 16],
+  pub rflags: u64,
+}
+
+const GPR_NAMES: [&str; 16] = [
+  "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+  "r14", "r15",
+];
+
+/// Snapshot the general-purpose registers of the caller
+///
+/// Best effort: the compiler may already have reused some registers before
+/// this runs (and one of them holds the snapshot's address).
+#[inline(always)]
+pub fn capture_registers() -> Registers {
+  let mut gprs = [0u64; 16];
+  unsafe {
+    core::arch::asm!(
+      "mov [{0}], rax",
+      "mov [{0} + 8], rbx",
+      "mov [{0} + 16], rcx",
+      "mov [{0} + 24], rdx",
+      "mov [{0} + 32], rsi",
+      "mov [{0} + 40], rdi",
+      "mov [{0} + 48], rbp",
+      "mov [{0} + 56], rsp",
+      "mov [{0} + 64], r8",
+      "mov [{0} + 72], r9",
+      "mov [{0} + 80], r10",
+      "mov [{0} + 88], r11",
+      "mov [{0} + 96], r12",
+      "mov [{0} + 104], r13",
+      "mov [{0} + 112], r14",
+      "mov [{0} + 120], r15",
+      in(reg) gprs.as_mut_ptr(),
+      options(nostack, preserves_flags)
+    );
+  }
+  Registers {
+    gprs,
+    rflags: x86_64::registers::rflags::read_raw(),
+  }
+}
+
+/// Four registers per line, e.g. `rax=0x0000000000000001`
+impl fmt::Display for Registers {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (i, (name, value)) in GPR_NAMES.iter().zip(self.gprs).enumerate() {
+      write!(f, "{:>3}={:#018x}", name, value)?;
+      f.write_str(if i % 4 == 3 { "\n" } else { "  " })?;
+    }
+    write!(f, "rflags={:#x}", self.rflags)
+  }
+}
+
+/// Decoded selector error code (of `#GP`, `#TS`, `#NP`, `#SS`), see `describe_selector_error`
+pub struct SelectorErrorDescription(u64);
+
+/// Describe the selector an exception error code refers to, e.g. `GDT[10]`
+/// or `IDT[3] (external)`
+pub fn describe_selector_error(error_code: u64) -> SelectorErrorDescription {
+  SelectorErrorDescription(error_code)
+}
+
+impl fmt::Display for SelectorErrorDescription {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let error_code = self.0;
+    if error_code == 0 {
+      return f.write_str("no selector");
+    }
+    let table = match (error_code & 0b010 != 0, error_code & 0b100 != 0) {
+      (true, _) => "IDT",
+      (false, true) => "LDT",
+      (false, false) => "GDT",
+    };
+    write!(f, "{}[{}]", table, (error_code >> 3) & 0x1FFF)?;
+    if error_code & 0b001 != 0 {
+      f.write_str(" (external)")?;
+    }
+    Ok(())
+  }
+}
+
 /// hook of `breakpoint`
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-  println!("EXCEPTION: BREAKPOINT\n{:#?}\n", stack_frame);
+  println!("EXCEPTION: BREAKPOINT\n{}\n", describe_frame(&stack_frame));
 }
 
 /// hook of `double_fault`
@@ -22,18 +187,118 @@ extern "x86-interrupt" fn double_fault_handler(
   stack_frame: InterruptStackFrame,
   _error_code: u64,
 ) -> ! {
-  panic!("EXCEPTION: DOUBLE FAULT\n{:#?}\n", stack_frame);
+  // the report below needs locks and formatting, which may fail this deep
+  ist_safe_print("EXCEPTION: DOUBLE FAULT\n");
+  panic!(
+    "EXCEPTION: DOUBLE FAULT\n{}\n",
+    describe_frame(&stack_frame)
+  );
+}
+
+/// Emergency output for exception handlers (e.g. running on an IST stack)
+///
+/// Writes `s` straight to the COM1 UART, polling its transmit register: no
+/// lock, no formatting, no allocation. Output may interleave with concurrent
+/// `serial_print!`s, so use it only when the normal path can't be trusted.
+/// Assumes COM1 has been initialized (i.e. something was printed before).
+pub fn ist_safe_print(s: &str) {
+  crate::serial::write_raw(s.as_bytes());
+}
+
+/// Whether maskable interrupts are enabled (`IF` bit of `RFLAGS`)
+pub fn are_enabled() -> bool {
+  use x86_64::registers::rflags::{self, RFlags};
+
+  rflags::read().contains(RFlags::INTERRUPT_FLAG)
+}
+
+/// Disables interrupts until dropped, then restores their previous state
+///
+/// Unlike a bare `cli`/`sti` pair, nested guards compose: an inner guard
+/// never re-enables interrupts inside an outer critical section.
+#[must_use]
+pub struct InterruptGuard {
+  were_enabled: bool,
+}
+
+impl InterruptGuard {
+  pub fn new() -> Self {
+    let were_enabled = are_enabled();
+    if were_enabled {
+      x86_64::instructions::interrupts::disable();
+    }
+    Self { were_enabled }
+  }
+}
+
+impl Default for InterruptGuard {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Drop for InterruptGuard {
+  fn drop(&mut self) {
+    if self.were_enabled {
+      x86_64::instructions::interrupts::enable();
+    }
+  }
+}
+
+/// Sanity check for critical sections which must run with interrupts disabled
+///
+/// Only checked in debug builds.
+#[track_caller]
+pub fn assert_disabled() {
+  debug_assert!(!are_enabled(), "interrupts unexpectedly enabled!\n");
+}
+
+/// Sanity check for code which must run with interrupts enabled
+///
+/// Only checked in debug builds.
+#[track_caller]
+pub fn assert_enabled() {
+  debug_assert!(are_enabled(), "interrupts unexpectedly disabled!\n");
+}
+
+/// The callback invoked on each timer tick, as a pointer (`null` => no callback)
+static TIMER_CALLBACK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Install (`Some`) or uninstall (`None`) the callback invoked on each timer tick
+///
+/// The callback runs inside the timer interrupt handler, after the tick counter
+/// has been incremented and before `EOI`, so it must not block or allocate.
+pub fn set_timer_callback(callback: Option<fn()>) {
+  let ptr = callback.map_or(core::ptr::null_mut(), |f| f as *mut ());
+  TIMER_CALLBACK.store(ptr, Ordering::SeqCst);
+}
+
+/// Signal the end of interrupt `index` to the interrupt controller in charge
+fn end_of_interrupt(index: InterruptIndex) {
+  #[cfg(feature = "use_apic")]
+  if crate::apic::is_enabled() {
+    crate::apic::end_of_interrupt();
+    return;
+  }
+  unsafe { PICS.lock().notify_end_of_interrupt(index.as_u8()) }
 }
 
 /// hook of `timer_interrupt`
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-  // print!(".");
-  // handle `EOI`
-  unsafe {
-    PICS
-      .lock()
-      .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+  #[cfg(feature = "timer_trace")]
+  crate::serial_putc!(b'.');
+  time::tick();
+  crate::task::timer::wake_expired(time::ticks());
+  crate::sound::on_tick(time::ticks());
+  let callback = TIMER_CALLBACK.load(Ordering::SeqCst);
+  if !callback.is_null() {
+    // SAFETY: a non-null value is only ever stored from a `fn()` in
+    // `set_timer_callback`, and function pointers round trip through `*mut ()`
+    let callback = unsafe { core::mem::transmute::<*mut (), fn()>(callback) };
+    callback();
   }
+  // handle `EOI`
+  end_of_interrupt(InterruptIndex::Timer);
 }
 
 /// hook of `keyboard_interrupt`
@@ -41,7 +306,6 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
 #[allow(dead_code)]
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
   use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-  use x86_64::instructions::port::Port;
 
   // KEYBOARD Pool
   lazy_static! {
@@ -56,11 +320,9 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
   // keyboard singleton
   let mut keyboard = KEYBOARD.lock();
 
-  // port <~ 0x60 (IO)
-  let mut port = Port::new(0x60);
-
-  // scancode
-  let scancode: u8 = unsafe { port.read() };
+  // scancode <~ port 0x60 (IO)
+  // SAFETY: the keyboard raised the interrupt, so it has a scancode waiting
+  let scancode = unsafe { crate::io::inb(KEYBOARD_DATA_PORT) };
 
   if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
     if let Some(key) = keyboard.process_keyevent(key_event) {
@@ -86,49 +348,447 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
   }
 
   // handle `EOI`
-  unsafe {
-    PICS
-      .lock()
-      .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-  }
+  end_of_interrupt(InterruptIndex::Keyboard);
 }
 
 /// hook of `keyboard_interrupt`, with support of concurrency
 extern "x86-interrupt" fn async_keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-  use x86_64::instructions::port::Port;
-
-  let mut port = Port::new(0x60);
-  let scancode: u8 = unsafe { port.read() };
+  // SAFETY: the keyboard raised the interrupt, so it has a scancode waiting
+  let scancode = unsafe { crate::io::inb(KEYBOARD_DATA_PORT) };
 
   crate::task::keyboard::add_scancode(scancode);
 
   // handle `EOI`
-  unsafe {
-    PICS
-      .lock()
-      .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+  end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+/// hook of `mouse_interrupt` (IRQ12), with support of concurrency
+extern "x86-interrupt" fn async_mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+  // SAFETY: the mouse raised the interrupt, so it has a byte waiting
+  let byte = unsafe { crate::io::inb(KEYBOARD_DATA_PORT) };
+
+  crate::task::mouse::add_mouse_byte(byte);
+
+  // handle `EOI` (of both PICs)
+  end_of_interrupt(InterruptIndex::Mouse);
+}
+
+/// hook of `serial_interrupt` (IRQ4, COM1), with support of concurrency
+extern "x86-interrupt" fn async_serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+  // drain the receive FIFO
+  while let Some(byte) = crate::serial::read_byte() {
+    crate::task::serial::add_serial_byte(byte);
+  }
+
+  // handle `EOI`
+  end_of_interrupt(InterruptIndex::Serial1);
+}
+
+/// Command ports of the primary and the secondary PIC
+const PIC_1_COMMAND_PORT: u16 = 0x20;
+const PIC_2_COMMAND_PORT: u16 = 0xA0;
+/// OCW3: the next read of the command port returns the in-service register
+const PIC_READ_ISR: u8 = 0x0B;
+/// Non-specific `EOI`
+const PIC_EOI: u8 = 0x20;
+
+/// Number of spurious IRQ7/IRQ15 left unacknowledged
+static SPURIOUS_IRQS: AtomicUsize = AtomicUsize::new(0);
+
+/// Get how many spurious PIC interrupts have been suppressed
+pub fn spurious_irqs() -> usize {
+  SPURIOUS_IRQS.load(Ordering::Relaxed)
+}
+
+/// Whether IRQ7 of the PIC at `command_port` is really in service
+///
+/// # Safety
+///
+/// `command_port` must be `PIC_1_COMMAND_PORT` or `PIC_2_COMMAND_PORT`.
+unsafe fn irq7_in_service(command_port: u16) -> bool {
+  crate::io::outb(command_port, PIC_READ_ISR);
+  crate::io::inb(command_port) & (1 << 7) != 0
+}
+
+/// hook of IRQ7, raised spuriously by the primary PIC
+///
+/// A spurious one has no in-service bit and must not be acknowledged.
+extern "x86-interrupt" fn irq7_handler(_stack_frame: InterruptStackFrame) {
+  // SAFETY: the command port of the primary PIC
+  match unsafe { irq7_in_service(PIC_1_COMMAND_PORT) } {
+    true => end_of_interrupt(InterruptIndex::Irq7),
+    false => {
+      SPURIOUS_IRQS.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+}
+
+/// hook of IRQ15, raised spuriously by the secondary PIC
+///
+/// A spurious one is only acknowledged to the primary PIC (which did see the
+/// cascade interrupt).
+extern "x86-interrupt" fn irq15_handler(_stack_frame: InterruptStackFrame) {
+  // SAFETY: the command port of the secondary PIC
+  match unsafe { irq7_in_service(PIC_2_COMMAND_PORT) } {
+    true => end_of_interrupt(InterruptIndex::Irq15),
+    false => {
+      SPURIOUS_IRQS.fetch_add(1, Ordering::Relaxed);
+      // SAFETY: the primary PIC has the cascade interrupt in service
+      unsafe { crate::io::outb(PIC_1_COMMAND_PORT, PIC_EOI) };
+    }
   }
 }
 
+/// hook of the APIC spurious interrupt (which must not be acknowledged)
+#[cfg(feature = "use_apic")]
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+/// hook of `non_maskable_interrupt`
+///
+/// Reported and resumed, it usually signals a hardware error (or a watchdog).
+extern "x86-interrupt" fn nmi_handler(stack_frame: InterruptStackFrame) {
+  use core::fmt::Write;
+
+  // an NMI ignores `IF`, so it may arrive while `WRITER` is held: take no lock
+  ist_safe_print("\nEXCEPTION: NON-MASKABLE INTERRUPT\n");
+  let _ = writeln!(crate::serial::RawWriter, "{}", describe_frame(&stack_frame));
+}
+
+/// Address of the read in `probe_read` (`0` => no probe running)
+static PROBE_RIP: AtomicU64 = AtomicU64::new(0);
+/// Where `probe_read` continues if the read faults
+static PROBE_FIXUP: AtomicU64 = AtomicU64::new(0);
+/// Whether the read of the running `probe_read` faulted
+static PROBE_FAULTED: AtomicBool = AtomicBool::new(false);
+
+/// Read the byte at `addr`, `None` if that page faults
+///
+/// The page fault handler recognizes the probing instruction and resumes
+/// behind it instead of reporting the fault.
+pub fn probe_read(addr: VirtAddr) -> Option<u8> {
+  without_interrupts(|| {
+    PROBE_FAULTED.store(false, Ordering::SeqCst);
+    let value: u32;
+    unsafe {
+      core::arch::asm!(
+        "lea {tmp}, [rip + 2f]",
+        "mov [{rip_slot}], {tmp}",
+        "lea {tmp}, [rip + 3f]",
+        "mov [{fixup_slot}], {tmp}",
+        "xor {value:e}, {value:e}",
+        "2:",
+        "movzx {value:e}, byte ptr [{addr}]",
+        "3:",
+        addr = in(reg) addr.as_u64(),
+        rip_slot = in(reg) PROBE_RIP.as_ptr(),
+        fixup_slot = in(reg) PROBE_FIXUP.as_ptr(),
+        tmp = out(reg) _,
+        value = out(reg) value,
+        options(nostack)
+      );
+    }
+    PROBE_RIP.store(0, Ordering::SeqCst);
+    match PROBE_FAULTED.load(Ordering::SeqCst) {
+      true => None,
+      false => Some(value as u8),
+    }
+  })
+}
+
 /// hook of `page_fault`
 extern "x86-interrupt" fn page_fault_handler(
-  stack_frame: InterruptStackFrame,
+  mut stack_frame: InterruptStackFrame,
   error_code: PageFaultErrorCode,
 ) {
   use x86_64::registers::control::Cr2;
 
+  if stack_frame.instruction_pointer.as_u64() == PROBE_RIP.load(Ordering::SeqCst) {
+    PROBE_FAULTED.store(true, Ordering::SeqCst);
+    let fixup = PROBE_FIXUP.load(Ordering::SeqCst);
+    // SAFETY: continues right behind the probing read
+    unsafe {
+      stack_frame
+        .as_mut()
+        .update(|frame| frame.instruction_pointer = VirtAddr::new(fixup));
+    }
+    return;
+  }
+
   println!("\nEXCEPTION: PAGE FAULT");
   println!("Accessed Address: {:?}", Cr2::read());
   println!("Error Code: {:?}", error_code);
-  println!("{:#?}\n", stack_frame);
+  println!("{}\n", describe_frame(&stack_frame));
+  hlt_loop();
+}
+
+/// Length of the `div`/`idiv` instruction starting with `code`, `None` if it
+/// is something else
+fn div_instruction_len(code: &[u8; 15]) -> Option<usize> {
+  let mut len = 0;
+  // legacy prefixes
+  while len < 4
+    && matches!(
+      code[len],
+      0x26 | 0x2E | 0x36 | 0x3E | 0x64..=0x67 | 0xF0 | 0xF2 | 0xF3
+    )
+  {
+    len += 1;
+  }
+  // REX prefix
+  if (0x40..=0x4F).contains(&code[len]) {
+    len += 1;
+  }
+  if !matches!(code[len], 0xF6 | 0xF7) {
+    return None;
+  }
+  let modrm = code[len + 1];
+  len += 2;
+  // `/6` => div, `/7` => idiv
+  if !matches!((modrm >> 3) & 0b111, 6 | 7) {
+    return None;
+  }
+  let (mode, rm) = (modrm >> 6, modrm & 0b111);
+  if mode != 0b11 && rm == 0b100 {
+    let sib = code[len];
+    len += 1;
+    if mode == 0b00 && sib & 0b111 == 0b101 {
+      len += 4;
+    }
+  }
+  len += match (mode, rm) {
+    (0b00, 0b101) | (0b10, _) => 4,
+    (0b01, _) => 1,
+    _ => 0,
+  };
+  Some(len)
+}
+
+/// Number of divide errors skipped by `divide_error_handler`
+static DIVIDE_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Get how many divide errors have been recovered from
+pub fn divide_errors() -> usize {
+  DIVIDE_ERRORS.load(Ordering::Relaxed)
+}
+
+/// hook of `divide_error`
+///
+/// Recoverable: execution resumes after the faulting `div`/`idiv`, leaving
+/// its destination registers unchanged. Halts if the instruction can't be
+/// decoded (returning would just fault again).
+extern "x86-interrupt" fn divide_error_handler(mut stack_frame: InterruptStackFrame) {
+  println!("\nEXCEPTION: DIVIDE ERROR");
+  println!("{}\n", describe_frame(&stack_frame));
+  let rip = stack_frame.instruction_pointer;
+  // SAFETY: `rip` points to the (mapped) faulting instruction
+  let code = unsafe { core::ptr::read_unaligned(rip.as_ptr::<[u8; 15]>()) };
+  let Some(len) = div_instruction_len(&code) else {
+    hlt_loop();
+  };
+  DIVIDE_ERRORS.fetch_add(1, Ordering::Relaxed);
+  // SAFETY: skips exactly the faulting instruction
+  unsafe {
+    stack_frame
+      .as_mut()
+      .update(|frame| frame.instruction_pointer += len as u64);
+  }
+}
+
+/// hook of `invalid_opcode`
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+  println!("\nEXCEPTION: INVALID OPCODE");
+  println!("{}\n", describe_frame(&stack_frame));
   hlt_loop();
 }
 
+/// hook of `general_protection_fault`
+///
+/// The error code is the offending selector, if a segment load caused it.
+extern "x86-interrupt" fn general_protection_fault_handler(
+  stack_frame: InterruptStackFrame,
+  error_code: u64,
+) {
+  println!("\nEXCEPTION: GENERAL PROTECTION FAULT");
+  println!(
+    "Error Code: {:#x} ({})",
+    error_code,
+    describe_selector_error(error_code)
+  );
+  println!("{}\n", describe_frame(&stack_frame));
+  hlt_loop();
+}
+
+/// hook of `alignment_check`
+///
+/// Only raised at `CPL = 3` with both `CR0.AM` and `RFLAGS.AC` set,
+/// so it can't be provoked from the kernel itself.
+extern "x86-interrupt" fn alignment_check_handler(
+  stack_frame: InterruptStackFrame,
+  error_code: u64,
+) {
+  println!("\nEXCEPTION: ALIGNMENT CHECK");
+  println!("Error Code: {:#x}", error_code);
+  println!("{}\n", describe_frame(&stack_frame));
+  hlt_loop();
+}
+
+/// hook of `machine_check`
+///
+/// Best-effort only: `#MC` is abort-class, the machine state may be
+/// unreliable, so just print what we can and halt immediately.
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+  println!("\nEXCEPTION: MACHINE CHECK");
+  println!("{}\n", describe_frame(&stack_frame));
+  hlt_loop();
+}
+
+/// Names of the cpu exception vectors (`None` => reserved)
+const EXCEPTION_NAMES: [Option<&str>; 32] = [
+  Some("DIVIDE ERROR"),
+  Some("DEBUG"),
+  Some("NON-MASKABLE INTERRUPT"),
+  Some("BREAKPOINT"),
+  Some("OVERFLOW"),
+  Some("BOUND RANGE EXCEEDED"),
+  Some("INVALID OPCODE"),
+  Some("DEVICE NOT AVAILABLE"),
+  Some("DOUBLE FAULT"),
+  None, // coprocessor segment overrun
+  Some("INVALID TSS"),
+  Some("SEGMENT NOT PRESENT"),
+  Some("STACK SEGMENT FAULT"),
+  Some("GENERAL PROTECTION FAULT"),
+  Some("PAGE FAULT"),
+  None,
+  Some("X87 FLOATING POINT"),
+  Some("ALIGNMENT CHECK"),
+  Some("MACHINE CHECK"),
+  Some("SIMD FLOATING POINT"),
+  Some("VIRTUALIZATION"),
+  Some("CONTROL PROTECTION"),
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  Some("HYPERVISOR INJECTION"),
+  Some("VMM COMMUNICATION"),
+  Some("SECURITY"),
+  None,
+];
+
+/// Report an exception vector without a dedicated handler, then panic (and halt)
+///
+/// Better than the triple fault (i.e. a silent reboot) a missing handler causes.
+fn unhandled_exception(
+  vector: u8,
+  stack_frame: &InterruptStackFrame,
+  error_code: Option<u64>,
+) -> ! {
+  let name = EXCEPTION_NAMES[vector as usize].unwrap_or("RESERVED");
+  match error_code {
+    Some(error_code) => panic!(
+      "EXCEPTION: UNHANDLED VECTOR {} ({})\nError Code: {:#x}\n{}\n",
+      vector,
+      name,
+      error_code,
+      describe_frame(stack_frame)
+    ),
+    None => panic!(
+      "EXCEPTION: UNHANDLED VECTOR {} ({})\n{}\n",
+      vector,
+      name,
+      describe_frame(stack_frame)
+    ),
+  }
+}
+
+/// Define catch-all handlers forwarding to `unhandled_exception`
+macro_rules! catch_all_handlers {
+  ($($name:ident => $vector:literal),* $(,)?) => {$(
+    extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+      unhandled_exception($vector, &stack_frame, None);
+    }
+  )*};
+  (error_code: $($name:ident => $vector:literal),* $(,)?) => {$(
+    extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, error_code: u64) {
+      unhandled_exception($vector, &stack_frame, Some(error_code));
+    }
+  )*};
+}
+
+catch_all_handlers!(
+  catch_all_1 => 1,
+  catch_all_4 => 4,
+  catch_all_5 => 5,
+  catch_all_7 => 7,
+  catch_all_16 => 16,
+  catch_all_19 => 19,
+  catch_all_20 => 20,
+  catch_all_28 => 28,
+);
+catch_all_handlers!(error_code:
+  catch_all_10 => 10,
+  catch_all_11 => 11,
+  catch_all_12 => 12,
+  catch_all_21 => 21,
+  catch_all_29 => 29,
+  catch_all_30 => 30,
+);
+
+/// Address of the handler of exception `vector` in `idt` (`None` for reserved vectors)
+fn exception_handler_addr(idt: &InterruptDescriptorTable, vector: u8) -> Option<u64> {
+  let addr = match vector {
+    8 => idt.double_fault.handler_addr(),
+    10 => idt.invalid_tss.handler_addr(),
+    11 => idt.segment_not_present.handler_addr(),
+    12 => idt.stack_segment_fault.handler_addr(),
+    13 => idt.general_protection_fault.handler_addr(),
+    14 => idt.page_fault.handler_addr(),
+    17 => idt.alignment_check.handler_addr(),
+    18 => idt.machine_check.handler_addr(),
+    21 => idt.cp_protection_exception.handler_addr(),
+    29 => idt.vmm_communication_exception.handler_addr(),
+    30 => idt.security_exception.handler_addr(),
+    9 | 15 | 22..=27 | 31 => return None,
+    vector => idt[vector].handler_addr(),
+  };
+  Some(addr.as_u64())
+}
+
+/// Exception vectors of `idt` which have no handler (reserved ones excluded)
+fn missing_exception_handlers(idt: &InterruptDescriptorTable) -> impl Iterator<Item = u8> + '_ {
+  (0..32).filter(move |&vector| exception_handler_addr(idt, vector) == Some(0))
+}
+
+/// Check that every (non-reserved) cpu exception has a handler in the kernel IDT
+///
+/// An exception without a handler escalates to a double fault, or a triple
+/// fault (a silent reboot) if that fails too. Each missing one is reported
+/// over serial.
+pub fn verify_idt_complete() -> bool {
+  let mut complete = true;
+  for vector in missing_exception_handlers(&IDT) {
+    complete = false;
+    crate::serial_println!(
+      "WARNING: no handler for exception vector {} ({}), it would triple-fault",
+      vector,
+      EXCEPTION_NAMES[vector as usize].unwrap_or("RESERVED")
+    );
+  }
+  complete
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
-  Timer = PIC_1_OFFSET, // offset = 0
-  Keyboard,             // offset = +1
+  Timer = PIC_1_OFFSET,       // offset = 0
+  Keyboard,                   // offset = +1
+  Serial1 = PIC_1_OFFSET + 4, // offset = +4 (COM1)
+  Irq7 = PIC_1_OFFSET + 7,    // offset = +7 (spurious on the primary PIC)
+  Mouse = PIC_1_OFFSET + 12,  // offset = +12 (on the secondary PIC)
+  Irq15 = PIC_1_OFFSET + 15,  // offset = +15 (spurious on the secondary PIC)
 }
 
 impl InterruptIndex {
@@ -149,8 +809,45 @@ lazy_static! {
         idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
         // keyboard_interruption
         idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(async_keyboard_interrupt_handler);
-        // page_fault
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        // mouse_interruption
+        idt[InterruptIndex::Mouse.as_u8()].set_handler_fn(async_mouse_interrupt_handler);
+        // serial_interruption
+        idt[InterruptIndex::Serial1.as_u8()].set_handler_fn(async_serial_interrupt_handler);
+        // spurious interrupts of the PICs
+        idt[InterruptIndex::Irq7.as_u8()].set_handler_fn(irq7_handler);
+        idt[InterruptIndex::Irq15.as_u8()].set_handler_fn(irq15_handler);
+        // apic spurious interrupt
+        #[cfg(feature = "use_apic")]
+        idt[crate::apic::SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
+        // divide_error (recoverable)
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        // invalid_opcode
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        // general_protection_fault
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        // page_fault (with a pre-defined reserved stack)
+        unsafe { idt.page_fault.set_handler_fn(page_fault_handler).set_stack_index(gdt::PAGE_FAULT_IST_INDEX) };
+        // non_maskable_interrupt (with a pre-defined reserved stack)
+        unsafe { idt.non_maskable_interrupt.set_handler_fn(nmi_handler).set_stack_index(gdt::NMI_IST_INDEX) };
+        // alignment_check
+        idt.alignment_check.set_handler_fn(alignment_check_handler);
+        // machine_check
+        idt.machine_check.set_handler_fn(machine_check_handler);
+        // catch-all for the remaining exceptions
+        idt.debug.set_handler_fn(catch_all_1);
+        idt.overflow.set_handler_fn(catch_all_4);
+        idt.bound_range_exceeded.set_handler_fn(catch_all_5);
+        idt.device_not_available.set_handler_fn(catch_all_7);
+        idt.invalid_tss.set_handler_fn(catch_all_10);
+        idt.segment_not_present.set_handler_fn(catch_all_11);
+        idt.stack_segment_fault.set_handler_fn(catch_all_12);
+        idt.x87_floating_point.set_handler_fn(catch_all_16);
+        idt.simd_floating_point.set_handler_fn(catch_all_19);
+        idt.virtualization.set_handler_fn(catch_all_20);
+        idt.cp_protection_exception.set_handler_fn(catch_all_21);
+        idt.hv_injection_exception.set_handler_fn(catch_all_28);
+        idt.vmm_communication_exception.set_handler_fn(catch_all_29);
+        idt.security_exception.set_handler_fn(catch_all_30);
         // ref bind
         idt
     };
@@ -158,6 +855,114 @@ lazy_static! {
 
 pub fn init_idt() {
   IDT.load();
+  #[cfg(debug_assertions)]
+  verify_idt_complete();
+}
+
+#[test_case]
+fn test_idt_complete() {
+  assert!(verify_idt_complete());
+  // 32 vectors, 9 of them reserved
+  assert_eq!(
+    missing_exception_handlers(&InterruptDescriptorTable::new()).count(),
+    32 - 9
+  );
+}
+
+#[test_case]
+fn test_capture_registers() {
+  use alloc::format;
+
+  let registers = capture_registers();
+  let rsp = registers.gprs[7];
+  let local = 0u64;
+  let local_addr = core::hint::black_box(&local) as *const u64 as u64;
+  // the snapshot was taken in this frame, whose locals sit right above `rsp`
+  assert!(
+    (local_addr - 4096..=local_addr).contains(&rsp),
+    "rsp {:#x} is not just below {:#x}\n",
+    rsp,
+    local_addr
+  );
+  assert!(RFlags::from_bits_truncate(registers.rflags).contains(RFlags::INTERRUPT_FLAG));
+  let text = format!("{}", registers);
+  assert_eq!(text.lines().count(), 5);
+  assert!(text.starts_with("rax=0x"));
+}
+
+#[test_case]
+fn test_describe_selector_error() {
+  use alloc::format;
+
+  let describe = |error_code| format!("{}", describe_selector_error(error_code));
+  assert_eq!(describe(0), "no selector");
+  assert_eq!(describe(0x50), "GDT[10]");
+  assert_eq!(describe(0x54), "LDT[10]");
+  assert_eq!(describe((3 << 3) | 0b011), "IDT[3] (external)");
+}
+
+#[test_case]
+fn test_div_instruction_len() {
+  let len = |bytes: &[u8]| {
+    let mut code = [0x90; 15];
+    code[..bytes.len()].copy_from_slice(bytes);
+    div_instruction_len(&code)
+  };
+  // div ecx
+  assert_eq!(len(&[0xF7, 0xF1]), Some(2));
+  // idiv r9
+  assert_eq!(len(&[0x49, 0xF7, 0xF9]), Some(3));
+  // div byte ptr [rsp + 8]
+  assert_eq!(len(&[0xF6, 0x74, 0x24, 0x08]), Some(4));
+  // div dword ptr [rip + disp32]
+  assert_eq!(len(&[0xF7, 0x35, 0, 0, 0, 0]), Some(6));
+  // div word ptr [rax + disp32]
+  assert_eq!(len(&[0x66, 0xF7, 0xB0, 0, 0, 0, 0]), Some(7));
+  // neg ecx, nop
+  assert_eq!(len(&[0xF7, 0xD9]), None);
+  assert_eq!(len(&[0x90]), None);
+}
+
+#[test_case]
+fn test_divide_error_recovers() {
+  let errors_before = divide_errors();
+  let divisor = core::hint::black_box(0u32);
+  unsafe {
+    core::arch::asm!(
+      "xor edx, edx",
+      "mov eax, 1",
+      "div {0:e}",
+      in(reg) divisor,
+      out("eax") _,
+      out("edx") _,
+    );
+  }
+  assert_eq!(divide_errors(), errors_before + 1);
+}
+
+#[test_case]
+fn test_spurious_irqs_are_suppressed() {
+  let before = spurious_irqs();
+  // raised by software, so no in-service bit is set
+  unsafe { core::arch::asm!("int {}", const InterruptIndex::Irq7 as u8) };
+  unsafe { core::arch::asm!("int {}", const InterruptIndex::Irq15 as u8) };
+  assert_eq!(spurious_irqs(), before + 2);
+}
+
+#[test_case]
+fn test_nested_interrupt_guards() {
+  assert!(are_enabled());
+  {
+    let _outer = InterruptGuard::new();
+    assert!(!are_enabled());
+    {
+      let _inner = InterruptGuard::new();
+      assert!(!are_enabled());
+    }
+    // the inner guard must not re-enable them
+    assert!(!are_enabled());
+  }
+  assert!(are_enabled());
 }
 
 #[test_case]
@@ -165,3 +970,65 @@ fn test_breakpoint_exception() {
   // invoke a breakpoint exception
   x86_64::instructions::interrupts::int3();
 }
+
+#[test_case]
+fn test_timer_callback() {
+  static COUNTER: AtomicUsize = AtomicUsize::new(0);
+  fn probe() {
+    COUNTER.fetch_add(1, Ordering::SeqCst);
+  }
+
+  set_timer_callback(Some(probe));
+  let start = time::ticks();
+  time::wait_ticks(3);
+  set_timer_callback(None);
+  let elapsed = time::ticks() - start;
+  let called = COUNTER.load(Ordering::SeqCst) as u64;
+  assert!((3..=elapsed + 1).contains(&called));
+
+  time::wait_ticks(3);
+  assert_eq!(COUNTER.load(Ordering::SeqCst) as u64, called);
+}
+
+#[test_case]
+fn test_ist_safe_print() {
+  crate::serial_print!("");
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    // would spin forever if it took the serial lock
+    let _serial = crate::serial::SERIAL1.lock();
+    ist_safe_print("[ist_safe_print] ");
+  });
+}
+
+#[test_case]
+fn test_describe_frame() {
+  use alloc::format;
+  use x86_64::structures::gdt::SegmentSelector;
+
+  let frame = InterruptStackFrameValue::new(
+    VirtAddr::new(0x20_a3c5),
+    SegmentSelector(0x8),
+    RFlags::CARRY_FLAG | RFlags::ZERO_FLAG | RFlags::INTERRUPT_FLAG,
+    VirtAddr::new(0x1000),
+    SegmentSelector(0),
+  );
+  assert_eq!(
+    format!("{}", describe_frame(&frame)),
+    "RIP: 0x20a3c5  CS: 0x8  RSP: 0x1000  SS: 0x0\nRFLAGS: 0x241 [CF ZF IF]"
+  );
+}
+
+#[test_case]
+fn test_are_enabled() {
+  let enabled_before = are_enabled();
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    assert!(!are_enabled());
+    assert_disabled();
+  });
+  assert_eq!(are_enabled(), enabled_before);
+  if enabled_before {
+    assert_enabled();
+  } else {
+    assert_disabled();
+  }
+}
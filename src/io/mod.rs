@@ -0,0 +1,153 @@
+//! Port-mapped I/O
+//!
+//! Thin wrappers over `x86_64::instructions::port::Port`. Each helper performs
+//! exactly one `in`/`out` instruction, the caller is responsible for the
+//! device-level protocol.
+//!
+//! In debug builds, subsystems also `claim` the ports they drive, so two of
+//! them accidentally sharing a port is caught early.
+
+use core::ops::RangeInclusive;
+use x86_64::instructions::port::Port;
+
+/// Read a byte from `port`
+///
+/// # Safety
+///
+/// Port I/O can have side effects on any device (or the whole machine), the
+/// caller must make sure the access is valid for the device at `port`.
+#[inline]
+pub unsafe fn inb(port: u16) -> u8 {
+  Port::<u8>::new(port).read()
+}
+
+/// Write a byte to `port`
+///
+/// # Safety
+///
+/// Same as `inb`.
+#[inline]
+pub unsafe fn outb(port: u16, value: u8) {
+  Port::<u8>::new(port).write(value)
+}
+
+/// Read a word from `port`
+///
+/// # Safety
+///
+/// Same as `inb`.
+#[inline]
+pub unsafe fn inw(port: u16) -> u16 {
+  Port::<u16>::new(port).read()
+}
+
+/// Write a word to `port`
+///
+/// # Safety
+///
+/// Same as `inb`.
+#[inline]
+pub unsafe fn outw(port: u16, value: u16) {
+  Port::<u16>::new(port).write(value)
+}
+
+/// Read a double word from `port`
+///
+/// # Safety
+///
+/// Same as `inb`.
+#[inline]
+pub unsafe fn inl(port: u16) -> u32 {
+  Port::<u32>::new(port).read()
+}
+
+/// Write a double word to `port`
+///
+/// # Safety
+///
+/// Same as `inb`.
+#[inline]
+pub unsafe fn outl(port: u16, value: u32) {
+  Port::<u32>::new(port).write(value)
+}
+
+#[cfg(debug_assertions)]
+mod claims {
+  use core::ops::RangeInclusive;
+  use spin::Mutex;
+
+  const MAX_CLAIMS: usize = 32;
+
+  type Claim = (RangeInclusive<u16>, &'static str);
+
+  static CLAIMS: Mutex<[Option<Claim>; MAX_CLAIMS]> = Mutex::new([const { None }; MAX_CLAIMS]);
+
+  pub fn claim(ports: RangeInclusive<u16>, owner: &'static str) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+      let mut claims = CLAIMS.lock();
+      if claims
+        .iter()
+        .flatten()
+        .any(|claim| *claim == (ports.clone(), owner))
+      {
+        return;
+      }
+      for (claimed, claimer) in claims.iter().flatten() {
+        let overlaps = claimed.start() <= ports.end() && ports.start() <= claimed.end();
+        assert!(
+          !overlaps || *claimer == owner,
+          "ports {:#x?} of `{}` are already claimed by `{}`!\n",
+          ports,
+          owner,
+          claimer
+        );
+      }
+      match claims.iter_mut().find(|claim| claim.is_none()) {
+        Some(free) => *free = Some((ports, owner)),
+        None => {
+          crate::serial_println!("WARNING: port claim table full, `{}` untracked", owner);
+        }
+      }
+    });
+  }
+}
+
+/// Record that `owner` drives `ports`
+///
+/// In debug builds, panics if (part of) `ports` is already claimed by
+/// another owner. Does nothing in release builds.
+pub fn claim(ports: RangeInclusive<u16>, owner: &'static str) {
+  #[cfg(debug_assertions)]
+  claims::claim(ports, owner);
+  #[cfg(not(debug_assertions))]
+  let _ = (ports, owner);
+}
+
+/// Claim the ports of the legacy devices driven by the kernel
+pub fn claim_legacy_devices() {
+  claim(0x20..=0x21, "pic");
+  claim(0xA0..=0xA1, "pic");
+  claim(0x40..=0x43, "pit");
+  claim(0x60..=0x60, "keyboard");
+  claim(0x64..=0x64, "keyboard");
+  claim(0x61..=0x61, "speaker");
+  claim(0x70..=0x71, "cmos");
+  claim(0x3C0..=0x3C1, "vga-attribute");
+  claim(0x3D4..=0x3D5, "vga-crtc");
+  claim(0x3DA..=0x3DA, "vga-attribute");
+  claim(0x3F8..=0x3FF, "com1");
+  claim(0x2F8..=0x2FF, "com2");
+  claim(0xf4..=0xf4, "isa-debug-exit");
+  claim(0x604..=0x605, "acpi-pm");
+}
+
+#[test_case]
+fn test_uart_scratch_register() {
+  const COM1_SCRATCH: u16 = 0x3F8 + 7;
+
+  for value in [0x00, 0xA5, 0x5A, 0xFF] {
+    // SAFETY: the scratch register has no effect on the uart
+    unsafe { outb(COM1_SCRATCH, value) };
+    assert_eq!(unsafe { inb(COM1_SCRATCH) }, value);
+  }
+}
@@ -13,21 +13,31 @@
 extern crate alloc;
 
 pub mod allocator;
+#[cfg(feature = "use_apic")]
+pub mod apic;
+pub mod bench;
 pub mod collections;
 pub mod demo;
+pub mod elf;
 pub mod exit;
 pub mod gdt;
 pub mod interrupts;
 pub mod io;
+pub mod log;
 pub mod memory;
 pub mod prelude;
+pub mod rtc;
 pub mod serial;
 pub mod shell;
+pub mod sound;
 pub mod task;
 pub mod test_framework;
+pub mod time;
+pub mod tui;
 pub mod utils;
 pub mod vga_buffer;
 
+use alloc::{format, string::String, vec::Vec};
 #[cfg(test)]
 use bootloader::entry_point;
 use bootloader::BootInfo;
@@ -48,10 +58,33 @@ fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
   hlt_loop();
 }
 
+/// Run `tests`, in a shuffled order if `TEST_SEED` is set at build time
 pub fn test_runner(tests: &[&dyn Testable]) {
-  serial_println!("\nRunning {} tests\n", tests.len());
-  for test in tests {
-    test.run();
+  let seed = match test_framework::TEST_SEED.map(str::parse::<u64>) {
+    Some(Ok(seed)) if tests.len() <= test_framework::MAX_SHUFFLED_TESTS => Some(seed),
+    Some(_) => {
+      serial_println!("\nWARNING: ignoring `TEST_SEED`, running tests in source order");
+      None
+    }
+    None => None,
+  };
+
+  match seed {
+    Some(seed) => {
+      serial_println!("\nRunning {} tests (TEST_SEED={})\n", tests.len(), seed);
+      let mut order = [0; test_framework::MAX_SHUFFLED_TESTS];
+      let order = &mut order[..tests.len()];
+      test_framework::shuffled_order(order, seed);
+      for &index in order.iter() {
+        tests[index as usize].run();
+      }
+    }
+    None => {
+      serial_println!("\nRunning {} tests\n", tests.len());
+      for test in tests {
+        test.run();
+      }
+    }
   }
   serial_println!();
   exit_qemu(QemuExitCode::Success);
@@ -64,12 +97,129 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
   hlt_loop()
 }
 
+/// Number of log records shown by `print_panic`
+const PANIC_LOG_RECORDS: usize = 8;
+
+/// Render the panic screen, after flushing any pending output
+///
+/// Switches to the emergency allocator first, the caller must halt afterwards.
+/// Besides `info`, shows the most recent log records and the registers,
+/// without allocating.
+pub fn print_panic(info: &PanicInfo) {
+  use core::fmt::Write;
+
+  let registers = interrupts::capture_registers();
+  allocator::emergency::set_emergency_allocator();
+  vga_buffer::force_flush();
+  eprintln!("{}", info);
+
+  // skipped if the panic happened while logging
+  if let Some(records) = log::try_drain() {
+    let skip = records.iter().count().saturating_sub(PANIC_LOG_RECORDS);
+    for record in records.iter().skip(skip) {
+      print_with_color_ln!(Red, "{}", record);
+      let _ = writeln!(serial::RawWriter, "{}", record);
+    }
+  }
+  print_with_color_ln!(Red, "{}", registers);
+  let _ = writeln!(serial::RawWriter, "{}", registers);
+}
+
+/// Cargo features this kernel knows about, with whether they are enabled in this build
+const FEATURES: [(&str, bool); 14] = [
+  ("use_BumpAllocator", cfg!(feature = "use_BumpAllocator")),
+  (
+    "use_LinkedListAllocator",
+    cfg!(feature = "use_LinkedListAllocator"),
+  ),
+  (
+    "use_FixedSizeBlockAllocator",
+    cfg!(feature = "use_FixedSizeBlockAllocator"),
+  ),
+  (
+    "use_LockedHeapAllocator",
+    cfg!(feature = "use_LockedHeapAllocator"),
+  ),
+  (
+    "linked_list_best_fit",
+    cfg!(feature = "linked_list_best_fit"),
+  ),
+  ("use_SimpleExecutor", cfg!(feature = "use_SimpleExecutor")),
+  (
+    "use_EfficientExecutor",
+    cfg!(feature = "use_EfficientExecutor"),
+  ),
+  (
+    "use_SelfDefinedLRUCache",
+    cfg!(feature = "use_SelfDefinedLRUCache"),
+  ),
+  (
+    "usr_def_addr_translate",
+    cfg!(feature = "usr_def_addr_translate"),
+  ),
+  ("use_apic", cfg!(feature = "use_apic")),
+  ("vga_debug", cfg!(feature = "vga_debug")),
+  ("alloc_debug", cfg!(feature = "alloc_debug")),
+  ("timer_trace", cfg!(feature = "timer_trace")),
+  ("debug_assertions", cfg!(debug_assertions)),
+];
+
+/// Describe this build: version, target, allocator and enabled features
+pub fn version_info() -> String {
+  let features = FEATURES
+    .iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| *name)
+    .collect::<Vec<_>>();
+  format!(
+    "{} {} ({})\nallocator: {}\nfeatures: {}",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_VERSION"),
+    env!("EMBER_OS_TARGET"),
+    allocator::ALLOCATOR_NAME,
+    features.join(", ")
+  )
+}
+
+#[test_case]
+fn test_version_info() {
+  let info = version_info();
+  assert!(info.contains(env!("CARGO_PKG_VERSION")));
+  assert!(info.contains(allocator::ALLOCATOR_NAME));
+}
+
 pub fn hlt_loop() -> ! {
   loop {
     x86_64::instructions::hlt()
   }
 }
 
+/// Halt until `cond` holds, re-checking it after every interrupt
+///
+/// `cond` is checked with interrupts disabled and `sti; hlt` follows
+/// atomically, so an interrupt making it true in between can't be missed.
+/// Interrupts are enabled on return (also if they were disabled before).
+pub fn park_until(cond: impl Fn() -> bool) {
+  use x86_64::instructions::interrupts;
+
+  loop {
+    interrupts::disable();
+    if cond() {
+      interrupts::enable();
+      return;
+    }
+    interrupts::enable_and_hlt();
+  }
+}
+
+#[test_case]
+fn test_park_until_next_tick() {
+  let start = time::ticks();
+  park_until(|| time::ticks() > start);
+  assert!(time::ticks() > start);
+  assert!(interrupts::are_enabled());
+}
+
 #[cfg(test)]
 #[panic_handler]
 pub fn panic(info: &PanicInfo) -> ! {
@@ -77,12 +227,22 @@ pub fn panic(info: &PanicInfo) -> ! {
 }
 
 pub fn minimum_init(boot_info: &'static BootInfo) {
+  // `WRITER` init (before any interrupt handler could print)
+  vga_buffer::init();
   // gdt(tss) init
   gdt::init();
+  // record the ports of the legacy devices (debug builds only)
+  io::claim_legacy_devices();
   // idt init
   interrupts::init_idt();
+  // keyboard init (before interrupts are enabled)
+  task::keyboard::init_keyboard();
   // PIC init
   unsafe { interrupts::PICS.lock().initialize() };
+  // mouse init (after the PIC, which would mask IRQ12 again)
+  task::mouse::init_mouse();
+  // serial input (after the PIC as well)
+  task::serial::init_serial_input();
   // enable listening on PIC
   x86_64::instructions::interrupts::enable();
   // heap init
@@ -93,4 +253,7 @@ pub fn minimum_init(boot_info: &'static BootInfo) {
     (mapper, frame_allocator)
   };
   allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed!\n");
+  // switch from the PICs to the APIC (needs the PIT ticking, to calibrate)
+  #[cfg(feature = "use_apic")]
+  apic::init(&mut mapper, &mut frame_allocator).expect("apic initialization failed!\n");
 }
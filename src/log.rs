@@ -0,0 +1,195 @@
+//! Leveled logging, kept in an in-memory ring buffer of recent records
+
+use crate::vga_buffer::{self, Color};
+use alloc::{collections::VecDeque, string::String};
+use core::fmt;
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+/// Number of records kept, older ones are dropped
+pub const CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+  Trace,
+  Debug,
+  Info,
+  Warn,
+  Error,
+}
+
+impl LogLevel {
+  /// Color the records of this level are printed in
+  pub fn color(self) -> Color {
+    match self {
+      LogLevel::Trace => Color::DarkGray,
+      LogLevel::Debug => Color::Cyan,
+      LogLevel::Info => Color::Green,
+      LogLevel::Warn => Color::Yellow,
+      LogLevel::Error => Color::Red,
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      LogLevel::Trace => "TRACE",
+      LogLevel::Debug => "DEBUG",
+      LogLevel::Info => "INFO",
+      LogLevel::Warn => "WARN",
+      LogLevel::Error => "ERROR",
+    }
+  }
+}
+
+/// A logged message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+  pub level: LogLevel,
+  /// Timer ticks when it was logged
+  pub ticks: u64,
+  pub message: String,
+}
+
+impl fmt::Display for Record {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "[{:>5} {}] {}",
+      self.level.name(),
+      self.ticks,
+      self.message
+    )
+  }
+}
+
+static RECORDS: Mutex<VecDeque<Record>> = Mutex::new(VecDeque::new());
+
+/// Print `args` in the color of `level`, and keep it in the ring buffer
+pub fn log(level: LogLevel, args: fmt::Arguments) {
+  let record = Record {
+    level,
+    ticks: crate::time::ticks(),
+    message: alloc::format!("{}", args),
+  };
+  vga_buffer::safe_print_with_color(format_args!("{}\n", record), level.color());
+  interrupts::without_interrupts(|| {
+    let mut records = RECORDS.lock();
+    if records.len() == CAPACITY {
+      records.pop_front();
+    }
+    records.push_back(record);
+  });
+}
+
+/// The records in the ring buffer, removed once this is dropped
+pub struct Drain {
+  records: MutexGuard<'static, VecDeque<Record>>,
+}
+
+impl Drain {
+  /// Oldest first
+  pub fn iter(&self) -> impl Iterator<Item = &Record> {
+    self.records.iter()
+  }
+}
+
+impl<'a> IntoIterator for &'a Drain {
+  type Item = &'a Record;
+  type IntoIter = alloc::collections::vec_deque::Iter<'a, Record>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.records.iter()
+  }
+}
+
+impl Drop for Drain {
+  fn drop(&mut self) {
+    self.records.clear();
+  }
+}
+
+/// Take the records out of the ring buffer
+///
+/// Logging blocks until the `Drain` is dropped, so keep interrupts disabled
+/// meanwhile (see `try_drain`).
+pub fn drain() -> Drain {
+  Drain {
+    records: RECORDS.lock(),
+  }
+}
+
+/// Like `drain`, but gives up if the ring buffer is locked (e.g. on panic)
+pub fn try_drain() -> Option<Drain> {
+  RECORDS.try_lock().map(|records| Drain { records })
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::LogLevel::Trace, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::LogLevel::Debug, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::LogLevel::Info, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::LogLevel::Warn, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => ($crate::log::log($crate::log::LogLevel::Error, format_args!($($arg)*)));
+}
+
+#[cfg(test)]
+mod test_log {
+  use super::*;
+  use alloc::vec::Vec;
+
+  #[test_case]
+  fn records_are_kept_in_order() {
+    interrupts::without_interrupts(|| drop(drain()));
+    crate::log_debug!("debug {}", 1);
+    crate::log_info!("info");
+    crate::log_warn!("warn");
+    crate::log_error!("error {}", 2);
+
+    let logged = interrupts::without_interrupts(|| {
+      drain()
+        .iter()
+        .map(|record| (record.level, record.message.clone()))
+        .collect::<Vec<_>>()
+    });
+    assert_eq!(
+      logged,
+      [
+        (LogLevel::Debug, "debug 1".into()),
+        (LogLevel::Info, "info".into()),
+        (LogLevel::Warn, "warn".into()),
+        (LogLevel::Error, "error 2".into()),
+      ]
+    );
+    // drained
+    assert_eq!(interrupts::without_interrupts(|| drain().iter().count()), 0);
+  }
+
+  #[test_case]
+  fn oldest_records_are_dropped() {
+    interrupts::without_interrupts(|| drop(drain()));
+    for i in 0..CAPACITY + 2 {
+      crate::log_trace!("{}", i);
+    }
+    interrupts::without_interrupts(|| {
+      let drain = drain();
+      assert_eq!(drain.iter().count(), CAPACITY);
+      assert_eq!(drain.iter().next().unwrap().message, "2");
+    });
+  }
+}
@@ -8,7 +8,7 @@ extern crate alloc;
 
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use ember_os::{demo, eprintln, println, task};
+use ember_os::{demo, println, task};
 
 entry_point!(main);
 
@@ -38,7 +38,7 @@ fn main(boot_info: &'static BootInfo) -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 pub(crate) fn panic(info: &PanicInfo) -> ! {
-  eprintln!("{}", info);
+  ember_os::print_panic(info);
   ember_os::hlt_loop()
 }
 
@@ -50,5 +50,5 @@ fn panic(info: &PanicInfo) -> ! {
 
 #[test_case]
 fn test_framework_check() {
-  eprintln!("Make sure the user defined test framework works!");
+  ember_os::eprintln!("Make sure the user defined test framework works!");
 }
@@ -1,7 +1,14 @@
+use crate::{allocator, gdt, serial_println};
+use alloc::vec::Vec;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::fmt;
+use spin::Mutex;
 use x86_64::{
+  instructions::interrupts,
   structures::paging::{
-    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+    mapper::{MapToError, UnmapError},
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, Size4KiB,
   },
   PhysAddr, VirtAddr,
 };
@@ -14,14 +21,26 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
   }
 }
 
+impl FrameDeallocator<Size4KiB> for EmptyFrameAllocator {
+  /// Never called with a frame of its own (it hands out none)
+  unsafe fn deallocate_frame(&mut self, _frame: PhysFrame) {}
+}
+
 /// ## BootInfoFrameAllocator
 ///
 /// A `FrameAllocator` which gets available frames from bootloader's memory map
 pub struct BootInfoFrameAllocator {
   memory_map: &'static MemoryMap,
   next: usize,
+  /// Freed frames, handed out again first
+  free: Vec<PhysFrame>,
+  /// Frames freed before the heap is initialized
+  early_free: [Option<PhysFrame>; EARLY_FREE_FRAMES],
 }
 
+/// Capacity of `BootInfoFrameAllocator::early_free`
+const EARLY_FREE_FRAMES: usize = 16;
+
 impl BootInfoFrameAllocator {
   /// memory_map => FrameAllocator
   ///
@@ -34,8 +53,37 @@ impl BootInfoFrameAllocator {
     BootInfoFrameAllocator {
       memory_map,
       next: 0,
+      free: Vec::new(),
+      early_free: [None; EARLY_FREE_FRAMES],
+    }
+  }
+
+  /// Give `frame` back, to be returned by a later `allocate_frame`
+  ///
+  /// Before the heap is initialized only `EARLY_FREE_FRAMES` frames can be
+  /// kept, further ones are leaked (with a warning).
+  ///
+  /// # Safety
+  ///
+  /// `frame` must have been allocated by this allocator, and must not be
+  /// mapped (or otherwise used) anymore.
+  pub unsafe fn free_frame(&mut self, frame: PhysFrame) {
+    if allocator::heap_size() > 0 {
+      self.free.push(frame);
+      return;
+    }
+    match self.early_free.iter_mut().find(|slot| slot.is_none()) {
+      Some(slot) => *slot = Some(frame),
+      None => {
+        serial_println!("WARNING: early free frame list full, leaking {:?}", frame);
+      }
     }
   }
+
+  /// Number of freed frames waiting to be reused
+  pub fn free_frames(&self) -> usize {
+    self.free.len() + self.early_free.iter().flatten().count()
+  }
 }
 
 impl BootInfoFrameAllocator {
@@ -58,6 +106,15 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
   fn allocate_frame(&mut self) -> Option<PhysFrame> {
+    // reuse a freed frame
+    let early = self
+      .early_free
+      .iter_mut()
+      .rev()
+      .find_map(|slot| slot.take());
+    if let Some(frame) = early.or_else(|| self.free.pop()) {
+      return Some(frame);
+    }
     // get available frame
     let frame = self.usable_frames().nth(self.next);
     // update the mark
@@ -67,6 +124,12 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
   }
 }
 
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+  unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+    self.free_frame(frame)
+  }
+}
+
 /// create an example mapping to `0xb8000` => VGA_BUFFER
 pub fn create_example_mapping(
   page: Page,
@@ -85,6 +148,141 @@ pub fn create_example_mapping(
   map_to_result.expect("map_to failed!\n").flush();
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmapPageError {
+  /// The page has no mapping
+  NotMapped,
+  /// The page lies inside a huge page
+  HugePage,
+  /// The page table entry holds an invalid frame address
+  InvalidFrame(PhysAddr),
+}
+
+impl From<UnmapError> for UnmapPageError {
+  fn from(err: UnmapError) -> Self {
+    match err {
+      UnmapError::PageNotMapped => UnmapPageError::NotMapped,
+      UnmapError::ParentEntryHugePage => UnmapPageError::HugePage,
+      UnmapError::InvalidFrameAddress(addr) => UnmapPageError::InvalidFrame(addr),
+    }
+  }
+}
+
+impl fmt::Display for UnmapPageError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      UnmapPageError::NotMapped => write!(f, "page is not mapped"),
+      UnmapPageError::HugePage => write!(f, "page is part of a huge page"),
+      UnmapPageError::InvalidFrame(addr) => write!(f, "invalid frame address {:#x}", addr),
+    }
+  }
+}
+
+/// Unmap `page` and flush it from the TLB, returning the frame it was mapped to
+///
+/// The frame is not freed, hand it to `BootInfoFrameAllocator::free_frame`
+/// once nothing else maps it.
+pub fn unmap_page(
+  mapper: &mut impl Mapper<Size4KiB>,
+  page: Page<Size4KiB>,
+) -> Result<PhysFrame, UnmapPageError> {
+  let (frame, flush) = mapper.unmap(page)?;
+  flush.flush();
+  Ok(frame)
+}
+
+/// Virtual window handed out by `map_region`
+pub const REGION_WINDOW: Region = Region::new("regions", 0x_5555_0000_0000, 1 << 30);
+
+/// Start of the part of `REGION_WINDOW` not handed out yet
+static NEXT_REGION: Mutex<u64> = Mutex::new(REGION_WINDOW.start);
+
+#[derive(Debug)]
+pub enum MapRegionError {
+  /// `REGION_WINDOW` has no room left for the region
+  WindowFull,
+  Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for MapRegionError {
+  fn from(err: MapToError<Size4KiB>) -> Self {
+    MapRegionError::Map(err)
+  }
+}
+
+impl fmt::Display for MapRegionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      MapRegionError::WindowFull => write!(f, "no room left in {}", REGION_WINDOW),
+      MapRegionError::Map(err) => write!(f, "mapping the region failed: {:?}", err),
+    }
+  }
+}
+
+/// Map `size` bytes (rounded up to whole pages) of fresh frames into
+/// `REGION_WINDOW`, returning the start of the region
+///
+/// Regions are never handed out twice. On failure, the pages mapped so far
+/// are unmapped and their frames freed again, and the window space is given
+/// back (unless another region was handed out meanwhile).
+pub fn map_region<A>(
+  mapper: &mut impl Mapper<Size4KiB>,
+  frame_allocator: &mut A,
+  size: usize,
+  flags: PageTableFlags,
+) -> Result<VirtAddr, MapRegionError>
+where
+  A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+  let size = x86_64::align_up(size.max(1) as u64, Size4KiB::SIZE);
+  let start = interrupts::without_interrupts(|| {
+    let mut next = NEXT_REGION.lock();
+    let start = *next;
+    match start.checked_add(size) {
+      Some(end) if end <= REGION_WINDOW.end => {
+        *next = end;
+        Ok(VirtAddr::new(start))
+      }
+      _ => Err(MapRegionError::WindowFull),
+    }
+  })?;
+
+  let pages = Page::range(
+    Page::containing_address(start),
+    Page::containing_address(start + size),
+  );
+  for (mapped, page) in pages.enumerate() {
+    let result = frame_allocator
+      .allocate_frame()
+      .ok_or(MapToError::FrameAllocationFailed)
+      .and_then(|frame| {
+        let result = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
+        if result.is_err() {
+          unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+        result
+      });
+    match result {
+      Ok(flush) => flush.flush(),
+      Err(err) => {
+        for page in pages.take(mapped) {
+          if let Ok(frame) = unmap_page(mapper, page) {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+          }
+        }
+        interrupts::without_interrupts(|| {
+          let mut next = NEXT_REGION.lock();
+          if *next == start.as_u64() + size {
+            *next = start.as_u64();
+          }
+        });
+        return Err(err.into());
+      }
+    }
+  }
+  Ok(start)
+}
+
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
   use x86_64::registers::control::Cr3;
 
@@ -144,3 +342,151 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Opt
 pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
   translate_addr_inner(addr, physical_memory_offset)
 }
+
+/// A named range `start..end` of virtual addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+  pub name: &'static str,
+  pub start: u64,
+  pub end: u64,
+}
+
+impl Region {
+  pub const fn new(name: &'static str, start: u64, size: u64) -> Self {
+    Self {
+      name,
+      start,
+      end: start + size,
+    }
+  }
+
+  pub fn overlaps(&self, other: &Region) -> bool {
+    self.start < other.end && other.start < self.end
+  }
+}
+
+impl fmt::Display for Region {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "`{}` ({:#x}..{:#x})", self.name, self.start, self.end)
+  }
+}
+
+/// Two regions sharing addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conflict(pub Region, pub Region);
+
+impl fmt::Display for Conflict {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} overlaps {}", self.0, self.1)
+  }
+}
+
+/// The VGA text buffer (identity-mapped by the bootloader)
+const VGA_REGION: Region = Region::new("vga", 0xb8000, 80 * 25 * 2);
+
+/// Maximum number of regions registered with `reserve_region`
+const MAX_RESERVED: usize = 16;
+
+/// Regions registered with `reserve_region`
+static RESERVED: Mutex<[Option<Region>; MAX_RESERVED]> = Mutex::new([None; MAX_RESERVED]);
+
+/// The heap range, grown by `additional` bytes
+fn heap_region(additional: usize) -> Region {
+  Region::new(
+    "heap",
+    allocator::heap_start() as u64,
+    (allocator::heap_size() + additional) as u64,
+  )
+}
+
+/// Every known region except the heap
+fn fixed_regions(mut f: impl FnMut(&Region) -> Result<(), Conflict>) -> Result<(), Conflict> {
+  f(&VGA_REGION)?;
+  f(&REGION_WINDOW)?;
+  for (name, stack) in gdt::kernel_stacks() {
+    f(&Region::new(
+      name,
+      stack.start.as_u64(),
+      stack.end - stack.start,
+    ))?;
+  }
+  let reserved = interrupts::without_interrupts(|| *RESERVED.lock());
+  reserved.iter().flatten().try_for_each(f)
+}
+
+/// Check that `region` overlaps none of the known regions but the heap
+fn check_against_fixed(region: &Region) -> Result<(), Conflict> {
+  fixed_regions(|other| match region.overlaps(other) {
+    true => Err(Conflict(*region, *other)),
+    false => Ok(()),
+  })
+}
+
+/// Check that the heap, the kernel stacks, the VGA buffer and the reserved
+/// regions don't overlap, returning the first conflict found
+pub fn check_address_space() -> Result<(), Conflict> {
+  check_against_fixed(&heap_region(0))?;
+  let mut seen = [None; MAX_RESERVED + 2 + gdt::KERNEL_STACKS];
+  let mut count = 0;
+  fixed_regions(|region| {
+    for other in seen[..count].iter().flatten() {
+      if region.overlaps(other) {
+        return Err(Conflict(*region, *other));
+      }
+    }
+    seen[count] = Some(*region);
+    count += 1;
+    Ok(())
+  })
+}
+
+/// Check whether growing the heap by `additional` bytes would run into
+/// another region
+pub fn check_heap_extension(additional: usize) -> Result<(), Conflict> {
+  check_against_fixed(&heap_region(additional))
+}
+
+/// Register `region` (e.g. a stack or an MMIO window) for the address space checks
+///
+/// Fails if it overlaps a known region (or the table is full, reported as a
+/// conflict with itself).
+pub fn reserve_region(region: Region) -> Result<(), Conflict> {
+  check_against_fixed(&region)?;
+  if region.overlaps(&heap_region(0)) {
+    return Err(Conflict(region, heap_region(0)));
+  }
+  interrupts::without_interrupts(|| {
+    let mut reserved = RESERVED.lock();
+    let free = reserved
+      .iter_mut()
+      .find(|slot| slot.is_none())
+      .ok_or(Conflict(region, region))?;
+    *free = Some(region);
+    Ok(())
+  })
+}
+
+/// Unregister the region reserved as `name`
+pub fn release_region(name: &str) {
+  interrupts::without_interrupts(|| {
+    for slot in RESERVED.lock().iter_mut() {
+      if slot.is_some_and(|region| region.name == name) {
+        *slot = None;
+      }
+    }
+  });
+}
+
+#[test_case]
+fn test_heap_extension_into_reserved_region() {
+  assert_eq!(check_address_space(), Ok(()));
+
+  let heap_end = (allocator::heap_start() + allocator::heap_size()) as u64;
+  let guard = Region::new("test guard", heap_end + 0x1000, 0x1000);
+  reserve_region(guard).expect("reserving failed!\n");
+  let checked = check_heap_extension(0x4000);
+  release_region("test guard");
+
+  assert_eq!(checked, Err(Conflict(heap_region(0x4000), guard)));
+  assert_eq!(check_heap_extension(0x4000), Ok(()));
+}
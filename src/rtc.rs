@@ -0,0 +1,173 @@
+//! Wall-clock time from the CMOS real-time clock
+//!
+//! A register is read by writing its index to `CMOS_ADDRESS_PORT`, then
+//! reading `CMOS_DATA_PORT`. Bit 7 of the index disables NMIs, it is always
+//! left clear.
+
+use crate::io;
+use core::fmt;
+
+const CMOS_ADDRESS_PORT: u16 = 0x70;
+const CMOS_DATA_PORT: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Status A: an update is in progress, the time registers may be torn
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Status B: the hours are in 24-hour format (else 12-hour, bit 7 => pm)
+const HOURS_24: u8 = 1 << 1;
+/// Status B: the values are binary (else BCD)
+const BINARY_MODE: u8 = 1 << 2;
+/// Bit of the hours register marking pm (12-hour format)
+const HOURS_PM: u8 = 1 << 7;
+
+/// Years of the RTC count from here (there is no century register without ACPI)
+const CENTURY: u16 = 2000;
+
+/// A date and time of day, as kept by the RTC (usually UTC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+  pub year: u16,
+  pub month: u8,
+  pub day: u8,
+  pub hour: u8,
+  pub minute: u8,
+  pub second: u8,
+}
+
+impl DateTime {
+  /// Seconds since `1970-01-01 00:00:00`
+  pub fn total_seconds(&self) -> u64 {
+    let days = days_since_epoch(self.year as i64, self.month as i64, self.day as i64);
+    days as u64 * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+  }
+}
+
+impl fmt::Display for DateTime {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+      self.year, self.month, self.day, self.hour, self.minute, self.second
+    )
+  }
+}
+
+/// Days from `1970-01-01` to the given (proleptic Gregorian) date
+fn days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+  // count years from March, so the leap day ends a year
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let year_of_era = year - era * 400;
+  let month_from_march = (month + 9) % 12;
+  let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146097 + day_of_era - 719468
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+  (value >> 4) * 10 + (value & 0x0f)
+}
+
+fn read_register(register: u8) -> u8 {
+  // SAFETY: selects a register (with NMIs left enabled), then reads it
+  unsafe {
+    io::outb(CMOS_ADDRESS_PORT, register & 0x7f);
+    io::inb(CMOS_DATA_PORT)
+  }
+}
+
+/// The raw time registers, read once no update is in progress
+fn read_raw() -> [u8; 6] {
+  while read_register(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+    core::hint::spin_loop();
+  }
+  [
+    REG_SECONDS,
+    REG_MINUTES,
+    REG_HOURS,
+    REG_DAY,
+    REG_MONTH,
+    REG_YEAR,
+  ]
+  .map(read_register)
+}
+
+/// Decode the raw time registers, according to status register B
+fn decode(raw: [u8; 6], status_b: u8) -> DateTime {
+  let [second, minute, hours, day, month, year] = raw;
+  let pm = status_b & HOURS_24 == 0 && hours & HOURS_PM != 0;
+  let convert = |value: u8| match status_b & BINARY_MODE {
+    0 => bcd_to_binary(value),
+    _ => value,
+  };
+  let mut hour = convert(hours & !HOURS_PM);
+  if status_b & HOURS_24 == 0 {
+    // 12 am => 0, 12 pm => 12
+    hour = hour % 12 + if pm { 12 } else { 0 };
+  }
+  DateTime {
+    year: CENTURY + convert(year) as u16,
+    month: convert(month),
+    day: convert(day),
+    hour,
+    minute: convert(minute),
+    second: convert(second),
+  }
+}
+
+/// Read the current date and time from the RTC
+///
+/// Reads until two reads in a row agree, so an update can't tear the result.
+pub fn now() -> DateTime {
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    let mut raw = read_raw();
+    loop {
+      let again = read_raw();
+      if again == raw {
+        break;
+      }
+      raw = again;
+    }
+    decode(raw, read_register(REG_STATUS_B))
+  })
+}
+
+#[cfg(test)]
+mod test_rtc {
+  use super::*;
+
+  #[test_case]
+  fn decodes_bcd_and_12_hour_format() {
+    // 2024-02-29 11:05:09 pm, in BCD
+    let raw = [0x09, 0x05, 0x11 | HOURS_PM, 0x29, 0x02, 0x24];
+    let time = decode(raw, 0);
+    assert_eq!(
+      time,
+      DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 23,
+        minute: 5,
+        second: 9,
+      }
+    );
+    assert_eq!(time.total_seconds(), 1_709_247_909);
+    assert_eq!(decode([9, 5, 23, 29, 2, 24], HOURS_24 | BINARY_MODE), time);
+  }
+
+  #[test_case]
+  fn time_does_not_go_backwards() {
+    let first = now();
+    let second = now();
+    assert!(second.total_seconds() >= first.total_seconds());
+  }
+}
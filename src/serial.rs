@@ -1,26 +1,303 @@
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::lazy_static;
 use spin::Mutex;
-use uart_16550::SerialPort;
+
+/// Base port of the first serial port
+pub const COM1: u16 = 0x3F8;
+/// Base port of the second serial port
+pub const COM2: u16 = 0x2F8;
 
 lazy_static! {
-  pub static ref SERIAL1: Mutex<SerialPort> = {
-    let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-    serial_port.init();
-    Mutex::new(serial_port)
+  pub static ref SERIAL1: Mutex<SerialPort> = Mutex::new(SerialPort::init(COM1));
+  pub static ref SERIAL2: Mutex<SerialPort> = Mutex::new(SerialPort::init(COM2));
+}
+
+/// Line control register (relative to the base port)
+const LINE_CONTROL: u16 = 3;
+/// "divisor latch access" bit of the line control, mapping the divisor
+/// over the data and interrupt enable registers
+const DLAB: u8 = 1 << 7;
+/// Line status register (relative to the base port)
+const LINE_STATUS: u16 = 5;
+/// "transmitter holding register empty" bit of the line status
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+/// "data ready" bit of the line status
+const DATA_READY: u8 = 1 << 0;
+
+/// Clock of the UART divided by 16, i.e. the baud rate at divisor `1`
+pub const MAX_BAUD_RATE: u32 = 115200;
+/// Baud rate at the largest divisor (rounded up)
+pub const MIN_BAUD_RATE: u32 = MAX_BAUD_RATE.div_ceil(u16::MAX as u32);
+
+/// The baud rate can't be approximated within 2% by any divisor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedBaudRate(pub u32);
+
+/// Divisor for `rate`, clamped into `MIN_BAUD_RATE..=MAX_BAUD_RATE`
+pub fn baud_divisor(rate: u32) -> Result<u16, UnsupportedBaudRate> {
+  let rate = rate.clamp(MIN_BAUD_RATE, MAX_BAUD_RATE);
+  let divisor = (MAX_BAUD_RATE + rate / 2) / rate;
+  let actual = MAX_BAUD_RATE / divisor;
+  if actual.abs_diff(rate) * 50 > rate {
+    return Err(UnsupportedBaudRate(rate));
+  }
+  Ok(divisor as u16)
+}
+
+/// A 16550 UART at a base port (`COM1` or `COM2`)
+pub struct SerialPort {
+  base: u16,
+  uart: uart_16550::SerialPort,
+  /// Column of the output, as seen by `safe_print`
+  column: usize,
+}
+
+impl SerialPort {
+  /// Initialize the UART at `base`
+  pub fn init(base: u16) -> Self {
+    let mut uart = unsafe { uart_16550::SerialPort::new(base) };
+    uart.init();
+    Self {
+      base,
+      uart,
+      column: 0,
+    }
+  }
+
+  /// Get the base port
+  pub fn base(&self) -> u16 {
+    self.base
+  }
+
+  /// Write `byte` without any translation, see `putc`
+  pub fn putc(&mut self, byte: u8) {
+    putc_at(self.base, byte);
+  }
+
+  /// Read a received byte, if there is one
+  pub fn read_byte(&mut self) -> Option<u8> {
+    read_byte_at(self.base)
+  }
+
+  /// Program the divisor for `rate` (see `baud_divisor`), returning the
+  /// actual baud rate
+  pub fn set_baud_rate(&mut self, rate: u32) -> Result<u32, UnsupportedBaudRate> {
+    use crate::io::{inb, outb};
+
+    let divisor = baud_divisor(rate)?;
+    let [low, high] = divisor.to_le_bytes();
+    // SAFETY: `base` is the uart initialized by `init`, only its divisor changes
+    unsafe {
+      let line_control = inb(self.base + LINE_CONTROL);
+      outb(self.base + LINE_CONTROL, line_control | DLAB);
+      outb(self.base, low);
+      outb(self.base + 1, high);
+      outb(self.base + LINE_CONTROL, line_control & !DLAB);
+    }
+    Ok(MAX_BAUD_RATE / divisor as u32)
+  }
+}
+
+/// Change the baud rate of COM1, see `SerialPort::set_baud_rate`
+pub fn set_baud_rate(rate: u32) -> Result<u32, UnsupportedBaudRate> {
+  x86_64::instructions::interrupts::without_interrupts(|| SERIAL1.lock().set_baud_rate(rate))
+}
+
+impl fmt::Write for SerialPort {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.uart.write_str(s)
+  }
+}
+
+/// `base` must be the base port of an initialized uart (`COM1` or `COM2`)
+#[inline]
+fn putc_at(base: u16, byte: u8) {
+  // SAFETY: polls the line status, then writes the transmit register of that uart
+  unsafe {
+    while crate::io::inb(base + LINE_STATUS) & TRANSMIT_EMPTY == 0 {
+      core::hint::spin_loop();
+    }
+    crate::io::outb(base, byte);
+  }
+}
+
+/// `base` must be the base port of an initialized uart (`COM1` or `COM2`)
+#[inline]
+fn read_byte_at(base: u16) -> Option<u8> {
+  // SAFETY: reads the line status, then the receive register of that uart
+  unsafe {
+    match crate::io::inb(base + LINE_STATUS) & DATA_READY {
+      0 => None,
+      _ => Some(crate::io::inb(base)),
+    }
+  }
+}
+
+/// Write `byte` straight to the COM1 UART, bypassing the `SERIAL1` lock
+///
+/// Only polls the transmit-ready bit, assuming the UART has been
+/// initialized already. Meant for hot tracing paths, see `serial_putc!`.
+#[inline]
+pub fn putc(byte: u8) {
+  putc_at(COM1, byte);
+}
+
+/// Read a received byte from COM1, if there is one
+///
+/// Like `putc`, bypasses the `SERIAL1` lock.
+#[inline]
+pub fn read_byte() -> Option<u8> {
+  read_byte_at(COM1)
+}
+
+/// Wait for a byte from COM1, see `read_byte`
+///
+/// With the receive interrupt enabled (see `task::serial::init_serial_input`)
+/// the interrupt handler may take the byte first, prefer `SerialStream` then.
+pub fn read_byte_blocking() -> u8 {
+  loop {
+    if let Some(byte) = read_byte() {
+      return byte;
+    }
+    core::hint::spin_loop();
+  }
+}
+
+/// Write `bytes` straight to the COM1 UART, see `putc`
+pub fn write_raw(bytes: &[u8]) {
+  for &byte in bytes {
+    putc(byte);
+  }
+}
+
+/// `fmt::Write` adapter of `write_raw`, for formatted output where taking
+/// the `SERIAL1` lock could deadlock (e.g. on panic)
+pub struct RawWriter;
+
+impl fmt::Write for RawWriter {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    write_raw(s.as_bytes());
+    Ok(())
+  }
+}
+
+/// Format `n` as lowercase hex (no prefix) into the tail of `buf`
+fn hex_digits(mut n: u64, buf: &mut [u8; 16]) -> &[u8] {
+  let mut start = buf.len();
+  loop {
+    start -= 1;
+    buf[start] = b"0123456789abcdef"[(n & 0xf) as usize];
+    n >>= 4;
+    if n == 0 {
+      return &buf[start..];
+    }
+  }
+}
+
+/// Format `n` as decimal into the tail of `buf`
+fn dec_digits(mut n: u64, buf: &mut [u8; 20]) -> &[u8] {
+  let mut start = buf.len();
+  loop {
+    start -= 1;
+    buf[start] = b'0' + (n % 10) as u8;
+    n /= 10;
+    if n == 0 {
+      return &buf[start..];
+    }
+  }
+}
+
+/// Write `n` in lowercase hex (like `{:x}`) to the serial port
+///
+/// Uses neither `core::fmt` nor the `SERIAL1` lock (see `write_raw`), so it
+/// is usable in early boot and exception handlers.
+pub fn write_hex(n: u64) {
+  write_raw(hex_digits(n, &mut [0; 16]));
+}
+
+/// Write `n` in decimal (like `{}`) to the serial port, see `write_hex`
+pub fn write_dec(n: u64) {
+  write_raw(dec_digits(n, &mut [0; 20]));
+}
+
+/// Tab stops are every `TAB_WIDTH` columns (matching the default on VGA)
+pub const TAB_WIDTH: usize = 8;
+
+/// Whether `safe_print` expands `\t` to spaces
+static TAB_EXPANSION: AtomicBool = AtomicBool::new(false);
+
+/// Expand `\t` in serial output to spaces up to the next tab stop
+///
+/// Off by default, forwarding `\t` as is (rendered by the remote terminal).
+pub fn set_tab_expansion(expand: bool) {
+  TAB_EXPANSION.store(expand, Ordering::Relaxed);
+}
+
+/// Forwards to `inner`, tracking the column and optionally expanding tabs
+struct TabExpander<'a, W: fmt::Write> {
+  inner: &'a mut W,
+  column: usize,
+  expand: bool,
+}
+
+impl<W: fmt::Write> fmt::Write for TabExpander<'_, W> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    for (i, part) in s.split('\t').enumerate() {
+      if i > 0 {
+        if self.expand {
+          let spaces = TAB_WIDTH - self.column % TAB_WIDTH;
+          for _ in 0..spaces {
+            self.inner.write_char(' ')?;
+          }
+          self.column += spaces;
+        } else {
+          self.inner.write_char('\t')?;
+          self.column += TAB_WIDTH - self.column % TAB_WIDTH;
+        }
+      }
+      self.inner.write_str(part)?;
+      self.column = match part.rfind(['\n', '\r']) {
+        Some(end) => part.len() - end - 1,
+        None => self.column + part.len(),
+      };
+    }
+    Ok(())
+  }
+}
+
+fn print_to(port: &Mutex<SerialPort>, args: fmt::Arguments) {
+  use fmt::Write;
+
+  // access the port without being interrupted by signals
+  let _guard = crate::interrupts::InterruptGuard::new();
+  let mut port = port.lock();
+  let port = &mut *port;
+  let mut writer = TabExpander {
+    inner: &mut port.uart,
+    column: port.column,
+    expand: TAB_EXPANSION.load(Ordering::Relaxed),
   };
+  writer.write_fmt(args).expect("printing to serial failed!");
+  port.column = writer.column;
 }
 
-pub fn safe_print(args: ::core::fmt::Arguments) {
-  use core::fmt::Write;
-  use x86_64::instructions::interrupts;
+/// Print to COM1
+pub fn safe_print(args: fmt::Arguments) {
+  print_to(&SERIAL1, args);
+}
 
-  // access SERIAL1 without being interrupted by signals
-  interrupts::without_interrupts(|| {
-    SERIAL1
-      .lock()
-      .write_fmt(args)
-      .expect("printing to serial failed!");
-  });
+/// Print to COM2
+pub fn safe_print2(args: fmt::Arguments) {
+  print_to(&SERIAL2, args);
+}
+
+/// Writes a single byte to the host through the serial interface, without formatting.
+#[macro_export]
+macro_rules! serial_putc {
+  ($byte:expr) => {
+    $crate::serial::putc($byte)
+  };
 }
 
 /// Prints to the host through the serial interface.
@@ -38,3 +315,119 @@ macro_rules! serial_println {
     ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+/// Prints to the host through the second serial interface.
+#[macro_export]
+macro_rules! serial2_print {
+    ($($arg:tt)*) => {
+        $crate::serial::safe_print2(format_args!($($arg)*));
+    };
+}
+
+/// Prints to the host through the second serial interface, appending a newline.
+#[macro_export]
+macro_rules! serial2_println {
+    () => ($crate::serial2_print!("\n"));
+    ($fmt:expr) => ($crate::serial2_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial2_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+#[test_case]
+fn test_integer_digits() {
+  use alloc::format;
+
+  for n in [0, 1, 9, 10, 0xdead_beef, 1 << 63, u64::MAX] {
+    assert_eq!(hex_digits(n, &mut [0; 16]), format!("{:x}", n).as_bytes());
+    assert_eq!(dec_digits(n, &mut [0; 20]), format!("{}", n).as_bytes());
+  }
+  // smoke test of the raw output path
+  crate::serial_print!("");
+  write_hex(0xdead_beef);
+  write_raw(b" ");
+  write_dec(u64::MAX);
+  write_raw(b" ");
+}
+
+#[test_case]
+fn test_putc_loopback() {
+  /// Modem control register: `DTR`, `RTS`, `OUT2` and the loopback bit
+  const COM1_MODEM_CONTROL: u16 = COM1 + 4;
+  const LOOPBACK: u8 = 1 << 4;
+
+  use crate::io::{inb, outb};
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    // keep other output off the wire meanwhile
+    let _serial = SERIAL1.lock();
+    let modem_control = unsafe { inb(COM1_MODEM_CONTROL) };
+    while read_byte().is_some() {}
+    unsafe { outb(COM1_MODEM_CONTROL, modem_control | LOOPBACK) };
+
+    // back to back, fits in the receive FIFO
+    let sent = b"\x00putc\xff\n\x7f";
+    for &byte in sent {
+      serial_putc!(byte);
+    }
+    let mut received = [0; 8];
+    for byte in received.iter_mut() {
+      *byte = read_byte_blocking();
+    }
+    assert_eq!(read_byte(), None);
+
+    unsafe { outb(COM1_MODEM_CONTROL, modem_control) };
+    assert_eq!(&received, sent);
+  });
+}
+
+#[test_case]
+fn test_tab_expansion() {
+  use alloc::string::String;
+  use fmt::Write;
+
+  let mut out = String::new();
+  let mut writer = TabExpander {
+    inner: &mut out,
+    column: 0,
+    expand: true,
+  };
+  write!(writer, "ab\tc\t\td\nabcd\te").unwrap();
+  assert_eq!(writer.column, 9);
+  assert_eq!(out, "ab      c               d\nabcd    e");
+
+  let mut out = String::new();
+  let mut writer = TabExpander {
+    inner: &mut out,
+    column: 0,
+    expand: false,
+  };
+  write!(writer, "ab\tc").unwrap();
+  assert_eq!(writer.column, 9);
+  assert_eq!(out, "ab\tc");
+
+  set_tab_expansion(true);
+  crate::serial_print!("\t");
+  set_tab_expansion(false);
+}
+
+#[test_case]
+fn test_baud_divisor() {
+  assert_eq!(baud_divisor(9600), Ok(12));
+  assert_eq!(baud_divisor(MAX_BAUD_RATE), Ok(1));
+  assert_eq!(baud_divisor(38400), Ok(3));
+  // clamped
+  assert_eq!(baud_divisor(1_000_000), Ok(1));
+  assert_eq!(baud_divisor(0), Ok(57600));
+  // between the divisors 2 and 3
+  assert_eq!(baud_divisor(48000), Err(UnsupportedBaudRate(48000)));
+
+  // 38400 is what `SerialPort::init` sets up
+  assert_eq!(set_baud_rate(38400), Ok(38400));
+}
+
+#[test_case]
+fn test_com2_output() {
+  assert_eq!(SERIAL2.lock().base(), COM2);
+  crate::serial2_print!("[com2] ");
+  x86_64::instructions::interrupts::without_interrupts(|| SERIAL2.lock().putc(b'\n'));
+}
@@ -0,0 +1,130 @@
+//! Editing of a single input line
+
+use crate::vga_buffer::{self, CursorStyle};
+use alloc::string::String;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+/// An input line with a cursor, in insert or overwrite mode
+///
+/// Only printable ASCII is accepted, so byte and character positions match.
+pub struct LineEditor {
+  line: String,
+  /// Byte position of the cursor in `line`
+  cursor: usize,
+  /// Typing replaces the character under the cursor (toggled by `Insert`)
+  overwrite: bool,
+  max_len: usize,
+}
+
+impl LineEditor {
+  /// Create an empty editor accepting up to `max_len` characters, in insert mode
+  pub fn new(max_len: usize) -> Self {
+    LineEditor {
+      line: String::new(),
+      cursor: 0,
+      overwrite: false,
+      max_len,
+    }
+  }
+
+  pub fn line(&self) -> &str {
+    &self.line
+  }
+
+  pub fn cursor(&self) -> usize {
+    self.cursor
+  }
+
+  pub fn is_overwrite(&self) -> bool {
+    self.overwrite
+  }
+
+  /// Apply `key`, returning the finished line on `Enter`
+  ///
+  /// Returns `Err(())` for a character which doesn't fit anymore.
+  #[allow(clippy::result_unit_err)]
+  pub fn handle_key(&mut self, key: DecodedKey) -> Result<Option<String>, ()> {
+    match key {
+      DecodedKey::RawKey(KeyCode::Return) | DecodedKey::Unicode('\n') => {
+        self.cursor = 0;
+        return Ok(Some(core::mem::take(&mut self.line)));
+      }
+      DecodedKey::RawKey(KeyCode::Backspace) | DecodedKey::Unicode('\x08') => {
+        if self.cursor > 0 {
+          self.cursor -= 1;
+          self.line.remove(self.cursor);
+        }
+      }
+      DecodedKey::RawKey(KeyCode::ArrowLeft) => self.cursor = self.cursor.saturating_sub(1),
+      DecodedKey::RawKey(KeyCode::ArrowRight) => {
+        self.cursor = (self.cursor + 1).min(self.line.len())
+      }
+      DecodedKey::RawKey(KeyCode::Insert) => self.set_overwrite(!self.overwrite),
+      DecodedKey::Unicode(c) if c.is_ascii() && !c.is_ascii_control() => self.type_char(c)?,
+      _ => {}
+    }
+    Ok(None)
+  }
+
+  /// Switch between overwrite and insert mode, updating the cursor style
+  pub fn set_overwrite(&mut self, overwrite: bool) {
+    self.overwrite = overwrite;
+    vga_buffer::set_cursor_style(match overwrite {
+      true => CursorStyle::Block,
+      false => CursorStyle::Underline,
+    });
+  }
+
+  fn type_char(&mut self, c: char) -> Result<(), ()> {
+    if self.overwrite && self.cursor < self.line.len() {
+      // replaced in place
+      self
+        .line
+        .replace_range(self.cursor..=self.cursor, c.encode_utf8(&mut [0; 4]));
+    } else if self.line.len() < self.max_len {
+      // inserted, or appended at the end of the line (in either mode)
+      self.line.insert(self.cursor, c);
+    } else {
+      return Err(());
+    }
+    self.cursor += 1;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test_line_editor {
+  use super::*;
+
+  fn type_keys(editor: &mut LineEditor, keys: impl IntoIterator<Item = DecodedKey>) {
+    for key in keys {
+      assert_eq!(editor.handle_key(key), Ok(None));
+    }
+  }
+
+  #[test_case]
+  fn overwrite_replaces_and_extends() {
+    let mut editor = LineEditor::new(8);
+    type_keys(&mut editor, "abcd".chars().map(DecodedKey::Unicode));
+    type_keys(&mut editor, [DecodedKey::RawKey(KeyCode::ArrowLeft); 3]);
+    type_keys(&mut editor, [DecodedKey::RawKey(KeyCode::Insert)]);
+    assert!(editor.is_overwrite());
+    assert_eq!(vga_buffer::cursor_style(), CursorStyle::Block);
+
+    type_keys(&mut editor, "XYZW".chars().map(DecodedKey::Unicode));
+    assert_eq!(editor.line(), "aXYZW");
+    assert_eq!(editor.cursor(), 5);
+
+    // back to inserting
+    type_keys(&mut editor, [DecodedKey::RawKey(KeyCode::Insert)]);
+    assert_eq!(vga_buffer::cursor_style(), CursorStyle::Underline);
+    type_keys(&mut editor, [DecodedKey::RawKey(KeyCode::ArrowLeft)]);
+    type_keys(&mut editor, "-".chars().map(DecodedKey::Unicode));
+    assert_eq!(editor.line(), "aXYZ-W");
+    assert_eq!(
+      editor.handle_key(DecodedKey::Unicode('\n')),
+      Ok(Some("aXYZ-W".into()))
+    );
+    assert_eq!(editor.line(), "");
+  }
+}
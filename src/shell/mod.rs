@@ -1,5 +1,345 @@
-use alloc::string::String;
+use crate::{
+  allocator::heap_stats,
+  print, print_with_color_ln, println,
+  task::keyboard::{self, KeyStream},
+  time,
+  vga_buffer::{BUFFER_HEIGHT, BUFFER_WIDTH, WRITER},
+};
+use alloc::{format, string::String, vec::Vec};
+use futures_util::{Stream, StreamExt};
+use line_editor::LineEditor;
+use pc_keyboard::DecodedKey;
+use x86_64::instructions::interrupts;
+
+pub mod line_editor;
+pub mod selftest;
+
+/// Shown in front of the input
+pub const PROMPT: &str = "> ";
+/// Row reserved for the prompt by `run_with_prompt`
+pub const PROMPT_ROW: usize = BUFFER_HEIGHT - 1;
+/// Longest input fitting on the prompt row
+const MAX_INPUT_LEN: usize = BUFFER_WIDTH - PROMPT.len() - 1;
 
 pub fn shell_entry() {
   let mut _input = String::new();
 }
+
+/// Run the shell: print the prompt, read a line, run it, until `exit`
+pub async fn run() {
+  run_on(&mut KeyStream::new()).await
+}
+
+/// Same as `run`, but reads the keys from `keys` (until it ends)
+pub async fn run_on(keys: &mut (impl Stream<Item = DecodedKey> + Unpin)) {
+  loop {
+    print!("{}", PROMPT);
+    let line = keyboard::read_line_from(keys).await;
+    if !run_command(&line) {
+      break;
+    }
+  }
+}
+
+/// Run the shell, keeping the prompt on the bottom row
+///
+/// Command output scrolls in the rows above, the prompt row stays fixed.
+pub async fn run_with_prompt() {
+  run_with_prompt_on(&mut KeyStream::new()).await
+}
+
+/// Same as `run_with_prompt`, but reads the keys from `keys` (until it ends)
+pub async fn run_with_prompt_on(keys: &mut (impl Stream<Item = DecodedKey> + Unpin)) {
+  interrupts::without_interrupts(|| WRITER.lock().set_scroll_region(0..=PROMPT_ROW - 1));
+
+  let mut editor = LineEditor::new(MAX_INPUT_LEN);
+  draw_prompt(&editor);
+  while let Some(key) = keys.next().await {
+    match editor.handle_key(key) {
+      Ok(Some(line)) => {
+        println!("{}{}", PROMPT, line);
+        if !run_command(&line) {
+          break;
+        }
+      }
+      Ok(None) => {}
+      // the line is full
+      Err(()) => crate::sound::bell(),
+    }
+    draw_prompt(&editor);
+  }
+
+  editor.set_overwrite(false);
+  interrupts::without_interrupts(|| {
+    draw_row("");
+    WRITER.lock().reset_scroll_region();
+  });
+}
+
+/// Repaint the prompt row with the line of `editor`
+///
+/// The cursor shows as `_` at the end of the line, and as inverted colors
+/// within it.
+fn draw_prompt(editor: &LineEditor) {
+  let line = editor.line();
+  if editor.cursor() == line.len() {
+    draw_row(&format!("{}{}_", PROMPT, line));
+    return;
+  }
+  draw_row(&format!("{}{}", PROMPT, line));
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let mut color_code = writer.color_code();
+    color_code.invert();
+    let at = editor.cursor();
+    writer.write_str_at(PROMPT_ROW, PROMPT.len() + at, &line[at..=at], color_code);
+  });
+}
+
+fn draw_row(line: &str) {
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let color_code = writer.color_code();
+    writer.write_str_at(PROMPT_ROW, 0, line, color_code);
+    writer.clear_range(PROMPT_ROW, line.len(), BUFFER_WIDTH);
+  });
+}
+
+/// Outcome of a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandResult {
+  Ok,
+  /// The command failed, with the message to show
+  Err(String),
+  /// The shell should stop
+  Exit,
+}
+
+/// A built-in command, called with the words following its name
+type Command = fn(&[&str]) -> CommandResult;
+
+/// Built-in commands, by name
+const COMMANDS: [(&str, Command); 9] = [
+  ("help", help),
+  ("clear", clear),
+  ("echo", echo),
+  ("uptime", uptime),
+  ("mem", mem),
+  ("selftest", run_selftest),
+  ("tasks", tasks),
+  ("version", version),
+  ("exit", |_| CommandResult::Exit),
+];
+
+fn help(_: &[&str]) -> CommandResult {
+  let names = COMMANDS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+  println!("commands: {}", names.join(", "));
+  CommandResult::Ok
+}
+
+fn clear(_: &[&str]) -> CommandResult {
+  interrupts::without_interrupts(|| WRITER.lock().clear_screen());
+  CommandResult::Ok
+}
+
+fn echo(args: &[&str]) -> CommandResult {
+  println!("{}", args.join(" "));
+  CommandResult::Ok
+}
+
+fn uptime(_: &[&str]) -> CommandResult {
+  println!("{} ticks ({} ms)", time::ticks(), time::uptime_ms());
+  CommandResult::Ok
+}
+
+fn mem(_: &[&str]) -> CommandResult {
+  let stats = heap_stats();
+  println!(
+    "heap: {} used, {} free, {} total (bytes)",
+    stats.used, stats.free, stats.total
+  );
+  CommandResult::Ok
+}
+
+fn run_selftest(_: &[&str]) -> CommandResult {
+  match selftest::run() {
+    true => CommandResult::Ok,
+    false => CommandResult::Err("some checks failed".into()),
+  }
+}
+
+fn version(_: &[&str]) -> CommandResult {
+  println!("{}", crate::version_info());
+  CommandResult::Ok
+}
+
+fn tasks(_: &[&str]) -> CommandResult {
+  use crate::task::executor;
+  println!(
+    "live: {}, spawned: {}, completed: {}",
+    executor::task_count(),
+    executor::spawned_total(),
+    executor::completed_total()
+  );
+  CommandResult::Ok
+}
+
+/// Run a command line: its first word names the command, the others are its arguments
+pub fn execute(line: &str) -> CommandResult {
+  let words = line.split_whitespace().collect::<Vec<_>>();
+  let Some((&name, args)) = words.split_first() else {
+    return CommandResult::Ok;
+  };
+  match COMMANDS.iter().find(|(command, _)| *command == name) {
+    Some((_, command)) => command(args),
+    None => CommandResult::Err(format!("unknown command: {}", name)),
+  }
+}
+
+/// Run a command line, reporting errors in red
+///
+/// Returns whether the shell should keep running.
+pub fn run_command(line: &str) -> bool {
+  match execute(line) {
+    CommandResult::Ok => true,
+    CommandResult::Err(message) => {
+      print_with_color_ln!(Red, "{}", message);
+      true
+    }
+    CommandResult::Exit => false,
+  }
+}
+
+#[cfg(test)]
+mod test_prompt {
+  use super::*;
+  use crate::task::{executor::Executor, Task};
+  use alloc::{string::String, sync::Arc, vec::Vec};
+  use core::task::Poll;
+  use futures_util::stream;
+  use spin::Mutex;
+
+  /// Run the prompt over `keys`, returning the screen once every key has been handled
+  fn screen_after(keys: &str) -> Vec<String> {
+    screen_after_keys(keys.chars().map(DecodedKey::Unicode).collect())
+  }
+
+  fn screen_after_keys(keys: Vec<DecodedKey>) -> Vec<String> {
+    let screen = Arc::new(Mutex::new(Vec::new()));
+    let snapshot = screen.clone();
+    let mut keys = stream::iter(keys).chain(stream::poll_fn(move |_| {
+      *snapshot.lock() = interrupts::without_interrupts(|| WRITER.lock().rows());
+      Poll::Ready(None)
+    }));
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+      run_with_prompt_on(&mut keys).await;
+    }));
+    executor.run_until_all_task_finished();
+    let rows = screen.lock().clone();
+    rows
+  }
+
+  #[test_case]
+  fn output_scrolls_above_fixed_prompt() {
+    let rows = screen_after("echo hi\nab");
+    assert!(rows[PROMPT_ROW].starts_with("> ab_ "));
+    assert!(rows[PROMPT_ROW - 1].starts_with("hi "));
+    assert!(rows[PROMPT_ROW - 2].starts_with("> echo hi "));
+  }
+
+  #[test_case]
+  fn exit_stops_the_shell() {
+    use core::{future::Future, pin::pin, task::Context};
+    use futures_util::task::noop_waker_ref;
+
+    let mut keys = stream::iter("exit\nx".chars().map(DecodedKey::Unicode));
+    {
+      let shell = pin!(run_with_prompt_on(&mut keys));
+      let mut cx = Context::from_waker(noop_waker_ref());
+      assert!(shell.poll(&mut cx).is_ready());
+    }
+    // the key after `exit` is never read
+    assert_eq!(keys.size_hint(), (1, Some(1)));
+  }
+
+  #[test_case]
+  fn errors_are_printed_in_red() {
+    use crate::vga_buffer::Color;
+
+    assert!(run_command("no_such_command"));
+    let screen = interrupts::without_interrupts(|| WRITER.lock().snapshot_with_colors());
+    let row = &screen[BUFFER_HEIGHT - 2];
+    let text = row.iter().map(|&(c, _)| c).collect::<String>();
+    assert!(text.starts_with("unknown command: no_such_command "));
+    assert!(row[..text.trim_end().len()]
+      .iter()
+      .all(|&(_, color_code)| color_code.fg() == Color::Red));
+  }
+
+  #[test_case]
+  fn insert_toggles_overwrite() {
+    use pc_keyboard::KeyCode;
+
+    let mut keys = "echo abcd"
+      .chars()
+      .map(DecodedKey::Unicode)
+      .collect::<Vec<_>>();
+    keys.extend([DecodedKey::RawKey(KeyCode::ArrowLeft); 3]);
+    keys.push(DecodedKey::RawKey(KeyCode::Insert));
+    keys.extend("XYZW".chars().map(DecodedKey::Unicode));
+    let rows = screen_after_keys(keys.clone());
+    assert!(rows[PROMPT_ROW].starts_with("> echo aXYZW_ "));
+
+    keys.push(DecodedKey::Unicode('\n'));
+    let rows = screen_after_keys(keys);
+    assert!(rows[PROMPT_ROW - 1].starts_with("aXYZW "));
+  }
+
+  #[test_case]
+  fn backspace_stops_at_prompt() {
+    let rows = screen_after("\x08\x08x\x08\x08y");
+    assert!(rows[PROMPT_ROW].starts_with("> y_ "));
+  }
+}
+
+#[cfg(test)]
+mod test_commands {
+  use super::*;
+  use core::{future::Future, pin::pin, task::Context};
+  use futures_util::{stream, task::noop_waker_ref};
+
+  #[test_case]
+  fn commands_are_looked_up_by_first_word() {
+    for (name, _) in COMMANDS {
+      if !["clear", "selftest", "exit"].contains(&name) {
+        assert_eq!(execute(name), CommandResult::Ok);
+      }
+    }
+    assert_eq!(execute("  exit now "), CommandResult::Exit);
+    assert_eq!(execute("   "), CommandResult::Ok);
+    assert_eq!(
+      execute("mem2"),
+      CommandResult::Err("unknown command: mem2".into())
+    );
+  }
+
+  #[test_case]
+  fn echo_joins_its_arguments() {
+    echo(&["a", "b"]);
+    let rows = interrupts::without_interrupts(|| WRITER.lock().rows());
+    assert!(rows[BUFFER_HEIGHT - 2].starts_with("a b "));
+  }
+
+  #[test_case]
+  fn run_stops_at_exit() {
+    let mut keys = stream::iter("echo hi\nexit\nx".chars().map(DecodedKey::Unicode));
+    {
+      let shell = pin!(run_on(&mut keys));
+      let mut cx = Context::from_waker(noop_waker_ref());
+      assert!(shell.poll(&mut cx).is_ready());
+    }
+    assert_eq!(keys.size_hint(), (1, Some(1)));
+  }
+}
@@ -0,0 +1,132 @@
+use crate::{interrupts, print, print_with_color_ln, println, task::keyboard, time, vga_buffer};
+use alloc::{
+  alloc::{alloc, dealloc, Layout},
+  vec::Vec,
+};
+
+/// A runtime check, `Err` carries the reason of the failure
+type Check = fn() -> Result<(), &'static str>;
+
+const CHECKS: [(&str, Check); 5] = [
+  ("vga round-trip", check_vga),
+  ("allocator", check_allocator),
+  ("timer", check_timer),
+  ("keyboard queue", check_keyboard),
+  ("serial", check_serial),
+];
+
+/// Run every check, printing a colored report and a summary
+///
+/// Returns whether all checks passed.
+pub fn run() -> bool {
+  let mut passed = 0;
+  for (name, check) in CHECKS {
+    print!("{:<16}", name);
+    match check() {
+      Ok(()) => {
+        passed += 1;
+        print_with_color_ln!(Green, "[ok]");
+      }
+      Err(reason) => print_with_color_ln!(Red, "[failed] {}", reason),
+    }
+  }
+  println!("{}/{} checks passed", passed, CHECKS.len());
+  passed == CHECKS.len()
+}
+
+fn check_vga() -> Result<(), &'static str> {
+  vga_buffer::self_test()
+    .then_some(())
+    .ok_or("read back a different cell")
+}
+
+fn check_allocator() -> Result<(), &'static str> {
+  for shift in 0..=12 {
+    let layout = Layout::from_size_align(1 << shift, 8).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    if ptr.is_null() {
+      return Err("allocation failed");
+    }
+    unsafe {
+      ptr.write_bytes(0xa5, layout.size());
+      let last = ptr.add(layout.size() - 1).read();
+      dealloc(ptr, layout);
+      if last != 0xa5 {
+        return Err("allocated memory is not writable");
+      }
+    }
+  }
+  Ok(())
+}
+
+fn check_timer() -> Result<(), &'static str> {
+  if !interrupts::are_enabled() {
+    return Err("interrupts are disabled");
+  }
+  let start = time::ticks();
+  // any interrupt ends a `hlt`, so give the timer a few chances
+  for _ in 0..1000 {
+    x86_64::instructions::hlt();
+    if time::ticks() > start {
+      return Ok(());
+    }
+  }
+  Err("no timer tick")
+}
+
+fn check_keyboard() -> Result<(), &'static str> {
+  // an unused scancode, ignored by the decoder if it reaches a reader
+  const MARKER: u8 = 0x00;
+
+  let mut scancodes = keyboard::scancode_stream();
+  // `drain` registers no waker, and the reader's waker is woken again when
+  // the real key presses (queued around the marker) are handed back
+  let found = x86_64::instructions::interrupts::without_interrupts(|| {
+    keyboard::add_scancode(MARKER);
+    let mut queued = scancodes.drain().collect::<Vec<_>>();
+    let marker = queued.iter().position(|&scancode| scancode == MARKER);
+    if let Some(index) = marker {
+      queued.remove(index);
+    }
+    keyboard::requeue_scancodes(queued);
+    marker.is_some()
+  });
+  match found {
+    true => Ok(()),
+    false => Err("injected scancode was lost"),
+  }
+}
+
+fn check_serial() -> Result<(), &'static str> {
+  const COM1_SCRATCH: u16 = 0x3F8 + 7;
+
+  for value in [0x5a, 0xa5] {
+    // SAFETY: the scratch register has no effect on the uart
+    let read_back = unsafe {
+      crate::io::outb(COM1_SCRATCH, value);
+      crate::io::inb(COM1_SCRATCH)
+    };
+    if read_back != value {
+      return Err("no uart at COM1");
+    }
+  }
+  Ok(())
+}
+
+#[test_case]
+fn test_selftest_passes() {
+  assert!(run());
+}
+
+#[test_case]
+fn test_keyboard_check_keeps_queued_keys() {
+  let mut scancodes = keyboard::scancode_stream();
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    scancodes.drain().for_each(drop);
+    // `a` pressed and released
+    keyboard::add_scancode(0x1E);
+    keyboard::add_scancode(0x9E);
+    assert_eq!(check_keyboard(), Ok(()));
+    assert_eq!(scancodes.drain().collect::<Vec<_>>(), [0x1E, 0x9E]);
+  });
+}
@@ -0,0 +1,93 @@
+//! PC speaker, driven by channel 2 of the PIT
+//!
+//! Port `0x61` (keyboard controller port B) gates the speaker:
+//!
+//! - bit 0 => connect the PIT channel 2 `GATE` input (start the square wave)
+//! - bit 1 => connect the channel 2 output to the speaker
+//!
+//! Both bits must be set to hear anything, clearing them silences the speaker.
+//! The remaining bits belong to other devices and are always preserved.
+
+use crate::{io, task::timer, time};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+
+/// PIT mode/command register
+const PIT_COMMAND_PORT: u16 = 0x43;
+/// PIT channel 2 data port
+const PIT_CHANNEL_2_PORT: u16 = 0x42;
+/// Channel 2, access lo/hi byte, mode 3 (square wave), binary
+const PIT_CHANNEL_2_SQUARE_WAVE: u8 = 0b1011_0110;
+/// Keyboard controller port B
+const SPEAKER_PORT: u16 = 0x61;
+/// `GATE` (bit 0) and speaker data (bit 1) of port B
+const SPEAKER_ENABLE_BITS: u8 = 0b11;
+
+/// Frequency of the `bell`
+const BELL_FREQUENCY: u32 = 880;
+/// Duration of the `bell` (in ticks)
+const BELL_TICKS: u64 = 2;
+
+/// Tick at which the running `bell` should stop (`0` => no bell)
+static BELL_DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+/// PIT divisor producing `freq_hz` (clamped into the 16-bit range)
+pub fn divisor_for(freq_hz: u32) -> u16 {
+  (time::PIT_FREQUENCY / freq_hz.max(1)).clamp(1, u16::MAX as u32) as u16
+}
+
+/// Start beeping at `freq_hz` (until `stop`)
+pub fn beep(freq_hz: u32) {
+  let [low, high] = divisor_for(freq_hz).to_le_bytes();
+  // SAFETY: only PIT channel 2 and the speaker bits of port `0x61` change
+  interrupts::without_interrupts(|| unsafe {
+    io::outb(PIT_COMMAND_PORT, PIT_CHANNEL_2_SQUARE_WAVE);
+    io::outb(PIT_CHANNEL_2_PORT, low);
+    io::outb(PIT_CHANNEL_2_PORT, high);
+    io::outb(SPEAKER_PORT, io::inb(SPEAKER_PORT) | SPEAKER_ENABLE_BITS);
+  });
+}
+
+/// Silence the speaker
+pub fn stop() {
+  // SAFETY: only the speaker bits of port `0x61` change
+  interrupts::without_interrupts(|| unsafe {
+    io::outb(SPEAKER_PORT, io::inb(SPEAKER_PORT) & !SPEAKER_ENABLE_BITS);
+  });
+}
+
+/// Beep at `freq_hz` for `ticks` timer ticks
+pub async fn beep_for(freq_hz: u32, ticks: u64) {
+  beep(freq_hz);
+  timer::sleep(ticks).await;
+  stop();
+}
+
+/// Start a short beep, which is stopped by the timer interrupt
+///
+/// Unlike `beep_for`, this never blocks, so it can be used anywhere.
+pub fn bell() {
+  beep(BELL_FREQUENCY);
+  BELL_DEADLINE.store(time::ticks() + BELL_TICKS, Ordering::SeqCst);
+}
+
+/// Called by the timer interrupt handler
+pub(crate) fn on_tick(now: u64) {
+  let deadline = BELL_DEADLINE.load(Ordering::SeqCst);
+  if deadline != 0 && now >= deadline {
+    BELL_DEADLINE.store(0, Ordering::SeqCst);
+    stop();
+  }
+}
+
+#[test_case]
+fn test_beep_and_stop() {
+  assert_eq!(divisor_for(1000), 1193);
+  assert_eq!(divisor_for(0), u16::MAX);
+
+  let speaker_bits = || unsafe { io::inb(SPEAKER_PORT) } & SPEAKER_ENABLE_BITS;
+  beep(1000);
+  assert_eq!(speaker_bits(), SPEAKER_ENABLE_BITS);
+  stop();
+  assert_eq!(speaker_bits(), 0);
+}
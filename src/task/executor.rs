@@ -1,13 +1,79 @@
 use super::{Task, TaskId};
 use alloc::task::Wake;
-use alloc::{collections::BTreeMap, sync::Arc};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
 
+/// Tasks held by all `Executor`s
+static TASK_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Tasks ever spawned on an `Executor`
+static SPAWNED_TOTAL: AtomicU64 = AtomicU64::new(0);
+/// Tasks ever run to completion by an `Executor`
+static COMPLETED_TOTAL: AtomicU64 = AtomicU64::new(0);
+/// Wakeups dropped because the task queue was full
+static DROPPED_WAKES: AtomicU64 = AtomicU64::new(0);
+
+/// Get the number of live tasks, over all `Executor`s
+pub fn task_count() -> usize {
+  TASK_COUNT.load(Ordering::Relaxed)
+}
+
+/// Get the number of tasks spawned so far, over all `Executor`s
+pub fn spawned_total() -> u64 {
+  SPAWNED_TOTAL.load(Ordering::Relaxed)
+}
+
+/// Get the number of tasks completed so far, over all `Executor`s
+///
+/// Tasks dropped unfinished (with their `Executor`) don't count.
+pub fn completed_total() -> u64 {
+  COMPLETED_TOTAL.load(Ordering::Relaxed)
+}
+
+/// Get the number of wakeups dropped so far because a task queue was full
+pub fn dropped_wakes() -> u64 {
+  DROPPED_WAKES.load(Ordering::Relaxed)
+}
+
+/// Why `Executor::run_until_idle` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Idle {
+  /// Every spawned task has completed
+  NoTasks,
+  /// This many tasks are left, all waiting on a wakeup (e.g. a timer or a key press)
+  Blocked(usize),
+}
+
+/// A future handed over by a `Spawner`
+type SpawnedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Handle spawning tasks on an `Executor`, also while it runs (e.g. from a task)
+///
+/// Spawned tasks are picked up once the executor has polled every task
+/// which was ready, so they run after the currently ready ones.
+#[derive(Clone)]
+pub struct Spawner {
+  spawn_queue: Arc<ArrayQueue<SpawnedFuture>>,
+}
+
+impl Spawner {
+  pub fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
+    self
+      .spawn_queue
+      .push(Box::pin(future))
+      .unwrap_or_else(|_| panic!("spawn_queue full!\n"));
+  }
+}
+
 pub struct Executor {
   tasks: BTreeMap<TaskId, Task>,
   task_queue: Arc<ArrayQueue<TaskId>>,
   waker_cache: BTreeMap<TaskId, Waker>,
+  /// Tasks from `Spawner`s, not spawned yet
+  spawn_queue: Arc<ArrayQueue<SpawnedFuture>>,
 }
 
 impl Executor {
@@ -16,46 +82,119 @@ impl Executor {
       tasks: BTreeMap::new(),
       task_queue: Arc::new(ArrayQueue::new(100)),
       waker_cache: BTreeMap::new(),
+      spawn_queue: Arc::new(ArrayQueue::new(100)),
+    }
+  }
+
+  /// Get a `Spawner` of this executor
+  pub fn spawner(&self) -> Spawner {
+    Spawner {
+      spawn_queue: self.spawn_queue.clone(),
     }
   }
 
   pub fn spawn(&mut self, task: Task) {
     let task_id = task.id;
+    self.insert_task(task);
+    self.task_queue.push(task_id).expect("queue full!\n");
+  }
+
+  /// Spawn `task` and poll it right away, up to its first `await` point
+  ///
+  /// Useful for startup ordering (e.g. printing a banner before other tasks
+  /// consume input): the task runs before any already-spawned task gets
+  /// polled again. There is no priority scheduling, so afterwards it is
+  /// scheduled like any other task.
+  pub fn spawn_first(&mut self, task: Task) {
+    let task_id = task.id;
+    self.insert_task(task);
+    Self::poll_task(
+      &mut self.tasks,
+      &mut self.waker_cache,
+      &self.task_queue,
+      task_id,
+    );
+  }
+
+  fn insert_task(&mut self, task: Task) {
     if self.tasks.insert(task.id, task).is_some() {
       panic!("task with same ID already in tasks!\n");
     }
-    self.task_queue.push(task_id).expect("queue full!\n");
+    TASK_COUNT.fetch_add(1, Ordering::Relaxed);
+    SPAWNED_TOTAL.fetch_add(1, Ordering::Relaxed);
   }
 
-  fn run_ready_tasks(&mut self) {
-    // destructure `self` to avoid borrow checker errors
-    let Self {
-      tasks,
-      task_queue,
-      waker_cache,
-    } = self;
-
-    while let Some(task_id) = task_queue.pop() {
-      let task = match tasks.get_mut(&task_id) {
-        Some(task) => task,
-        None => continue, // task no longer exists
-      };
-      let waker = waker_cache
-        .entry(task_id)
-        .or_insert_with(|| TaskWaker::new_waker(task_id, task_queue.clone()));
-      let mut context = Context::from_waker(waker);
-      match task.poll(&mut context) {
-        Poll::Ready(()) => {
-          // task done -> remove it and its cached waker
-          tasks.remove(&task_id);
-          waker_cache.remove(&task_id);
-        }
-        Poll::Pending => {}
+  /// Poll the ready tasks, pass by pass, until none is left
+  ///
+  /// Each pass polls the tasks ready at its start, then picks up the tasks
+  /// from `Spawner`s: tasks woken during a pass (e.g. by `yield_now`) run
+  /// after those, so they can't starve them.
+  pub(crate) fn run_ready_tasks(&mut self) {
+    loop {
+      // destructure `self` to avoid borrow checker errors
+      let Self {
+        tasks,
+        task_queue,
+        waker_cache,
+        ..
+      } = self;
+
+      for _ in 0..task_queue.len() {
+        let Some(task_id) = task_queue.pop() else {
+          break;
+        };
+        Self::poll_task(tasks, waker_cache, task_queue, task_id);
+      }
+      let spawned = self.spawn_queued();
+      if !spawned && self.task_queue.is_empty() {
+        break;
+      }
+    }
+  }
+
+  /// Spawn the tasks queued by `Spawner`s, returning whether there were any
+  fn spawn_queued(&mut self) -> bool {
+    let mut spawned = false;
+    while let Some(future) = self.spawn_queue.pop() {
+      self.spawn(Task::from_pinned(future));
+      spawned = true;
+    }
+    spawned
+  }
+
+  fn poll_task(
+    tasks: &mut BTreeMap<TaskId, Task>,
+    waker_cache: &mut BTreeMap<TaskId, Waker>,
+    task_queue: &Arc<ArrayQueue<TaskId>>,
+    task_id: TaskId,
+  ) {
+    let task = match tasks.get_mut(&task_id) {
+      Some(task) => task,
+      None => return, // task no longer exists
+    };
+    let waker = waker_cache
+      .entry(task_id)
+      .or_insert_with(|| TaskWaker::new_waker(task_id, task_queue.clone()));
+    let mut context = Context::from_waker(waker);
+    match task.poll(&mut context) {
+      Poll::Ready(()) => {
+        // task done -> remove it and its cached waker
+        tasks.remove(&task_id);
+        waker_cache.remove(&task_id);
+        TASK_COUNT.fetch_sub(1, Ordering::Relaxed);
+        COMPLETED_TOTAL.fetch_add(1, Ordering::Relaxed);
       }
+      Poll::Pending => {}
     }
   }
 }
 
+impl Drop for Executor {
+  fn drop(&mut self) {
+    TASK_COUNT.fetch_sub(self.tasks.len(), Ordering::Relaxed);
+  }
+}
+
 impl Default for Executor {
   fn default() -> Self {
     Self::new()
@@ -70,19 +209,44 @@ impl Executor {
     }
   }
 
+  /// Run the ready tasks until none is left
+  ///
+  /// Never waits: tasks blocked on wakeups (a timer, a key press) are left
+  /// behind, so it also returns while such tasks never finish.
   pub fn run_until_all_task_finished(&mut self) {
-    while !self.task_queue.is_empty() {
+    while !self.is_idle() {
       self.run_ready_tasks();
     }
   }
 
+  /// Whether no task is ready to run (tasks may still be waiting on wakeups)
+  pub fn is_idle(&self) -> bool {
+    self.task_queue.is_empty() && self.spawn_queue.is_empty()
+  }
+
+  /// Run the ready tasks until none is left, without waiting for wakeups
+  ///
+  /// Like `run_until_all_task_finished`, but reports whether tasks are
+  /// left, blocked on external events.
+  pub fn run_until_idle(&mut self) -> Idle {
+    loop {
+      self.run_ready_tasks();
+      if self.tasks.is_empty() {
+        return Idle::NoTasks;
+      }
+      if self.is_idle() {
+        return Idle::Blocked(self.tasks.len());
+      }
+    }
+  }
+
   fn sleep_if_idle(&self) {
     use x86_64::instructions::interrupts::{self, enable_and_hlt};
 
     // to avoid race conditions, disable all interruptions temporarily
     interrupts::disable();
 
-    if self.task_queue.is_empty() {
+    if self.is_idle() {
       // enable interruptions again, hlt cpu
       enable_and_hlt();
     } else {
@@ -105,11 +269,14 @@ impl TaskWaker {
     }))
   }
 
+  /// Queue the task, dropping (and counting) the wakeup if the queue is full
+  ///
+  /// Never panics, as wakers are also called from interrupt handlers (e.g.
+  /// `timer::wake_expired`).
   fn wake_task(&self) {
-    self
-      .task_queue
-      .push(self.task_id)
-      .expect("task_queue full!\n");
+    if self.task_queue.push(self.task_id).is_err() {
+      DROPPED_WAKES.fetch_add(1, Ordering::Relaxed);
+    }
   }
 }
 
@@ -122,3 +289,165 @@ impl Wake for TaskWaker {
     self.wake_task();
   }
 }
+
+#[cfg(test)]
+mod test_idle {
+  use super::*;
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test_case]
+  fn completed_tasks_leave_no_tasks() {
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+    let mut executor = Executor::new();
+    for _ in 0..3 {
+      executor.spawn(Task::new(async {
+        DONE.fetch_add(1, Ordering::SeqCst);
+      }));
+    }
+    assert!(!executor.is_idle());
+    assert_eq!(executor.run_until_idle(), Idle::NoTasks);
+    assert!(executor.is_idle());
+    assert_eq!(DONE.load(Ordering::SeqCst), 3);
+  }
+
+  #[test_case]
+  fn forever_blocked_task_is_reported() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {}));
+    executor.spawn(Task::new(core::future::pending()));
+    assert_eq!(executor.run_until_idle(), Idle::Blocked(1));
+  }
+}
+
+#[cfg(test)]
+mod test_spawn_first {
+  use super::*;
+  use core::sync::atomic::{AtomicBool, Ordering};
+
+  #[test_case]
+  fn first_task_runs_before_others() {
+    static BANNER_PRINTED: AtomicBool = AtomicBool::new(false);
+    static SAW_BANNER: AtomicBool = AtomicBool::new(false);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+      SAW_BANNER.store(BANNER_PRINTED.load(Ordering::SeqCst), Ordering::SeqCst);
+    }));
+    executor.spawn_first(Task::new(async {
+      BANNER_PRINTED.store(true, Ordering::SeqCst);
+      core::future::pending::<()>().await;
+    }));
+    assert_eq!(executor.run_until_idle(), Idle::Blocked(1));
+    assert!(SAW_BANNER.load(Ordering::SeqCst));
+  }
+}
+
+#[cfg(test)]
+mod test_spawner {
+  use super::*;
+  use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+  #[test_case]
+  fn task_spawns_a_child() {
+    static CHILD_RAN: AtomicBool = AtomicBool::new(false);
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+      spawner.spawn(async {
+        CHILD_RAN.store(true, Ordering::SeqCst);
+      });
+    }));
+    executor.run_until_all_task_finished();
+    assert!(CHILD_RAN.load(Ordering::SeqCst));
+  }
+
+  #[test_case]
+  fn spawned_tasks_run_after_ready_ones() {
+    static ORDER: AtomicUsize = AtomicUsize::new(0);
+    static CHILD_POSITION: AtomicUsize = AtomicUsize::new(0);
+    static SIBLING_POSITION: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+      ORDER.fetch_add(1, Ordering::SeqCst);
+      spawner.spawn(async {
+        CHILD_POSITION.store(ORDER.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+      });
+    }));
+    executor.spawn(Task::new(async {
+      SIBLING_POSITION.store(ORDER.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+    }));
+    assert_eq!(executor.run_until_idle(), Idle::NoTasks);
+    assert_eq!(SIBLING_POSITION.load(Ordering::SeqCst), 1);
+    assert_eq!(CHILD_POSITION.load(Ordering::SeqCst), 2);
+  }
+
+  #[test_case]
+  fn yielding_task_does_not_starve_spawned_ones() {
+    static CHILD_RAN: AtomicBool = AtomicBool::new(false);
+    static YIELDS: AtomicUsize = AtomicUsize::new(0);
+
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawn(Task::new(async move {
+      spawner.spawn(async {
+        CHILD_RAN.store(true, Ordering::SeqCst);
+      });
+      while !CHILD_RAN.load(Ordering::SeqCst) {
+        YIELDS.fetch_add(1, Ordering::SeqCst);
+        crate::task::yield_now().await;
+      }
+    }));
+    assert_eq!(executor.run_until_idle(), Idle::NoTasks);
+    // the child runs in the pass after the one spawning it
+    assert_eq!(YIELDS.load(Ordering::SeqCst), 2);
+  }
+}
+
+#[cfg(test)]
+mod test_wake {
+  use super::*;
+
+  #[test_case]
+  fn full_queue_drops_the_wakeup() {
+    let task_queue = Arc::new(ArrayQueue::new(1));
+    let waker = TaskWaker::new_waker(TaskId::new(), task_queue.clone());
+    let dropped_before = dropped_wakes();
+    waker.wake_by_ref();
+    waker.wake_by_ref();
+    assert_eq!(task_queue.len(), 1);
+    assert_eq!(dropped_wakes(), dropped_before + 1);
+  }
+}
+
+#[cfg(test)]
+mod test_task_count {
+  use super::*;
+
+  #[test_case]
+  fn counters_follow_spawn_and_completion() {
+    const N: usize = 5;
+    let (count_before, spawned_before, completed_before) =
+      (task_count(), spawned_total(), completed_total());
+
+    let mut executor = Executor::new();
+    for _ in 0..N {
+      executor.spawn(Task::new(async {}));
+    }
+    assert_eq!(task_count(), count_before + N);
+    assert_eq!(spawned_total(), spawned_before + N as u64);
+    executor.run_until_all_task_finished();
+    assert_eq!(task_count(), count_before);
+    assert_eq!(completed_total(), completed_before + N as u64);
+
+    // unfinished tasks stop counting once dropped
+    executor.spawn(Task::new(core::future::pending()));
+    executor.run_until_idle();
+    assert_eq!(task_count(), count_before + 1);
+    drop(executor);
+    assert_eq!(task_count(), count_before);
+    assert_eq!(completed_total(), completed_before + N as u64);
+  }
+}
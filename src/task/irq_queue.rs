@@ -0,0 +1,115 @@
+use crate::eprintln;
+use conquer_once::spin::OnceCell;
+use core::{
+  pin::Pin,
+  task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{stream::Stream, task::AtomicWaker};
+
+/// Items an `IrqQueue` holds before dropping new input
+const CAPACITY: usize = 100;
+
+/// Queue filled by an interrupt handler, drained by an `IrqStream`
+///
+/// The queue is allocated by the first `stream`, input arriving before that
+/// is dropped.
+pub struct IrqQueue<T> {
+  /// Used in the warning printed when the queue is full
+  name: &'static str,
+  queue: OnceCell<ArrayQueue<T>>,
+  waker: AtomicWaker,
+}
+
+impl<T: 'static> IrqQueue<T> {
+  pub const fn new(name: &'static str) -> Self {
+    Self {
+      name,
+      queue: OnceCell::uninit(),
+      waker: AtomicWaker::new(),
+    }
+  }
+
+  /// Called by the interrupt handler
+  ///
+  /// Must not block or allocate.
+  pub fn push(&self, item: T) {
+    if let Ok(queue) = self.queue.try_get() {
+      if queue.push(item).is_err() {
+        eprintln!("WARNING: `{}` full, dropping input", self.name);
+      } else {
+        self.waker.wake();
+      }
+    }
+  }
+
+  /// Create a stream over this queue
+  ///
+  /// All streams share the same queue (and waker), so only one task
+  /// should consume it at a time.
+  pub fn stream(&'static self) -> IrqStream<T> {
+    let _ = self.queue.try_init_once(|| ArrayQueue::new(CAPACITY));
+    IrqStream { source: self }
+  }
+}
+
+pub struct IrqStream<T: 'static> {
+  source: &'static IrqQueue<T>,
+}
+
+impl<T> IrqStream<T> {
+  fn queue(&self) -> &'static ArrayQueue<T> {
+    self
+      .source
+      .queue
+      .try_get()
+      .expect("irq queue not initialized!\n")
+  }
+
+  /// Take every item queued right now, without waiting for more
+  ///
+  /// Lets a consumer handle a burst (e.g. fast typing, key repeat) in one go,
+  /// instead of going through `poll_next` for each item.
+  pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+    let queue = self.queue();
+    core::iter::from_fn(|| queue.pop())
+  }
+}
+
+impl<T> Stream for IrqStream<T> {
+  type Item = T;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+    let queue = self.queue();
+
+    // fast path
+    if let Some(item) = queue.pop() {
+      return Poll::Ready(Some(item));
+    }
+
+    self.source.waker.register(cx.waker());
+    match queue.pop() {
+      Some(item) => {
+        self.source.waker.take();
+        Poll::Ready(Some(item))
+      }
+      None => Poll::Pending,
+    }
+  }
+}
+
+#[test_case]
+fn test_input_before_the_first_stream_is_dropped() {
+  use futures_util::{task::noop_waker_ref, StreamExt};
+  static QUEUE: IrqQueue<u32> = IrqQueue::new("test queue");
+
+  QUEUE.push(1);
+  let mut stream = QUEUE.stream();
+  let mut cx = Context::from_waker(noop_waker_ref());
+  assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Pending);
+
+  QUEUE.push(2);
+  QUEUE.push(3);
+  assert_eq!(stream.poll_next_unpin(&mut cx), Poll::Ready(Some(2)));
+  assert_eq!(stream.drain().collect::<alloc::vec::Vec<_>>(), [3]);
+}
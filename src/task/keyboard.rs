@@ -1,112 +1,665 @@
-use crate::{eprintln, print, vga_buffer::WRITER};
-use conquer_once::spin::OnceCell;
+use super::irq_queue::{IrqQueue, IrqStream};
+use crate::{io, print, println, serial_println, vga_buffer::WRITER};
+use alloc::string::String;
 use core::{
   pin::Pin,
+  sync::atomic::{AtomicBool, AtomicU8, Ordering},
   task::{Context, Poll},
 };
-use crossbeam_queue::ArrayQueue;
-use futures_util::{
-  stream::{Stream, StreamExt},
-  task::AtomicWaker,
-};
-use lazy_static::lazy_static;
+use futures_util::stream::{Stream, StreamExt};
 use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
 
-lazy_static! {
-  static ref SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static SCANCODE_QUEUE: IrqQueue<u8> = IrqQueue::new("scancode queue");
+
+/// Modifier keys held (or toggled on) right now, see `current_modifiers`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+  pub shift: bool,
+  pub ctrl: bool,
+  pub alt: bool,
+  pub caps_lock: bool,
+}
+
+/// Bits of `MODIFIER_KEYS`, left and right keys apart so releasing one of
+/// them doesn't clear the modifier while the other is still held
+const LEFT_SHIFT: u8 = 1 << 0;
+const RIGHT_SHIFT: u8 = 1 << 1;
+const LEFT_CTRL: u8 = 1 << 2;
+const RIGHT_CTRL: u8 = 1 << 3;
+const LEFT_ALT: u8 = 1 << 4;
+const RIGHT_ALT: u8 = 1 << 5;
+const CAPS_LOCK: u8 = 1 << 6;
+
+/// Scancode (set 1) prefixing the extended keys
+const EXTENDED_PREFIX: u8 = 0xE0;
+/// Bit of a (set 1) scancode marking a key release
+const RELEASE: u8 = 0x80;
+
+static MODIFIER_KEYS: AtomicU8 = AtomicU8::new(0);
+/// Whether the previous scancode was `EXTENDED_PREFIX`
+static EXTENDED: AtomicBool = AtomicBool::new(false);
+
+/// Update `MODIFIER_KEYS` with a (set 1) scancode
+fn track_modifiers(scancode: u8) {
+  if scancode == EXTENDED_PREFIX {
+    EXTENDED.store(true, Ordering::Relaxed);
+    return;
+  }
+  let extended = EXTENDED.swap(false, Ordering::Relaxed);
+  let released = scancode & RELEASE != 0;
+  let bit = match (extended, scancode & !RELEASE) {
+    (false, 0x2A) => LEFT_SHIFT,
+    (false, 0x36) => RIGHT_SHIFT,
+    (false, 0x1D) => LEFT_CTRL,
+    (true, 0x1D) => RIGHT_CTRL,
+    (false, 0x38) => LEFT_ALT,
+    (true, 0x38) => RIGHT_ALT,
+    // a toggle: flips on press, the release does nothing
+    (false, 0x3A) => {
+      if !released {
+        MODIFIER_KEYS.fetch_xor(CAPS_LOCK, Ordering::Relaxed);
+      }
+      return;
+    }
+    // includes the fake shifts around some extended keys (`E0 2A`, ...)
+    _ => return,
+  };
+  match released {
+    true => MODIFIER_KEYS.fetch_and(!bit, Ordering::Relaxed),
+    false => MODIFIER_KEYS.fetch_or(bit, Ordering::Relaxed),
+  };
 }
-lazy_static! {
-  static ref WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Get the modifiers as of the last scancode received
+pub fn current_modifiers() -> Modifiers {
+  let keys = MODIFIER_KEYS.load(Ordering::Relaxed);
+  Modifiers {
+    shift: keys & (LEFT_SHIFT | RIGHT_SHIFT) != 0,
+    ctrl: keys & (LEFT_CTRL | RIGHT_CTRL) != 0,
+    alt: keys & (LEFT_ALT | RIGHT_ALT) != 0,
+    caps_lock: keys & CAPS_LOCK != 0,
+  }
 }
 
 /// Called by the keyboard interrupt handler
 ///
 /// Must not block or allocate.
 pub fn add_scancode(scancode: u8) {
-  if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-    if queue.push(scancode).is_err() {
-      eprintln!("WARNING: `scancode queue` full, dropping keyboard input");
+  track_modifiers(scancode);
+  SCANCODE_QUEUE.push(scancode);
+}
+
+/// Queue `scancodes` for the reader, without tracking modifiers
+///
+/// For handing back scancodes taken out of the queue (see `shell::selftest`),
+/// which `add_scancode` has tracked already.
+pub(crate) fn requeue_scancodes(scancodes: impl IntoIterator<Item = u8>) {
+  scancodes
+    .into_iter()
+    .for_each(|scancode| SCANCODE_QUEUE.push(scancode));
+}
+
+/// PS/2 controller data port
+pub(super) const PS2_DATA_PORT: u16 = 0x60;
+/// PS/2 controller status (read) and command (write) port
+pub(super) const PS2_STATUS_PORT: u16 = 0x64;
+/// Status bit: a byte is waiting in the data port
+pub(super) const OUTPUT_FULL: u8 = 1 << 0;
+/// Status bit: the controller hasn't consumed the last written byte yet
+const INPUT_FULL: u8 = 1 << 1;
+/// Controller command: read the configuration byte
+pub(super) const READ_CONFIG: u8 = 0x20;
+/// Configuration bit: scancodes are translated to set 1
+const TRANSLATION: u8 = 1 << 6;
+/// Keyboard command: get (sub-command `0`) or set (`1`..=`3`) the scancode set
+const SCANCODE_SET: u8 = 0xF0;
+/// Keyboard command: enable scanning
+const ENABLE_SCANNING: u8 = 0xF4;
+pub(super) const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+/// Status polls before giving up on the controller
+const POLL_LIMIT: usize = 100_000;
+/// Resends tolerated per keyboard command
+const MAX_RESENDS: usize = 3;
+
+/// Outcome of `init_keyboard`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardInit {
+  /// Whether the controller translates scancodes to set 1
+  pub translated: bool,
+  /// Scancode set (`1`..=`3`) the keyboard itself reports
+  pub keyboard_set: u8,
+}
+
+impl KeyboardInit {
+  /// Assumed when the controller doesn't answer: QEMU's and most BIOSes' default
+  const FALLBACK: Self = Self {
+    translated: true,
+    keyboard_set: 2,
+  };
+
+  /// Scancode set of the bytes reaching the interrupt handler
+  pub fn decoded_set(&self) -> u8 {
+    if self.translated {
+      1
     } else {
-      WAKER.wake(); // wake
+      self.keyboard_set
     }
-  } else {
-    // eprintln!("WARNING: `scancode queue` uninitialized");
   }
 }
 
-pub struct ScancodeStream {
-  _private: (),
+/// The controller didn't answer in time
+#[derive(Debug)]
+pub(super) struct Ps2Timeout;
+
+fn ps2_wait(mask: u8, set: bool) -> Result<(), Ps2Timeout> {
+  for _ in 0..POLL_LIMIT {
+    // SAFETY: reading the controller status has no side effects
+    if (unsafe { io::inb(PS2_STATUS_PORT) } & mask != 0) == set {
+      return Ok(());
+    }
+    core::hint::spin_loop();
+  }
+  Err(Ps2Timeout)
+}
+
+pub(super) fn ps2_read() -> Result<u8, Ps2Timeout> {
+  ps2_wait(OUTPUT_FULL, true)?;
+  // SAFETY: the controller has output waiting
+  Ok(unsafe { io::inb(PS2_DATA_PORT) })
+}
+
+/// Send `byte` to the controller (`PS2_STATUS_PORT`) or the device (`PS2_DATA_PORT`)
+pub(super) fn ps2_write(port: u16, byte: u8) -> Result<(), Ps2Timeout> {
+  debug_assert!(port == PS2_STATUS_PORT || port == PS2_DATA_PORT);
+  ps2_wait(INPUT_FULL, false)?;
+  // SAFETY: a PS/2 port, and the controller is ready for input
+  unsafe { io::outb(port, byte) };
+  Ok(())
+}
+
+/// Drop any output waiting in the controller
+pub(super) fn ps2_drain_output() {
+  // SAFETY: reads the controller status, and the data only while there is some
+  unsafe {
+    while io::inb(PS2_STATUS_PORT) & OUTPUT_FULL != 0 {
+      io::inb(PS2_DATA_PORT);
+    }
+  }
 }
 
-impl ScancodeStream {
+/// Send `byte` to the keyboard, until it is acknowledged
+fn keyboard_command(byte: u8) -> Result<(), Ps2Timeout> {
+  for _ in 0..=MAX_RESENDS {
+    ps2_write(PS2_DATA_PORT, byte)?;
+    // skip scancodes of keys pressed meanwhile
+    loop {
+      match ps2_read()? {
+        ACK => return Ok(()),
+        RESEND => break,
+        _ => {}
+      }
+    }
+  }
+  Err(Ps2Timeout)
+}
+
+/// Talk to the controller, see `init_keyboard`
+fn configure_keyboard() -> Result<KeyboardInit, Ps2Timeout> {
+  // drop stale output
+  ps2_drain_output();
+
+  ps2_write(PS2_STATUS_PORT, READ_CONFIG)?;
+  let translated = ps2_read()? & TRANSLATION != 0;
+
+  // our decoder expects set 1, which translation produces from set 2
+  keyboard_command(SCANCODE_SET)?;
+  keyboard_command(if translated { 2 } else { 1 })?;
+
+  keyboard_command(SCANCODE_SET)?;
+  keyboard_command(0)?;
+  let keyboard_set = match ps2_read()? {
+    // translated replies
+    0x43 => 1,
+    0x41 => 2,
+    0x3f => 3,
+    set => set,
+  };
+
+  // ends on an `ACK` (ignored by the decoder), as the interrupt handler may
+  // re-read the last byte once interrupts are enabled again
+  keyboard_command(ENABLE_SCANNING)?;
+  Ok(KeyboardInit {
+    translated,
+    keyboard_set,
+  })
+}
+
+/// Make the keyboard send the scancode set the decoders expect (set 1)
+///
+/// Reads whether the controller translates scancodes, selects the matching
+/// keyboard set and reads it back. Falls back to `KeyboardInit::FALLBACK`
+/// (with a warning) if the controller doesn't respond.
+pub fn init_keyboard() -> KeyboardInit {
+  let configured = x86_64::instructions::interrupts::without_interrupts(configure_keyboard);
+  configured.unwrap_or_else(|_| {
+    serial_println!("WARNING: no answer from the PS/2 keyboard, assuming scancode set 2");
+    KeyboardInit::FALLBACK
+  })
+}
+
+/// Stream of the raw scancodes received by the keyboard interrupt handler
+pub type ScancodeStream = IrqStream<u8>;
+
+/// Create a stream over the global scancode queue, see `IrqQueue::stream`
+pub fn scancode_stream() -> ScancodeStream {
+  SCANCODE_QUEUE.stream()
+}
+
+/// Keyboard layouts the scancode decoders can use, see `set_layout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum KeyboardLayout {
+  Us104,
+  /// French
+  Azerty,
+  /// German (QWERTZ)
+  German,
+}
+
+/// Current `KeyboardLayout`, as `u8`
+static LAYOUT: AtomicU8 = AtomicU8::new(KeyboardLayout::Us104 as u8);
+
+/// Decode further key presses with `layout`
+///
+/// Decoders switch on their next scancode, forgetting the modifiers they
+/// tracked (`current_modifiers` is unaffected).
+pub fn set_layout(layout: KeyboardLayout) {
+  LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+/// Get the layout last set by `set_layout` (`Us104` by default)
+pub fn layout() -> KeyboardLayout {
+  match LAYOUT.load(Ordering::Relaxed) {
+    1 => KeyboardLayout::Azerty,
+    2 => KeyboardLayout::German,
+    _ => KeyboardLayout::Us104,
+  }
+}
+
+/// A `pc_keyboard` decoder of set 1 scancodes, for any `KeyboardLayout`
+enum KeyDecoder {
+  Us104(Keyboard<layouts::Us104Key, ScancodeSet1>),
+  Azerty(Keyboard<layouts::Azerty, ScancodeSet1>),
+  German(Keyboard<layouts::De105Key, ScancodeSet1>),
+}
+
+impl KeyDecoder {
+  fn new(layout: KeyboardLayout) -> Self {
+    match layout {
+      KeyboardLayout::Us104 => Self::Us104(Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::Ignore,
+      )),
+      KeyboardLayout::Azerty => Self::Azerty(Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Azerty,
+        HandleControl::Ignore,
+      )),
+      KeyboardLayout::German => Self::German(Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::De105Key,
+        HandleControl::Ignore,
+      )),
+    }
+  }
+
+  fn layout(&self) -> KeyboardLayout {
+    match self {
+      Self::Us104(_) => KeyboardLayout::Us104,
+      Self::Azerty(_) => KeyboardLayout::Azerty,
+      Self::German(_) => KeyboardLayout::German,
+    }
+  }
+
+  /// Feed `scancode`, returning the key it completes (if any)
+  ///
+  /// Switches to the current `layout()` first.
+  fn decode(&mut self, scancode: u8) -> Option<DecodedKey> {
+    if self.layout() != layout() {
+      *self = Self::new(layout());
+    }
+    macro_rules! decode_with {
+      ($keyboard:expr) => {
+        match $keyboard.add_byte(scancode) {
+          Ok(Some(key_event)) => $keyboard.process_keyevent(key_event),
+          _ => None,
+        }
+      };
+    }
+    match self {
+      Self::Us104(keyboard) => decode_with!(keyboard),
+      Self::Azerty(keyboard) => decode_with!(keyboard),
+      Self::German(keyboard) => decode_with!(keyboard),
+    }
+  }
+}
+
+/// Stream of keys decoded from the global scancode queue, with the current layout
+pub struct KeyStream {
+  scancodes: ScancodeStream,
+  decoder: KeyDecoder,
+}
+
+impl KeyStream {
   pub fn new() -> Self {
-    SCANCODE_QUEUE
-      .try_init_once(|| ArrayQueue::new(100))
-      .expect("`ScancodeStream::new` should only be called once!\n");
-    ScancodeStream { _private: () }
+    KeyStream {
+      scancodes: scancode_stream(),
+      decoder: KeyDecoder::new(layout()),
+    }
   }
 }
 
-impl Default for ScancodeStream {
+impl Default for KeyStream {
   fn default() -> Self {
     Self::new()
   }
 }
 
-impl Stream for ScancodeStream {
-  type Item = u8;
+impl Stream for KeyStream {
+  type Item = DecodedKey;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<DecodedKey>> {
+    // scancodes which don't complete a key press are swallowed
+    while let Poll::Ready(scancode) = Pin::new(&mut self.scancodes).poll_next(cx) {
+      let Some(scancode) = scancode else {
+        return Poll::Ready(None);
+      };
+      if let Some(key) = self.decoder.decode(scancode) {
+        return Poll::Ready(Some(key));
+      }
+    }
+    Poll::Pending
+  }
+}
+
+/// Feed every scancode of `scancodes` to `handle`, batch by batch
+///
+/// Waits for a scancode, then handles it along with all the ones queued
+/// meanwhile before waiting again. Waiting re-registers the waker, so
+/// scancodes arriving after a batch still wake the task.
+pub async fn process_scancodes(scancodes: &mut ScancodeStream, mut handle: impl FnMut(u8)) {
+  while let Some(scancode) = scancodes.next().await {
+    handle(scancode);
+    scancodes.drain().for_each(&mut handle);
+  }
+}
 
-  fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
-    let queue = SCANCODE_QUEUE
-      .try_get()
-      .expect("scancode_queue not initialized!\n");
+pub async fn print_keypresses() {
+  let mut scancodes = scancode_stream();
+  let mut decoder = KeyDecoder::new(layout());
 
-    // fast path
-    if let Some(scancode) = queue.pop() {
-      return Poll::Ready(Some(scancode));
+  process_scancodes(&mut scancodes, |scancode| {
+    if let Some(key) = decoder.decode(scancode) {
+      print_key(key);
     }
+  })
+  .await
+}
 
-    WAKER.register(cx.waker());
-    match queue.pop() {
-      Some(scancode) => {
-        WAKER.take();
-        Poll::Ready(Some(scancode))
+/// Read a line from the keyboard, echoing it
+///
+/// Resolves on `Enter`, without the newline.
+pub async fn read_line() -> String {
+  read_line_from(&mut KeyStream::new()).await
+}
+
+/// Same as `read_line`, but reads the keys from `keys` (until it ends)
+pub async fn read_line_from(keys: &mut (impl Stream<Item = DecodedKey> + Unpin)) -> String {
+  let mut line = String::new();
+  while let Some(key) = keys.next().await {
+    match key {
+      DecodedKey::Unicode('\n') | DecodedKey::RawKey(KeyCode::Return) => {
+        println!();
+        break;
+      }
+      DecodedKey::Unicode('\x08') | DecodedKey::RawKey(KeyCode::Backspace) => {
+        // nothing to erase at the start of the line; a non-ASCII char
+        // was echoed as one cell per UTF-8 byte
+        if let Some(character) = line.pop() {
+          x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            for _ in 0..character.len_utf8() {
+              writer.enforce_backspace();
+            }
+          })
+        }
+      }
+      DecodedKey::Unicode(character) if !character.is_control() => {
+        line.push(character);
+        print!("{}", character);
       }
-      None => Poll::Pending,
+      _ => {}
     }
   }
+  line
 }
 
-pub async fn print_keypresses() {
-  let mut scancodes = ScancodeStream::new();
-  let mut keyboard = Keyboard::new(
-    ScancodeSet1::new(),
-    layouts::Us104Key,
-    HandleControl::Ignore,
-  );
+fn print_key(key: DecodedKey) {
+  match key {
+    // input := <backspace>
+    DecodedKey::Unicode(character) if character as u8 == b'\x08' => {
+      x86_64::instructions::interrupts::without_interrupts(|| {
+        WRITER.lock().enforce_backspace();
+      })
+    }
+    // input := unicode_char
+    DecodedKey::Unicode(character) => print!("{}", character),
+    // input <~ human-readable event (e.g. press `CapsLock` or 'LCtrl')
+    DecodedKey::RawKey(key) => match key {
+      KeyCode::Backspace => x86_64::instructions::interrupts::without_interrupts(|| {
+        WRITER.lock().enforce_backspace();
+      }),
+      KeyCode::LControl | KeyCode::RControl => print!("^"),
+      _ => {}
+    },
+  }
+}
 
-  while let Some(scancode) = scancodes.next().await {
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-      if let Some(key) = keyboard.process_keyevent(key_event) {
-        match key {
-          // input := <backspace>
-          DecodedKey::Unicode(character) if character as u8 == b'\x08' => {
-            x86_64::instructions::interrupts::without_interrupts(|| {
-              WRITER.lock().enforce_backspace();
-            })
-          }
-          // input := unicode_char
-          DecodedKey::Unicode(character) => print!("{}", character),
-          // input <~ human-readable event (e.g. press `CapsLock` or 'LCtrl')
-          DecodedKey::RawKey(key) => match key {
-            KeyCode::Backspace => x86_64::instructions::interrupts::without_interrupts(|| {
-              WRITER.lock().enforce_backspace();
-            }),
-            KeyCode::LControl | KeyCode::RControl => print!("^"),
-            _ => {}
-          },
-        }
+#[cfg(test)]
+mod test_init_keyboard {
+  use super::*;
+  use futures_util::task::noop_waker_ref;
+
+  #[test_case]
+  fn detection_is_consistent() {
+    let first = init_keyboard();
+    assert_eq!(init_keyboard(), first);
+    assert_eq!(first.decoded_set(), 1);
+
+    // decoding still works afterwards
+    let mut keys = KeyStream::new();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    while let Poll::Ready(Some(_)) = Pin::new(&mut keys).poll_next(&mut cx) {}
+    add_scancode(0x1e); // `a` pressed
+    assert_eq!(
+      Pin::new(&mut keys).poll_next(&mut cx),
+      Poll::Ready(Some(DecodedKey::Unicode('a')))
+    );
+  }
+}
+
+#[cfg(test)]
+mod test_batching {
+  use super::*;
+  use crate::task::{executor::Executor, Task};
+  use core::{
+    future::{poll_fn, Future},
+    sync::atomic::{AtomicUsize, Ordering},
+  };
+
+  #[test_case]
+  fn burst_is_processed_in_one_poll() {
+    static POLLS: AtomicUsize = AtomicUsize::new(0);
+    static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut scancodes = scancode_stream();
+    scancodes.drain().for_each(drop);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+      let mut processing = core::pin::pin!(process_scancodes(&mut scancodes, |_| {
+        HANDLED.fetch_add(1, Ordering::SeqCst);
+      }));
+      poll_fn(|cx| {
+        POLLS.fetch_add(1, Ordering::SeqCst);
+        processing.as_mut().poll(cx)
+      })
+      .await
+    }));
+    executor.run_ready_tasks();
+    let polls_before = POLLS.load(Ordering::SeqCst);
+
+    for _ in 0..10 {
+      add_scancode(0x00);
+    }
+    executor.run_ready_tasks();
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 10);
+    assert_eq!(POLLS.load(Ordering::SeqCst), polls_before + 1);
+
+    // a later scancode still wakes the task
+    add_scancode(0x00);
+    executor.run_ready_tasks();
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 11);
+  }
+}
+
+#[cfg(test)]
+mod test_read_line {
+  use super::*;
+  use core::{future::Future, pin::pin};
+  use futures_util::{stream, task::noop_waker_ref};
+
+  fn read(keys: &str) -> (String, usize) {
+    let mut keys = stream::iter(keys.chars().map(DecodedKey::Unicode));
+    let line = {
+      let mut reading = pin!(read_line_from(&mut keys));
+      let mut cx = Context::from_waker(noop_waker_ref());
+      match reading.as_mut().poll(&mut cx) {
+        Poll::Ready(line) => line,
+        Poll::Pending => panic!("line not finished!\n"),
+      }
+    };
+    (line, keys.size_hint().0)
+  }
+
+  #[test_case]
+  fn enter_finishes_the_line() {
+    assert_eq!(read("ls -l\nrest"), ("ls -l".into(), 4));
+  }
+
+  #[test_case]
+  fn backspace_erases_until_empty() {
+    assert_eq!(read("ab\x08\x08\x08c\x08d\n"), ("d".into(), 0));
+  }
+
+  #[test_case]
+  fn backspace_erases_every_cell_of_a_char() {
+    use crate::vga_buffer::BUFFER_HEIGHT;
+
+    println!();
+    assert_eq!(read("x\u{e9}\x08\x08\n"), ("".into(), 0));
+    // the echoed line (scrolled up by the final newline) is blank again
+    let rows = x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().rows());
+    assert_eq!(rows[BUFFER_HEIGHT - 2].trim_end(), "");
+  }
+}
+
+#[cfg(test)]
+mod test_modifiers {
+  use super::*;
+
+  /// Feed `scancodes`, then take them back out of the queue
+  fn feed(scancodes: &[u8]) -> Modifiers {
+    let mut stream = scancode_stream();
+    scancodes
+      .iter()
+      .for_each(|&scancode| add_scancode(scancode));
+    stream.drain().for_each(drop);
+    current_modifiers()
+  }
+
+  #[test_case]
+  fn shift_is_held_until_released() {
+    assert!(feed(&[0x2A]).shift);
+    // the other shift doesn't release it
+    assert!(feed(&[0x36, 0xB6]).shift);
+    assert!(!feed(&[0xAA]).shift);
+  }
+
+  #[test_case]
+  fn extended_keys() {
+    // right ctrl, right alt
+    let modifiers = feed(&[0xE0, 0x1D, 0xE0, 0x38]);
+    assert!(modifiers.ctrl && modifiers.alt && !modifiers.shift);
+    assert_eq!(feed(&[0xE0, 0x9D, 0xE0, 0xB8]), Modifiers::default());
+    // fake shift of print screen
+    assert!(!feed(&[0xE0, 0x2A]).shift);
+  }
+
+  #[test_case]
+  fn caps_lock_toggles_on_press() {
+    assert!(feed(&[0x3A]).caps_lock);
+    assert!(feed(&[0xBA]).caps_lock);
+    assert!(!feed(&[0x3A, 0xBA]).caps_lock);
+  }
+}
+
+#[cfg(test)]
+mod test_layout {
+  use super::*;
+  use futures_util::task::noop_waker_ref;
+
+  /// Decode the press (and release) of each of `scancodes` with `layout`
+  fn keys_with(layout: KeyboardLayout, scancodes: &[u8]) -> [Option<DecodedKey>; 3] {
+    let mut keys = KeyStream::new();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    while let Poll::Ready(Some(_)) = Pin::new(&mut keys).poll_next(&mut cx) {}
+
+    set_layout(layout);
+    let mut decoded = [None; 3];
+    for (key, &scancode) in decoded.iter_mut().zip(scancodes) {
+      add_scancode(scancode);
+      add_scancode(scancode | RELEASE);
+      if let Poll::Ready(Some(decoded)) = Pin::new(&mut keys).poll_next(&mut cx) {
+        *key = Some(decoded);
       }
     }
+    set_layout(KeyboardLayout::Us104);
+    decoded
+  }
+
+  #[test_case]
+  fn azerty_keys() {
+    // the keys labeled `2`, `0` and `Q` on a US keyboard
+    let scancodes = [0x03, 0x0B, 0x10];
+    assert_eq!(
+      keys_with(KeyboardLayout::Azerty, &scancodes),
+      ['é', 'à', 'a'].map(|c| Some(DecodedKey::Unicode(c)))
+    );
+    assert_eq!(
+      keys_with(KeyboardLayout::Us104, &scancodes),
+      ['2', '0', 'q'].map(|c| Some(DecodedKey::Unicode(c)))
+    );
+  }
+
+  #[test_case]
+  fn german_keys() {
+    // the keys labeled `Z`, `Y` and `[` on a US keyboard
+    assert_eq!(
+      keys_with(KeyboardLayout::German, &[0x2C, 0x15, 0x1A]),
+      ['y', 'z', 'ü'].map(|c| Some(DecodedKey::Unicode(c)))
+    );
+    assert_eq!(layout(), KeyboardLayout::Us104);
   }
 }
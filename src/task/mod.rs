@@ -1,15 +1,19 @@
 use crate::demo::concurrency;
 use alloc::boxed::Box;
 use core::{
-  future::Future,
+  future::{poll_fn, Future},
   pin::Pin,
   sync::atomic::{AtomicU64, Ordering},
   task::{Context, Poll},
 };
 
 pub mod executor;
+pub mod irq_queue;
 pub mod keyboard;
+pub mod mouse;
+pub mod serial;
 pub mod simple_executor;
+pub mod timer;
 
 cfg_if::cfg_if! {
   if #[cfg(feature = "use_SimpleExecutor")] {
@@ -32,11 +36,36 @@ impl Task {
     }
   }
 
+  /// Wrap an already pinned `future` (without boxing it again)
+  fn from_pinned(future: Pin<Box<dyn Future<Output = ()>>>) -> Task {
+    Task {
+      id: TaskId::new(),
+      future,
+    }
+  }
+
   fn poll(&mut self, context: &mut Context) -> Poll<()> {
     self.future.as_mut().poll(context)
   }
 }
 
+/// Let the other ready tasks run before resuming
+///
+/// Returns `Pending` once, after waking the task, which puts it at the back
+/// of the ready queue. Long computations can call it to stay fair.
+pub async fn yield_now() {
+  let mut yielded = false;
+  poll_fn(|cx| {
+    if yielded {
+      return Poll::Ready(());
+    }
+    yielded = true;
+    cx.waker().wake_by_ref();
+    Poll::Pending
+  })
+  .await
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
@@ -49,7 +78,7 @@ impl TaskId {
 
 impl UsedExecutor {
   fn spawn_hardware_task(&mut self) {
-    self.spawn(Task::new(keyboard::print_keypresses()));
+    self.spawn(Task::new(crate::shell::run()));
   }
 
   fn spawn_long_computation_demos(&mut self) {
@@ -78,3 +107,23 @@ pub fn init() -> UsedExecutor {
   executor.spawn_long_computation_demos();
   executor
 }
+
+#[test_case]
+fn test_yield_now_interleaves() {
+  use alloc::{sync::Arc, vec::Vec};
+  use spin::Mutex;
+
+  let log = Arc::new(Mutex::new(Vec::new()));
+  let mut executor = executor::Executor::new();
+  for name in ['a', 'b'] {
+    let log = log.clone();
+    executor.spawn(Task::new(async move {
+      for _ in 0..3 {
+        log.lock().push(name);
+        yield_now().await;
+      }
+    }));
+  }
+  executor.run_until_all_task_finished();
+  assert_eq!(*log.lock(), ['a', 'b', 'a', 'b', 'a', 'b']);
+}
@@ -0,0 +1,220 @@
+use super::irq_queue::{IrqQueue, IrqStream};
+use super::keyboard::{
+  ps2_drain_output, ps2_read, ps2_write, Ps2Timeout, ACK, PS2_DATA_PORT, PS2_STATUS_PORT,
+  READ_CONFIG,
+};
+use crate::{interrupts::PICS, serial_println};
+use spin::Mutex;
+
+static MOUSE_QUEUE: IrqQueue<MouseState> = IrqQueue::new("mouse queue");
+
+/// Packet being assembled by the mouse interrupt handler
+static DECODER: Mutex<PacketDecoder> = Mutex::new(PacketDecoder::new());
+
+/// Buttons and movement reported by one PS/2 mouse packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseState {
+  pub left: bool,
+  pub right: bool,
+  pub middle: bool,
+  /// Relative movement, positive to the right
+  pub dx: i16,
+  /// Relative movement, positive upwards
+  pub dy: i16,
+}
+
+/// First packet byte: always set, used to find the start of a packet
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+/// Assembles 3-byte PS/2 packets (flags, dx, dy) into `MouseState`s
+struct PacketDecoder {
+  packet: [u8; 3],
+  len: usize,
+}
+
+impl PacketDecoder {
+  const fn new() -> Self {
+    Self {
+      packet: [0; 3],
+      len: 0,
+    }
+  }
+
+  /// Add a byte, returning the state once a packet is complete
+  ///
+  /// A first byte without `ALWAYS_ONE` is dropped, to get back in sync after
+  /// a lost byte. Overflowed movements are reported as `0`.
+  fn add_byte(&mut self, byte: u8) -> Option<MouseState> {
+    if self.len == 0 && byte & ALWAYS_ONE == 0 {
+      return None;
+    }
+    self.packet[self.len] = byte;
+    self.len += 1;
+    if self.len < 3 {
+      return None;
+    }
+    self.len = 0;
+
+    let [flags, dx, dy] = self.packet;
+    // 9-bit two's complement, with the sign bit in `flags`
+    let delta = |value: u8, sign: u8, overflow: u8| match flags & overflow {
+      0 if flags & sign != 0 => value as i16 - 0x100,
+      0 => value as i16,
+      _ => 0,
+    };
+    Some(MouseState {
+      left: flags & (1 << 0) != 0,
+      right: flags & (1 << 1) != 0,
+      middle: flags & (1 << 2) != 0,
+      dx: delta(dx, X_SIGN, X_OVERFLOW),
+      dy: delta(dy, Y_SIGN, Y_OVERFLOW),
+    })
+  }
+}
+
+/// Called by the mouse interrupt handler
+///
+/// Must not block or allocate.
+pub fn add_mouse_byte(byte: u8) {
+  let Some(state) = DECODER.lock().add_byte(byte) else {
+    return;
+  };
+  MOUSE_QUEUE.push(state);
+}
+
+/// Controller command: enable the auxiliary (mouse) port
+const ENABLE_AUX: u8 = 0xA8;
+/// Controller command: write the configuration byte
+const WRITE_CONFIG: u8 = 0x60;
+/// Controller command: send the next data byte to the mouse
+const WRITE_AUX: u8 = 0xD4;
+/// Configuration bit: raise IRQ12 for mouse data
+const AUX_INTERRUPT: u8 = 1 << 1;
+/// Configuration bit: the mouse clock is disabled
+const AUX_CLOCK_DISABLED: u8 = 1 << 5;
+/// Mouse command: default sample rate and resolution
+const SET_DEFAULTS: u8 = 0xF6;
+/// Mouse command: send packets on movement
+const ENABLE_STREAMING: u8 = 0xF4;
+/// IRQ line of the secondary PIC on the primary one
+const CASCADE_IRQ: u8 = 2;
+/// IRQ12, as a line of the secondary PIC
+const MOUSE_IRQ: u8 = 12 - 8;
+
+/// Send `byte` to the mouse, expecting an `ACK`
+fn mouse_command(byte: u8) -> Result<(), Ps2Timeout> {
+  ps2_write(PS2_STATUS_PORT, WRITE_AUX)?;
+  ps2_write(PS2_DATA_PORT, byte)?;
+  match ps2_read()? {
+    ACK => Ok(()),
+    _ => Err(Ps2Timeout),
+  }
+}
+
+/// Talk to the controller, see `init_mouse`
+fn configure_mouse() -> Result<(), Ps2Timeout> {
+  ps2_write(PS2_STATUS_PORT, ENABLE_AUX)?;
+  // drop stale output
+  ps2_drain_output();
+
+  ps2_write(PS2_STATUS_PORT, READ_CONFIG)?;
+  let config = (ps2_read()? | AUX_INTERRUPT) & !AUX_CLOCK_DISABLED;
+  ps2_write(PS2_STATUS_PORT, WRITE_CONFIG)?;
+  ps2_write(PS2_DATA_PORT, config)?;
+
+  mouse_command(SET_DEFAULTS)?;
+  mouse_command(ENABLE_STREAMING)
+}
+
+/// Enable the PS/2 mouse, streaming packets through IRQ12
+///
+/// Also unmasks IRQ12 (and the cascade) on the PICs, so it must run after
+/// they are initialized. Leaves the mouse off (with a warning) if it doesn't
+/// respond.
+pub fn init_mouse() {
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    if configure_mouse().is_err() {
+      serial_println!("WARNING: no answer from the PS/2 mouse, leaving it off");
+      return;
+    }
+    let mut pics = PICS.lock();
+    unsafe {
+      let [primary, secondary] = pics.read_masks();
+      pics.write_masks(primary & !(1 << CASCADE_IRQ), secondary & !(1 << MOUSE_IRQ));
+    }
+  });
+}
+
+/// Stream of the states reported by the mouse interrupt handler
+pub type MouseStream = IrqStream<MouseState>;
+
+/// Create a stream over the global mouse queue, see `IrqQueue::stream`
+pub fn mouse_stream() -> MouseStream {
+  MOUSE_QUEUE.stream()
+}
+
+#[cfg(test)]
+mod test_mouse {
+  use super::*;
+  use core::task::{Context, Poll};
+  use futures_util::{task::noop_waker_ref, StreamExt};
+
+  #[test_case]
+  fn decodes_packets() {
+    let mut decoder = PacketDecoder::new();
+    // left button, moving right and down
+    assert_eq!(decoder.add_byte(ALWAYS_ONE | 1 | Y_SIGN), None);
+    assert_eq!(decoder.add_byte(5), None);
+    assert_eq!(
+      decoder.add_byte(0xFE),
+      Some(MouseState {
+        left: true,
+        dx: 5,
+        dy: -2,
+        ..Default::default()
+      })
+    );
+
+    // a stray byte is skipped until a packet start
+    assert_eq!(decoder.add_byte(0x00), None);
+    for byte in [ALWAYS_ONE | 2 | X_OVERFLOW | X_SIGN, 0x80] {
+      assert_eq!(decoder.add_byte(byte), None);
+    }
+    assert_eq!(
+      decoder.add_byte(3),
+      Some(MouseState {
+        right: true,
+        dx: 0,
+        dy: 3,
+        ..Default::default()
+      })
+    );
+  }
+
+  #[test_case]
+  fn states_reach_the_stream() {
+    let mut mouse = mouse_stream();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    while let Poll::Ready(Some(_)) = mouse.poll_next_unpin(&mut cx) {}
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+      for byte in [ALWAYS_ONE | 4, 1, 1] {
+        add_mouse_byte(byte);
+      }
+    });
+    assert_eq!(
+      mouse.poll_next_unpin(&mut cx),
+      Poll::Ready(Some(MouseState {
+        middle: true,
+        dx: 1,
+        dy: 1,
+        ..Default::default()
+      }))
+    );
+    assert_eq!(mouse.poll_next_unpin(&mut cx), Poll::Pending);
+  }
+}
@@ -0,0 +1,57 @@
+use super::irq_queue::{IrqQueue, IrqStream};
+use crate::{interrupts::PICS, serial::SERIAL1};
+
+static SERIAL_QUEUE: IrqQueue<u8> = IrqQueue::new("serial queue");
+
+/// IRQ line of COM1 on the primary PIC
+const COM1_IRQ: u8 = 4;
+
+/// Called by the COM1 interrupt handler
+///
+/// Must not block or allocate.
+pub fn add_serial_byte(byte: u8) {
+  SERIAL_QUEUE.push(byte);
+}
+
+/// Deliver the bytes received on COM1 through IRQ4 (see `SerialStream`)
+///
+/// The UART raises the interrupt once `SERIAL1` is initialized, this unmasks
+/// IRQ4 on the primary PIC, so it must run after the PICs are initialized.
+pub fn init_serial_input() {
+  lazy_static::initialize(&SERIAL1);
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    let mut pics = PICS.lock();
+    unsafe {
+      let [primary, secondary] = pics.read_masks();
+      pics.write_masks(primary & !(1 << COM1_IRQ), secondary);
+    }
+  });
+}
+
+/// Stream of the bytes received by the COM1 interrupt handler
+pub type SerialStream = IrqStream<u8>;
+
+/// Create a stream over the global serial input queue, see `IrqQueue::stream`
+pub fn serial_stream() -> SerialStream {
+  SERIAL_QUEUE.stream()
+}
+
+#[test_case]
+fn test_bytes_reach_the_stream() {
+  use core::task::{Context, Poll};
+  use futures_util::{task::noop_waker_ref, StreamExt};
+
+  let mut serial = serial_stream();
+  let mut cx = Context::from_waker(noop_waker_ref());
+  while let Poll::Ready(Some(_)) = serial.poll_next_unpin(&mut cx) {}
+
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    for &byte in b"ok\n" {
+      add_serial_byte(byte);
+    }
+  });
+  for &byte in b"ok\n" {
+    assert_eq!(serial.poll_next_unpin(&mut cx), Poll::Ready(Some(byte)));
+  }
+  assert_eq!(serial.poll_next_unpin(&mut cx), Poll::Pending);
+}
@@ -0,0 +1,326 @@
+use super::yield_now;
+use crate::time::{self, Instant};
+use alloc::vec::Vec;
+use core::{
+  future::Future,
+  pin::{pin, Pin},
+  sync::atomic::{AtomicU64, Ordering},
+  task::{Context, Poll, Waker},
+};
+use futures_util::future::{select, Either};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// A pending wakeup, registered by a `Sleep` future
+struct TimerEntry {
+  deadline: u64,
+  id: u64,
+  waker: Option<Waker>,
+}
+
+/// Pending wakeups, sorted by `deadline`
+///
+/// Only locked with interrupts disabled on the task side.
+static TIMERS: Mutex<Vec<TimerEntry>> = Mutex::new(Vec::new());
+
+/// Called by the timer interrupt handler
+///
+/// Wakes every sleeper whose deadline has been reached. Entries are left in
+/// place (their `Sleep` removes them), so this never allocates or deallocates.
+pub(crate) fn wake_expired(now: u64) {
+  crate::interrupts::assert_disabled();
+  if let Some(mut timers) = TIMERS.try_lock() {
+    for entry in timers.iter_mut().take_while(|e| e.deadline <= now) {
+      if let Some(waker) = entry.waker.take() {
+        waker.wake();
+      }
+    }
+  }
+}
+
+/// Future returned by `sleep`
+pub struct Sleep {
+  deadline: u64,
+  id: Option<u64>,
+}
+
+/// Suspend the current task for `ticks` timer ticks
+///
+/// `sleep(0)` resolves immediately.
+pub fn sleep(ticks: u64) -> Sleep {
+  sleep_until(Instant::now() + ticks)
+}
+
+/// Suspend the current task for (at least) `ms` milliseconds
+///
+/// Converted with `time::ms_to_ticks`, so it follows the configured timer
+/// frequency. `sleep_ms(0)` yields to the other tasks once.
+pub async fn sleep_ms(ms: u64) {
+  match ms {
+    0 => yield_now().await,
+    ms => sleep(time::ms_to_ticks(ms)).await,
+  }
+}
+
+/// Suspend the current task until `deadline`
+///
+/// Unlike `sleep`, periodic loops doing `next += period; sleep_until(next)`
+/// don't drift. A deadline in the past resolves immediately.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+  Sleep {
+    deadline: deadline.ticks(),
+    id: None,
+  }
+}
+
+impl Sleep {
+  fn register(&mut self, waker: &Waker) {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    interrupts::without_interrupts(|| {
+      let mut timers = TIMERS.lock();
+      if let Some(id) = self.id {
+        if let Some(entry) = timers.iter_mut().find(|e| e.id == id) {
+          entry.waker = Some(waker.clone());
+          return;
+        }
+      }
+      let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+      let index = timers.partition_point(|e| e.deadline <= self.deadline);
+      timers.insert(
+        index,
+        TimerEntry {
+          deadline: self.deadline,
+          id,
+          waker: Some(waker.clone()),
+        },
+      );
+      self.id = Some(id);
+    });
+  }
+}
+
+impl Future for Sleep {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+    // fast path
+    if time::ticks() >= self.deadline {
+      return Poll::Ready(());
+    }
+
+    self.register(cx.waker());
+    // the deadline may have passed while registering
+    if time::ticks() >= self.deadline {
+      Poll::Ready(())
+    } else {
+      Poll::Pending
+    }
+  }
+}
+
+impl Drop for Sleep {
+  fn drop(&mut self) {
+    if let Some(id) = self.id {
+      interrupts::without_interrupts(|| {
+        TIMERS.lock().retain(|e| e.id != id);
+      });
+    }
+  }
+}
+
+/// Error returned by `timeout` when the timer fires first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Run `future` for at most `ticks` timer ticks
+///
+/// Resolves to `Ok(value)` if `future` completes first, otherwise to
+/// `Err(Elapsed)`, dropping the unfinished `future`.
+pub async fn timeout<F: Future>(ticks: u64, future: F) -> Result<F::Output, Elapsed> {
+  let future = pin!(future);
+  match select(future, sleep(ticks)).await {
+    Either::Left((value, _)) => Ok(value),
+    Either::Right(((), _)) => Err(Elapsed),
+  }
+}
+
+#[cfg(test)]
+mod test_timer {
+  use super::*;
+  use crate::task::{
+    executor::{Executor, Idle},
+    Task,
+  };
+  use core::sync::atomic::AtomicBool;
+
+  /// Run `executor` until its tasks have completed, halting between ticks
+  fn run_to_completion(executor: &mut Executor) {
+    while executor.run_until_idle() != Idle::NoTasks {
+      x86_64::instructions::hlt();
+    }
+  }
+
+  #[test_case]
+  fn timeout_elapsed() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+      assert_eq!(timeout(2, sleep(1000)).await, Err(Elapsed));
+      DONE.store(true, Ordering::SeqCst);
+    }));
+    run_to_completion(&mut executor);
+    assert!(DONE.load(Ordering::SeqCst));
+  }
+
+  #[test_case]
+  fn timeout_completed() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+      assert_eq!(timeout(1000, sleep(2)).await, Ok(()));
+      DONE.store(true, Ordering::SeqCst);
+    }));
+    run_to_completion(&mut executor);
+    assert!(DONE.load(Ordering::SeqCst));
+  }
+
+  #[test_case]
+  fn advance_ticks_resolves_sleep() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+      sleep(100).await;
+      DONE.store(true, Ordering::SeqCst);
+    }));
+    executor.run_ready_tasks();
+    assert!(!DONE.load(Ordering::SeqCst));
+
+    time::advance_ticks(100);
+    executor.run_ready_tasks();
+    assert!(DONE.load(Ordering::SeqCst));
+  }
+
+  /// Run `periods` iterations of a periodic loop (each iteration doing
+  /// `WORK` ticks of work) and return how late the last wakeup was
+  fn periodic_lateness(absolute: bool) -> u64 {
+    const PERIOD: u64 = 10;
+    const WORK: u64 = 3;
+    const PERIODS: u64 = 5;
+    static LATENESS: AtomicU64 = AtomicU64::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+    DONE.store(false, Ordering::SeqCst);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+      let start = Instant::now();
+      let mut next = start;
+      for _ in 0..PERIODS {
+        next += PERIOD;
+        if absolute {
+          sleep_until(next).await;
+        } else {
+          sleep(PERIOD).await;
+        }
+        LATENESS.store(Instant::now() - next, Ordering::SeqCst);
+        time::advance_ticks(WORK);
+      }
+      DONE.store(true, Ordering::SeqCst);
+    }));
+    while !DONE.load(Ordering::SeqCst) {
+      executor.run_ready_tasks();
+      time::advance_ticks(1);
+    }
+    LATENESS.load(Ordering::SeqCst)
+  }
+
+  #[test_case]
+  fn sleep_until_does_not_drift() {
+    // real timer interrupts may still add a tick here and there
+    assert!(periodic_lateness(true) <= 2);
+    // `sleep` accumulates the work of every earlier iteration
+    assert!(periodic_lateness(false) >= 4 * 3);
+  }
+
+  #[test_case]
+  fn sleep_ms_waits_the_equivalent_ticks() {
+    static ELAPSED: AtomicU64 = AtomicU64::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+    let expected = time::ms_to_ticks(50);
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+      let start = Instant::now();
+      sleep_ms(50).await;
+      ELAPSED.store(start.elapsed(), Ordering::SeqCst);
+      DONE.store(true, Ordering::SeqCst);
+    }));
+    while !DONE.load(Ordering::SeqCst) {
+      executor.run_ready_tasks();
+      time::advance_ticks(1);
+    }
+    // real timer interrupts may still add a tick here and there
+    let elapsed = ELAPSED.load(Ordering::SeqCst);
+    assert!((expected..=expected + 2).contains(&elapsed));
+  }
+
+  #[test_case]
+  fn sleep_ms_edge_cases() {
+    use futures_util::task::noop_waker_ref;
+
+    static DONE: AtomicBool = AtomicBool::new(false);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+      sleep_ms(0).await;
+      DONE.store(true, Ordering::SeqCst);
+    }));
+    executor.run_ready_tasks();
+    assert!(DONE.load(Ordering::SeqCst));
+
+    // no overflow, just a (practically) endless sleep
+    let mut cx = Context::from_waker(noop_waker_ref());
+    assert!(pin!(sleep_ms(u64::MAX)).poll(&mut cx).is_pending());
+  }
+
+  #[test_case]
+  fn sleepers_expiring_on_the_same_tick() {
+    use core::sync::atomic::AtomicUsize;
+
+    static WOKEN: AtomicUsize = AtomicUsize::new(0);
+    let deadline = Instant::now() + 50;
+    let mut executor = Executor::new();
+    for _ in 0..8 {
+      executor.spawn(Task::new(async move {
+        sleep_until(deadline).await;
+        WOKEN.fetch_add(1, Ordering::SeqCst);
+      }));
+    }
+    executor.run_ready_tasks();
+    assert_eq!(WOKEN.load(Ordering::SeqCst), 0);
+
+    time::advance_ticks(50);
+    executor.run_ready_tasks();
+    assert_eq!(WOKEN.load(Ordering::SeqCst), 8);
+    // every entry is gone
+    let remaining = interrupts::without_interrupts(|| {
+      TIMERS
+        .lock()
+        .iter()
+        .filter(|e| e.deadline == deadline.ticks())
+        .count()
+    });
+    assert_eq!(remaining, 0);
+  }
+
+  #[test_case]
+  fn sleep_until_past_deadline() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+      sleep_until(Instant::from_ticks(0)).await;
+      DONE.store(true, Ordering::SeqCst);
+    }));
+    executor.run_ready_tasks();
+    assert!(DONE.load(Ordering::SeqCst));
+  }
+}
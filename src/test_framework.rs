@@ -1,4 +1,10 @@
-use crate::{serial_print, serial_println};
+use crate::{
+  exit::{exit_qemu, QemuExitCode},
+  serial_print, serial_println,
+  utils::rand::Rng,
+  vga_buffer::OutputTarget,
+};
+use core::fmt;
 
 pub trait Testable {
   fn run(&self);
@@ -18,3 +24,133 @@ impl<T: Fn()> Testable for T {
     // serial_println!("\x1b[0m");
   }
 }
+
+/// Seed of the randomized test order, set with `TEST_SEED=<u64>` at build time
+///
+/// Unset => tests run in source order.
+pub const TEST_SEED: Option<&str> = option_env!("TEST_SEED");
+
+/// Maximum number of tests `test_runner` can shuffle
+pub const MAX_SHUFFLED_TESTS: usize = 1024;
+
+/// Fill `order` with a permutation of `0..order.len()`, derived from `seed`
+pub fn shuffled_order(order: &mut [u16], seed: u64) {
+  for (i, index) in order.iter_mut().enumerate() {
+    *index = i as u16;
+  }
+  Rng::new(seed).shuffle(order);
+}
+
+/// Keeps the first `N` bytes written to it, without allocating
+///
+/// For tests that inspect a panic message (the heap may be unusable there).
+pub struct MessageBuffer<const N: usize> {
+  bytes: [u8; N],
+  len: usize,
+}
+
+impl<const N: usize> MessageBuffer<N> {
+  pub const fn new() -> Self {
+    Self {
+      bytes: [0; N],
+      len: 0,
+    }
+  }
+
+  /// What was kept so far (empty if cut inside a UTF-8 sequence)
+  pub fn as_str(&self) -> &str {
+    core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+  }
+}
+
+impl<const N: usize> Default for MessageBuffer<N> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<const N: usize> fmt::Write for MessageBuffer<N> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    let n = s.len().min(N - self.len);
+    self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+    self.len += n;
+    Ok(())
+  }
+}
+
+/// `OutputTarget` for integration tests of exception handlers
+///
+/// Collects what the handler prints, and once the report is complete (the
+/// stack frame, ending with `RFLAGS`, comes last) exits QEMU with success
+/// if the report contains every `expected` string.
+pub struct ReportChecker {
+  report: MessageBuffer<512>,
+  expected: &'static [&'static str],
+}
+
+impl ReportChecker {
+  pub fn new(expected: &'static [&'static str]) -> Self {
+    Self {
+      report: MessageBuffer::new(),
+      expected,
+    }
+  }
+
+  fn check(&self) -> ! {
+    let text = self.report.as_str();
+    if self.expected.iter().all(|expected| text.contains(expected)) {
+      // green
+      serial_print!("\x1b[32m");
+      serial_print!("[ok]");
+      serial_print!("\x1b[0m");
+      serial_println!("\n");
+      exit_qemu(QemuExitCode::Success);
+    } else {
+      // red
+      serial_print!("\x1b[31m");
+      serial_print!("[failed]");
+      serial_println!("\x1b[0m");
+      serial_println!("Report: {}\n", text);
+      exit_qemu(QemuExitCode::Failed);
+    }
+    crate::hlt_loop()
+  }
+}
+
+impl fmt::Write for ReportChecker {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.report.write_str(s)?;
+    if self.report.as_str().contains("RFLAGS") {
+      self.check();
+    }
+    Ok(())
+  }
+}
+
+impl OutputTarget for ReportChecker {}
+
+#[test_case]
+fn test_message_buffer_truncates() {
+  use fmt::Write;
+
+  let mut message = MessageBuffer::<8>::new();
+  write!(message, "{} bytes", 12345).unwrap();
+  assert_eq!(message.as_str(), "12345 by");
+}
+
+#[cfg(test)]
+mod test_shuffle {
+  use super::*;
+
+  #[test_case]
+  fn seeds_change_the_order() {
+    let (mut first, mut second) = ([0; 32], [0; 32]);
+    shuffled_order(&mut first, 1);
+    shuffled_order(&mut second, 2);
+    assert_ne!(first, second);
+
+    // and the same seed replays the same order
+    shuffled_order(&mut second, 1);
+    assert_eq!(first, second);
+  }
+}
@@ -0,0 +1,288 @@
+use core::{
+  ops::{Add, AddAssign, Sub},
+  sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+/// Input frequency of the PIT (in Hz)
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// Timer interrupt frequency at boot (in mHz): the PIT default of `PIT_FREQUENCY / 65536`
+pub const DEFAULT_TIMER_FREQUENCY_MHZ: u32 = 18_207;
+
+/// Current timer interrupt frequency (in mHz)
+static TIMER_FREQUENCY_MHZ: AtomicU32 = AtomicU32::new(DEFAULT_TIMER_FREQUENCY_MHZ);
+
+/// PIT mode/command register
+const PIT_COMMAND_PORT: u16 = 0x43;
+/// PIT channel 0 (timer interrupt) data port
+const PIT_CHANNEL_0_PORT: u16 = 0x40;
+/// Channel 0, access lo/hi byte, mode 2 (rate generator), binary
+const PIT_CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+/// Lowest timer frequency (in Hz) `set_timer_frequency` accepts, the divisor must fit in 16 bits
+pub const MIN_TIMER_FREQUENCY: u32 = 19;
+/// Highest timer frequency (in Hz) `set_timer_frequency` accepts
+pub const MAX_TIMER_FREQUENCY: u32 = 1000;
+
+/// PIT divisor closest to `hz` (clamped to `MIN_TIMER_FREQUENCY..=MAX_TIMER_FREQUENCY`)
+fn timer_divisor(hz: u32) -> u16 {
+  let hz = hz.clamp(MIN_TIMER_FREQUENCY, MAX_TIMER_FREQUENCY);
+  // rounded to the nearest, e.g. 11932 (not 11931) for 100 Hz
+  ((PIT_FREQUENCY + hz / 2) / hz) as u16
+}
+
+/// Frequency (in mHz) the PIT produces with `divisor`, rounded to the nearest
+fn divisor_frequency_mhz(divisor: u16) -> u32 {
+  let divisor = divisor as u64;
+  ((PIT_FREQUENCY as u64 * 1000 + divisor / 2) / divisor) as u32
+}
+
+/// Reprogram the timer interrupt (PIT channel 0) to fire at about `hz`
+///
+/// `hz` is clamped to `MIN_TIMER_FREQUENCY..=MAX_TIMER_FREQUENCY`, and the
+/// exact resulting frequency is what `timer_frequency_mhz` reports. Timeouts
+/// already computed in ticks (e.g. pending `sleep`s) are not rescaled.
+pub fn set_timer_frequency(hz: u32) {
+  use crate::io::outb;
+
+  let divisor = timer_divisor(hz);
+  let [low, high] = divisor.to_le_bytes();
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    // keep `uptime_ms` continuous across the change
+    UPTIME_BASE_MS.store(uptime_ms(), Ordering::Relaxed);
+    UPTIME_BASE_TICKS.store(ticks(), Ordering::Relaxed);
+    // SAFETY: reprograms PIT channel 0, whose only user is the timer interrupt
+    unsafe {
+      outb(PIT_COMMAND_PORT, PIT_CHANNEL_0_RATE_GENERATOR);
+      outb(PIT_CHANNEL_0_PORT, low);
+      outb(PIT_CHANNEL_0_PORT, high);
+    }
+    TIMER_FREQUENCY_MHZ.store(divisor_frequency_mhz(divisor), Ordering::Relaxed);
+  });
+}
+
+/// Frequency of the timer interrupt (in mHz, i.e. ticks per 1000 seconds)
+pub fn timer_frequency_mhz() -> u32 {
+  TIMER_FREQUENCY_MHZ.load(Ordering::Relaxed)
+}
+
+/// Convert `ticks` to milliseconds at the current timer frequency
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+  ticks_to_ms_at(ticks, timer_frequency_mhz())
+}
+
+/// Convert `ms` to ticks (rounded up) at the current timer frequency
+pub fn ms_to_ticks(ms: u64) -> u64 {
+  ms_to_ticks_at(ms, timer_frequency_mhz())
+}
+
+/// `ticks` in milliseconds at `frequency_mhz`
+///
+/// Computed in `u128`, saturating at `u64::MAX` (also when the frequency is `0`).
+fn ticks_to_ms_at(ticks: u64, frequency_mhz: u32) -> u64 {
+  if frequency_mhz == 0 {
+    return u64::MAX;
+  }
+  let ms = ticks as u128 * 1_000_000 / frequency_mhz as u128;
+  ms.try_into().unwrap_or(u64::MAX)
+}
+
+/// `ms` in ticks at `frequency_mhz`, rounded up (so sleeps never end early)
+fn ms_to_ticks_at(ms: u64, frequency_mhz: u32) -> u64 {
+  let ticks = (ms as u128 * frequency_mhz as u128).div_ceil(1_000_000);
+  ticks.try_into().unwrap_or(u64::MAX)
+}
+
+/// Number of timer interrupts received since `interrupts` were enabled
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Current value of the monotonic tick counter
+pub fn ticks() -> u64 {
+  TICKS.load(Ordering::Relaxed)
+}
+
+/// `uptime_ms` and `ticks` at the last `set_timer_frequency`
+static UPTIME_BASE_MS: AtomicU64 = AtomicU64::new(0);
+static UPTIME_BASE_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Milliseconds since `interrupts` were enabled, as counted by the timer
+///
+/// Accounts for each `set_timer_frequency`, so it stays monotonic.
+pub fn uptime_ms() -> u64 {
+  let since_base = ticks().saturating_sub(UPTIME_BASE_TICKS.load(Ordering::Relaxed));
+  UPTIME_BASE_MS
+    .load(Ordering::Relaxed)
+    .saturating_add(ticks_to_ms(since_base))
+}
+
+/// A point on the monotonic tick clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+  /// The current tick
+  pub fn now() -> Self {
+    Self(ticks())
+  }
+
+  pub const fn from_ticks(ticks: u64) -> Self {
+    Self(ticks)
+  }
+
+  pub const fn ticks(self) -> u64 {
+    self.0
+  }
+
+  /// Ticks passed since `self` (`0` if `self` is in the future)
+  pub fn elapsed(self) -> u64 {
+    ticks().saturating_sub(self.0)
+  }
+}
+
+/// Saturates at the last representable tick ("never")
+impl Add<u64> for Instant {
+  type Output = Instant;
+
+  fn add(self, ticks: u64) -> Instant {
+    Instant(self.0.saturating_add(ticks))
+  }
+}
+
+impl AddAssign<u64> for Instant {
+  fn add_assign(&mut self, ticks: u64) {
+    *self = *self + ticks;
+  }
+}
+
+impl Sub for Instant {
+  type Output = u64;
+
+  /// Ticks between two instants (saturating at `0`)
+  fn sub(self, earlier: Instant) -> u64 {
+    self.0.saturating_sub(earlier.0)
+  }
+}
+
+/// Called by the timer interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn tick() {
+  #[cfg(test)]
+  if FROZEN.load(Ordering::Relaxed) {
+    return;
+  }
+  TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Whether timer interrupts leave the tick counter alone, see `freeze`
+#[cfg(test)]
+static FROZEN: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// Stop timer interrupts from advancing the tick counter, until `unfreeze`
+///
+/// Gives tests a stable tick baseline, `advance_ticks` still works meanwhile.
+#[cfg(test)]
+pub fn freeze() {
+  FROZEN.store(true, Ordering::Relaxed);
+}
+
+/// Let timer interrupts advance the tick counter again
+#[cfg(test)]
+pub fn unfreeze() {
+  FROZEN.store(false, Ordering::Relaxed);
+}
+
+/// Fast-forward virtual time by `n` ticks, waking every timer that becomes due
+///
+/// Lets tests of time-dependent code run deterministically, without waiting
+/// for real timer interrupts.
+#[cfg(test)]
+pub fn advance_ticks(n: u64) {
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    let now = TICKS.fetch_add(n, Ordering::Relaxed) + n;
+    crate::task::timer::wake_expired(now);
+  });
+}
+
+/// Halt the cpu until at least `n` more ticks have passed
+pub fn wait_ticks(n: u64) {
+  let target = ticks() + n;
+  while ticks() < target {
+    x86_64::instructions::hlt();
+  }
+}
+
+#[test_case]
+fn test_freeze() {
+  freeze();
+  let start = Instant::now();
+  // every timer interrupt ends a `hlt`
+  for _ in 0..5 {
+    x86_64::instructions::hlt();
+  }
+  let frozen_elapsed = start.elapsed();
+  advance_ticks(3);
+  let advanced = start.elapsed();
+  unfreeze();
+
+  assert_eq!(frozen_elapsed, 0);
+  assert_eq!(advanced, 3);
+  wait_ticks(2);
+  assert!(start.elapsed() >= 5);
+}
+
+#[test_case]
+fn test_ticks_advance() {
+  let (start, uptime_start) = (ticks(), uptime_ms());
+  while ticks() == start {
+    core::hint::spin_loop();
+  }
+  assert!(ticks() > start);
+  assert!(uptime_ms() > uptime_start);
+}
+
+#[cfg(test)]
+mod test_timer_frequency {
+  use super::*;
+
+  #[test_case]
+  fn divisor_is_rounded() {
+    assert_eq!(timer_divisor(100), 11932);
+    assert_eq!(divisor_frequency_mhz(11932), 99_998);
+    assert_eq!(timer_divisor(1000), 1193);
+  }
+
+  #[test_case]
+  fn frequency_is_clamped() {
+    assert_eq!(timer_divisor(0), timer_divisor(MIN_TIMER_FREQUENCY));
+    assert_eq!(timer_divisor(MIN_TIMER_FREQUENCY), 62_799);
+    assert_eq!(timer_divisor(u32::MAX), timer_divisor(MAX_TIMER_FREQUENCY));
+  }
+}
+
+#[cfg(test)]
+mod test_conversion {
+  use super::*;
+
+  /// 100 Hz
+  const FREQUENCY: u32 = 100_000;
+
+  #[test_case]
+  fn exact_at_large_values() {
+    let ticks = u64::MAX / 1000;
+    assert_eq!(ticks_to_ms_at(ticks, FREQUENCY), ticks * 10);
+    assert_eq!(ms_to_ticks_at(ticks * 10, FREQUENCY), ticks);
+    assert_eq!(ms_to_ticks_at(u64::MAX, FREQUENCY), u64::MAX / 10 + 1);
+    // ~18.2 Hz: one tick is ~54.9 ms
+    assert_eq!(ticks_to_ms_at(1, DEFAULT_TIMER_FREQUENCY_MHZ), 54);
+    assert_eq!(ms_to_ticks_at(55, DEFAULT_TIMER_FREQUENCY_MHZ), 2);
+  }
+
+  #[test_case]
+  fn saturates() {
+    assert_eq!(ticks_to_ms_at(u64::MAX, FREQUENCY), u64::MAX);
+    assert_eq!(ticks_to_ms_at(1, 0), u64::MAX);
+    assert_eq!(ms_to_ticks_at(1000, 0), 0);
+    assert_eq!(ms_to_ticks_at(u64::MAX, u32::MAX), u64::MAX);
+  }
+}
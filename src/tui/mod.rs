@@ -0,0 +1,133 @@
+use crate::{
+  task::keyboard::KeyStream,
+  vga_buffer::{BUFFER_HEIGHT, WRITER},
+};
+use alloc::format;
+use futures_util::{Stream, StreamExt};
+use pc_keyboard::{DecodedKey, KeyCode};
+use x86_64::instructions::interrupts;
+
+/// A vertical list of items, navigated with `Up`/`Down`
+///
+/// The current selection is highlighted in reverse video.
+pub struct Menu<'a> {
+  items: &'a [&'a str],
+  top: usize,
+  left: usize,
+  selected: usize,
+}
+
+impl<'a> Menu<'a> {
+  /// Create a menu drawn at the top-left corner of the screen
+  pub fn new(items: &'a [&'a str]) -> Self {
+    Self {
+      items,
+      top: 0,
+      left: 0,
+      selected: 0,
+    }
+  }
+
+  /// Draw the menu starting at (`top`, `left`)
+  pub fn at(mut self, top: usize, left: usize) -> Self {
+    self.top = top;
+    self.left = left;
+    self
+  }
+
+  /// Get the index of the currently selected item
+  pub fn selected(&self) -> usize {
+    self.selected
+  }
+
+  /// Show the menu and let the user pick an item with the keyboard
+  ///
+  /// Returns the chosen index on `Enter`, or `None` on `Escape`.
+  pub async fn run(&mut self) -> Option<usize> {
+    self.run_with(&mut KeyStream::new()).await
+  }
+
+  /// Same as `run`, but reads the keys from `keys`
+  ///
+  /// The covered screen region (and the cursor) is restored on exit.
+  pub async fn run_with(
+    &mut self,
+    keys: &mut (impl Stream<Item = DecodedKey> + Unpin),
+  ) -> Option<usize> {
+    let saved = interrupts::without_interrupts(|| WRITER.lock().save_state());
+    self.draw();
+
+    let chosen = loop {
+      let Some(key) = keys.next().await else {
+        break None;
+      };
+      match key {
+        DecodedKey::RawKey(KeyCode::ArrowUp) => {
+          self.selected = self.selected.saturating_sub(1);
+          self.draw();
+        }
+        DecodedKey::RawKey(KeyCode::ArrowDown) => {
+          if self.selected + 1 < self.items.len() {
+            self.selected += 1;
+          }
+          self.draw();
+        }
+        DecodedKey::RawKey(KeyCode::Return) | DecodedKey::Unicode('\n') => {
+          break Some(self.selected);
+        }
+        DecodedKey::RawKey(KeyCode::Escape) | DecodedKey::Unicode('\x1b') => break None,
+        _ => {}
+      }
+    };
+
+    interrupts::without_interrupts(|| WRITER.lock().restore_state(&saved));
+    chosen.filter(|&index| index < self.items.len())
+  }
+
+  fn draw(&self) {
+    let width = self.items.iter().map(|item| item.len()).max().unwrap_or(0);
+    interrupts::without_interrupts(|| {
+      let mut writer = WRITER.lock();
+      let normal = writer.color_code();
+      let mut highlight = normal;
+      highlight.invert();
+      let rows = (self.top..BUFFER_HEIGHT).zip(self.items.iter().enumerate());
+      for (row, (index, item)) in rows {
+        let color_code = if index == self.selected {
+          highlight
+        } else {
+          normal
+        };
+        let line = format!(" {:<width$} ", item, width = width);
+        writer.write_str_at(row, self.left, &line, color_code);
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod test_menu {
+  use super::*;
+  use crate::task::{executor::Executor, keyboard, Task};
+  use core::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test_case]
+  fn arrows_and_enter_choose_item() {
+    static CHOSEN: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    let mut keys = KeyStream::new();
+    // `Down`, `Down`, `Up`, `Down`, `Enter` (scancode set 1, key presses only)
+    for scancode in [0xE0, 0x50, 0xE0, 0x50, 0xE0, 0x48, 0xE0, 0x50, 0x1C] {
+      keyboard::add_scancode(scancode);
+    }
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+      let items = ["first", "second", "third", "fourth"];
+      let chosen = Menu::new(&items).at(2, 10).run_with(&mut keys).await;
+      CHOSEN.store(chosen.unwrap_or(usize::MAX), Ordering::SeqCst);
+    }));
+    executor.run_until_all_task_finished();
+    assert_eq!(CHOSEN.load(Ordering::SeqCst), 2);
+  }
+}
@@ -1,2 +1,4 @@
 pub mod algorithms;
 pub mod collections;
+pub mod rand;
+pub mod table;
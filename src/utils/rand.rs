@@ -0,0 +1,61 @@
+/// A small, seedable pseudo-random number generator (`xorshift64*`)
+///
+/// Fast and reproducible, but **not** cryptographically secure.
+#[derive(Debug, Clone)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  /// Create a generator, the same `seed` always yields the same sequence
+  pub fn new(seed: u64) -> Self {
+    let state = seed ^ 0x9E37_79B9_7F4A_7C15;
+    // the all-zero state is a fixed point of xorshift
+    Self {
+      state: if state == 0 { 1 } else { state },
+    }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+    self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  /// A number in `0..bound` (`bound` must not be `0`)
+  pub fn below(&mut self, bound: usize) -> usize {
+    assert!(bound > 0, "`bound` must not be 0!\n");
+    (self.next_u64() % bound as u64) as usize
+  }
+
+  /// Shuffle `slice` in place (Fisher-Yates)
+  pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+    for i in (1..slice.len()).rev() {
+      slice.swap(i, self.below(i + 1));
+    }
+  }
+}
+
+#[cfg(test)]
+mod test_rand {
+  use super::*;
+
+  #[test_case]
+  fn same_seed_same_sequence() {
+    let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+    for _ in 0..16 {
+      assert_eq!(a.next_u64(), b.next_u64());
+    }
+    assert_ne!(Rng::new(0).next_u64(), Rng::new(1).next_u64());
+  }
+
+  #[test_case]
+  fn shuffle_is_permutation() {
+    let mut values = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+    Rng::new(7).shuffle(&mut values);
+    let mut sorted = values;
+    sorted.sort_unstable();
+    assert_eq!(sorted, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+  }
+}
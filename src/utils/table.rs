@@ -0,0 +1,206 @@
+use crate::vga_buffer::BUFFER_WIDTH;
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+/// Marker of a truncated cell
+///
+/// ASCII, so it takes a single screen cell (`…` would be printed as one `■`
+/// per UTF-8 byte, and misalign the table).
+const ELLIPSIS: char = '~';
+
+/// A text table with per-column alignment
+///
+/// ```rust
+/// let mut table = Table::new().with_header(["name", "size"]).bordered(true);
+/// table.push_row(["heap", "512 KiB"]);
+/// println!("{}", table);
+/// ```
+///
+/// Cells are padded to the widest cell of their column. When the table
+/// would be wider than `max_width` (default: the screen width), the widest
+/// columns are narrowed and their overlong cells end with `~`.
+pub struct Table {
+  header: Option<Vec<String>>,
+  rows: Vec<Vec<String>>,
+  bordered: bool,
+  max_width: usize,
+}
+
+impl Default for Table {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Table {
+  pub fn new() -> Self {
+    Self {
+      header: None,
+      rows: vec![],
+      bordered: false,
+      max_width: BUFFER_WIDTH,
+    }
+  }
+
+  /// Set the header row (separated from the other rows by a rule)
+  pub fn with_header<I, S>(mut self, columns: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self.header = Some(columns.into_iter().map(Into::into).collect());
+    self
+  }
+
+  /// Draw `+---+` borders around the table and between columns
+  pub fn bordered(mut self, bordered: bool) -> Self {
+    self.bordered = bordered;
+    self
+  }
+
+  /// Set the maximum rendered width of a line
+  pub fn max_width(mut self, max_width: usize) -> Self {
+    self.max_width = max_width;
+    self
+  }
+
+  /// Append a row
+  pub fn push_row<I, S>(&mut self, columns: I)
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self
+      .rows
+      .push(columns.into_iter().map(Into::into).collect());
+  }
+
+  fn all_rows(&self) -> impl Iterator<Item = &Vec<String>> {
+    self.header.iter().chain(self.rows.iter())
+  }
+
+  /// Compute the width of each column, after narrowing to `max_width`
+  fn column_widths(&self) -> Vec<usize> {
+    let columns = self.all_rows().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in self.all_rows() {
+      for (width, cell) in widths.iter_mut().zip(row) {
+        *width = (*width).max(cell.chars().count());
+      }
+    }
+
+    let decoration = if self.bordered {
+      3 * columns + 1
+    } else {
+      2 * columns.saturating_sub(1)
+    };
+    while widths.iter().sum::<usize>() + decoration > self.max_width {
+      match widths.iter_mut().max() {
+        Some(widest) if *widest > 1 => *widest -= 1,
+        _ => break,
+      }
+    }
+    widths
+  }
+
+  fn fmt_rule(f: &mut fmt::Formatter, widths: &[usize]) -> fmt::Result {
+    for width in widths {
+      write!(f, "+{:-<1$}", "", width + 2)?;
+    }
+    writeln!(f, "+")
+  }
+
+  fn fmt_row(&self, f: &mut fmt::Formatter, widths: &[usize], row: &[String]) -> fmt::Result {
+    for (i, &width) in widths.iter().enumerate() {
+      let cell = row.get(i).map_or("", String::as_str);
+      let last = i + 1 == widths.len();
+      if self.bordered {
+        f.write_str("| ")?;
+      } else if i > 0 {
+        f.write_str("  ")?;
+      }
+
+      let len = cell.chars().count();
+      if len > width {
+        // the marker takes the last column
+        let kept: String = cell.chars().take(width - 1).collect();
+        write!(f, "{}{}", kept, ELLIPSIS)?;
+      } else if self.bordered || !last {
+        write!(f, "{}{:2$}", cell, "", width - len)?;
+      } else {
+        f.write_str(cell)?;
+      }
+
+      if self.bordered {
+        f.write_str(" ")?;
+      }
+    }
+    if self.bordered {
+      f.write_str("|")?;
+    }
+    writeln!(f)
+  }
+}
+
+impl fmt::Display for Table {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let widths = self.column_widths();
+    if self.bordered {
+      Self::fmt_rule(f, &widths)?;
+    }
+    if let Some(header) = &self.header {
+      self.fmt_row(f, &widths, header)?;
+      if self.bordered {
+        Self::fmt_rule(f, &widths)?;
+      } else {
+        let total = widths.iter().sum::<usize>() + 2 * widths.len().saturating_sub(1);
+        writeln!(f, "{:-<1$}", "", total)?;
+      }
+    }
+    for row in &self.rows {
+      self.fmt_row(f, &widths, row)?;
+    }
+    if self.bordered {
+      Self::fmt_rule(f, &widths)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test_table {
+  use super::*;
+  use alloc::{format, vec::Vec};
+
+  #[test_case]
+  fn aligned_columns() {
+    let mut table = Table::new().with_header(["id", "name", "size"]);
+    table.push_row(["0", "heap", "512 KiB"]);
+    table.push_row(["10", "vga", "4000 B"]);
+    let rendered = format!("{}", table);
+    let lines = rendered.lines().collect::<Vec<_>>();
+    assert_eq!(
+      lines,
+      [
+        "id  name  size",
+        "-----------------",
+        "0   heap  512 KiB",
+        "10  vga   4000 B",
+      ]
+    );
+  }
+
+  #[test_case]
+  fn bordered_and_truncated() {
+    let mut table = Table::new().bordered(true).max_width(16);
+    table.push_row(["a", "abcdefghijklmnop"]);
+    let rendered = format!("{}", table);
+    let lines = rendered.lines().collect::<Vec<_>>();
+    assert_eq!(
+      lines,
+      ["+---+----------+", "| a | abcdefg~ |", "+---+----------+",]
+    );
+    // one screen cell per byte: every line is exactly `max_width` cells
+    assert!(lines.iter().all(|line| line.len() == 16));
+  }
+}
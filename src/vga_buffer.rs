@@ -1,4 +1,10 @@
-use core::fmt;
+use crate::interrupts::InterruptGuard;
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{
+  fmt,
+  ops::RangeInclusive,
+  sync::atomic::{AtomicU8, Ordering},
+};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
@@ -26,9 +32,15 @@ pub enum Color {
   White = 15,
 }
 
-impl From<u8> for Color {
-  fn from(value: u8) -> Self {
-    match value {
+/// A color byte outside of `0..=15`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidColor(pub u8);
+
+impl TryFrom<u8> for Color {
+  type Error = InvalidColor;
+
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    Ok(match value {
       0 => Self::Black,
       1 => Self::Blue,
       2 => Self::Green,
@@ -45,8 +57,56 @@ impl From<u8> for Color {
       13 => Self::Pink,
       14 => Self::Yellow,
       15 => Self::White,
-      _ => Self::Black,
-    }
+      _ => return Err(InvalidColor(value)),
+    })
+  }
+}
+
+impl Color {
+  /// Lossy `try_from`: values outside of `0..=15` become `Black`
+  pub fn from_lossy(value: u8) -> Self {
+    Self::try_from(value).unwrap_or(Self::Black)
+  }
+}
+
+/// RGB values of the 16 colors in the standard CGA palette, indexed by `Color as u8`
+const CGA_PALETTE: [(u8, u8, u8); 16] = [
+  (0x00, 0x00, 0x00),
+  (0x00, 0x00, 0xAA),
+  (0x00, 0xAA, 0x00),
+  (0x00, 0xAA, 0xAA),
+  (0xAA, 0x00, 0x00),
+  (0xAA, 0x00, 0xAA),
+  (0xAA, 0x55, 0x00),
+  (0xAA, 0xAA, 0xAA),
+  (0x55, 0x55, 0x55),
+  (0x55, 0x55, 0xFF),
+  (0x55, 0xFF, 0x55),
+  (0x55, 0xFF, 0xFF),
+  (0xFF, 0x55, 0x55),
+  (0xFF, 0x55, 0xFF),
+  (0xFF, 0xFF, 0x55),
+  (0xFF, 0xFF, 0xFF),
+];
+
+impl Color {
+  /// Get the closest color to (`r`, `g`, `b`), by euclidean distance to the CGA palette
+  pub fn nearest(r: u8, g: u8, b: u8) -> Color {
+    let distance = |&(pr, pg, pb): &(u8, u8, u8)| {
+      let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2);
+      d(r, pr) + d(g, pg) + d(b, pb)
+    };
+    let (index, _) = CGA_PALETTE
+      .iter()
+      .enumerate()
+      .min_by_key(|(_, rgb)| distance(rgb))
+      .unwrap();
+    Self::from_lossy(index as u8)
+  }
+
+  /// Get the RGB value of `self` in the CGA palette
+  pub fn rgb(self) -> (u8, u8, u8) {
+    CGA_PALETTE[self as usize]
   }
 }
 
@@ -63,14 +123,56 @@ impl From<Color> for u8 {
 /// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
-#[allow(dead_code)]
 impl ColorCode {
-  fn new(foreground: Color, background: Color) -> Self {
+  pub fn new(foreground: Color, background: Color) -> Self {
     Self(((background as u8) << 4) | (foreground as u8))
   }
 
+  /// Rebuild from the packed attribute byte
+  pub fn from_byte(byte: u8) -> Self {
+    Self(byte)
+  }
+
+  /// Get the packed attribute byte
+  pub fn as_byte(&self) -> u8 {
+    self.0
+  }
+
+  pub fn fg(&self) -> Color {
+    Color::from_lossy(self.get_foreground())
+  }
+
+  pub fn bg(&self) -> Color {
+    Color::from_lossy(self.get_background())
+  }
+
+  pub fn set_fg(&mut self, foreground: Color) {
+    self.set_foreground(foreground);
+  }
+
+  pub fn set_bg(&mut self, background: Color) {
+    self.set_background(background);
+  }
+
+  /// Set or clear bit 7, which blinks the cell (see `set_blink_enabled`)
+  pub fn set_blink(&mut self, on: bool) {
+    match on {
+      true => self.0 |= 0x80,
+      false => self.0 &= !0x80,
+    }
+  }
+
+  /// Swap `foreground` and `background`
+  pub fn invert(&mut self) {
+    let (foreground, background) = self.decrypt();
+    *self = Self::new_raw(background, foreground);
+  }
+}
+
+#[allow(dead_code)]
+impl ColorCode {
   fn new_raw(foreground: u8, background: u8) -> Self {
     Self((background << 4) | foreground)
   }
@@ -111,8 +213,15 @@ pub(crate) struct ScreenChar {
   color_code: ColorCode,
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+impl ScreenChar {
+  const BLANK: Self = Self {
+    ascii_char: b' ',
+    color_code: ColorCode(0),
+  };
+}
+
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
 
 /// VGA Buffer
 #[repr(transparent)]
@@ -127,24 +236,200 @@ impl Buffer {
   }
 }
 
+/// CRT controller index register
+const CRTC_INDEX: u16 = 0x3D4;
+/// CRT controller data register
+const CRTC_DATA: u16 = 0x3D5;
+const CURSOR_START: u8 = 0x0A;
+const CURSOR_END: u8 = 0x0B;
+const CURSOR_LOCATION_HIGH: u8 = 0x0E;
+const CURSOR_LOCATION_LOW: u8 = 0x0F;
+/// Attribute controller index (and data) register, written alternately
+const ATTRIBUTE_INDEX: u16 = 0x3C0;
+/// Attribute controller data register, for reading
+const ATTRIBUTE_DATA_READ: u16 = 0x3C1;
+/// Reading it resets `ATTRIBUTE_INDEX` to expect an index
+const INPUT_STATUS_1: u16 = 0x3DA;
+/// Keeps the screen on while an attribute register is selected
+const PALETTE_ADDRESS_SOURCE: u8 = 0x20;
+const ATTRIBUTE_MODE_CONTROL: u8 = 0x10;
+/// Bit of `ATTRIBUTE_MODE_CONTROL`: attribute bit 7 blinks instead of brightening the background
+const BLINK_ENABLE: u8 = 1 << 3;
+
+/// What happens to output past the last column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+  /// Continue on the next line
+  Wrap,
+  /// Drop it, until the next `\n`
+  Truncate,
+}
+
 pub struct Writer {
   row_pos: usize,
   col_pos: usize,
   color_code: ColorCode,
+  /// Rows moved by `new_line` (rows outside stay put)
+  scroll_region: RangeInclusive<usize>,
+  /// Break lines at whitespace rather than mid-word
+  word_wrap: bool,
+  wrap_mode: WrapMode,
+  /// Backspace at column 0 does nothing, instead of wrapping to the previous row
+  backspace_stops_at_col0: bool,
+  /// `\t` advances to the next multiple of `tab_width`
+  tab_width: usize,
+  /// Word being buffered while `word_wrap` is on
+  pending_word: [u8; BUFFER_WIDTH],
+  pending_len: usize,
+  /// Progress through an ANSI escape sequence
+  ansi_state: AnsiState,
+  /// Parameter bytes of the CSI sequence being parsed
+  ansi_params: [u8; 16],
+  ansi_len: usize,
+  /// Rows scrolled off the top of the screen
+  history: &'static mut Scrollback,
+  /// How many rows the view is scrolled back into `history` (`0` => live output)
+  scroll_offset: usize,
+  /// The live screen, kept aside while scrolled back (`scroll_offset > 0`)
+  live_screen: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
   buffer: &'static mut Buffer,
 }
 
+/// Where `write_string` is within an ANSI escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+  /// Plain text
+  Ground,
+  /// After `ESC`
+  Escape,
+  /// After `ESC [`, collecting parameters up to the final byte
+  Csi,
+}
+
+/// VGA colors of the ANSI colors `0..=7`, and of their bright variants
+const ANSI_COLORS: [Color; 8] = [
+  Color::Black,
+  Color::Red,
+  Color::Green,
+  Color::Brown,
+  Color::Blue,
+  Color::Magenta,
+  Color::Cyan,
+  Color::LightGray,
+];
+const ANSI_BRIGHT_COLORS: [Color; 8] = [
+  Color::DarkGray,
+  Color::LightRed,
+  Color::LightGreen,
+  Color::Yellow,
+  Color::LightBlue,
+  Color::Pink,
+  Color::LightCyan,
+  Color::White,
+];
+
+/// Rows kept in the scrollback history
+pub const SCROLLBACK_LINES: usize = 512;
+
+/// Ring of the last `SCROLLBACK_LINES` rows scrolled off the screen
+///
+/// It is static, so printing never allocates (handlers may print while the
+/// code they interrupted holds the allocator).
+struct Scrollback {
+  rows: [[ScreenChar; BUFFER_WIDTH]; SCROLLBACK_LINES],
+  /// Index of the oldest row in `rows`
+  start: usize,
+  len: usize,
+}
+
+impl Scrollback {
+  /// Get the scrollback ring (return a &'static mut Self) only once
+  unsafe fn static_init() -> &'static mut Self {
+    static mut SCROLLBACK: Scrollback = Scrollback {
+      rows: [[ScreenChar::BLANK; BUFFER_WIDTH]; SCROLLBACK_LINES],
+      start: 0,
+      len: 0,
+    };
+    &mut *core::ptr::addr_of_mut!(SCROLLBACK)
+  }
+
+  fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Append `row`, dropping the oldest one when full
+  fn push(&mut self, row: [ScreenChar; BUFFER_WIDTH]) {
+    let end = (self.start + self.len) % SCROLLBACK_LINES;
+    self.rows[end] = row;
+    if self.len == SCROLLBACK_LINES {
+      self.start = (self.start + 1) % SCROLLBACK_LINES;
+    } else {
+      self.len += 1;
+    }
+  }
+
+  /// The `index`-th row, oldest first
+  fn get(&self, index: usize) -> Option<&[ScreenChar; BUFFER_WIDTH]> {
+    (index < self.len).then(|| &self.rows[(self.start + index) % SCROLLBACK_LINES])
+  }
+}
+
 lazy_static! {
+  /// The screen writer
+  ///
+  /// Initialized on first access, which must not be interrupted by a handler
+  /// printing itself (the `Once` would spin forever). `init` forces it while
+  /// interrupts are still disabled, and every printing path locks it inside
+  /// `without_interrupts`.
   pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
     row_pos: BUFFER_HEIGHT - 1,
     col_pos: 0,
     color_code: ColorCode::new(Color::White, Color::Black),
+    scroll_region: 0..=BUFFER_HEIGHT - 1,
+    word_wrap: false,
+    wrap_mode: WrapMode::Wrap,
+    backspace_stops_at_col0: false,
+    tab_width: 8,
+    pending_word: [0; BUFFER_WIDTH],
+    pending_len: 0,
+    ansi_state: AnsiState::Ground,
+    ansi_params: [0; 16],
+    ansi_len: 0,
+    history: unsafe { Scrollback::static_init() },
+    scroll_offset: 0,
+    live_screen: [[ScreenChar::BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT],
     buffer: unsafe { Buffer::static_init() },
   });
 }
 
+/// Force the initialization of `WRITER`, and select blinking for attribute bit 7
+///
+/// Must run before interrupts are enabled, see `WRITER`.
+pub fn init() {
+  lazy_static::initialize(&WRITER);
+  set_blink_enabled(true);
+}
+
 impl Writer {
+  /// Make backspace at column 0 do nothing ("protected" mode, for shells)
+  ///
+  /// Otherwise (the default) it wraps to the last column of the previous row.
+  pub fn set_backspace_stops_at_col0(&mut self, stops: bool) {
+    self.backspace_stops_at_col0 = stops;
+  }
+
+  /// Put tab stops every `tab_width` columns (clamped to `1..=BUFFER_WIDTH`)
+  pub fn set_tab_width(&mut self, tab_width: usize) {
+    self.tab_width = tab_width.clamp(1, BUFFER_WIDTH);
+  }
+
   pub fn enforce_backspace(&mut self) {
+    if self.scroll_offset > 0 {
+      self.scroll_to_bottom();
+    }
+    if self.col_pos == 0 && self.backspace_stops_at_col0 {
+      return;
+    }
     if self.col_pos > 0 {
       self.col_pos -= 1;
     } else {
@@ -157,50 +442,211 @@ impl Writer {
       ascii_char: b' ',
       color_code: self.color_code,
     });
+    self.update_cursor();
   }
 
   /// Write a byte on the screen (in one line)
   pub fn write_byte(&mut self, byte: u8) {
+    if self.scroll_offset > 0 {
+      self.scroll_to_bottom();
+    }
     match byte {
       b'\n' => self.new_line(),
       b'\r' => self.clear_row(self.row_pos),
       b'\t' => {
-        for _ in 0..4 {
-          self.write_byte(b' ');
+        let truncate = self.wrap_mode == WrapMode::Truncate;
+        if self.col_pos >= BUFFER_WIDTH && !truncate {
+          self.new_line();
+        }
+        let next_stop = (self.col_pos / self.tab_width + 1) * self.tab_width;
+        if next_stop > BUFFER_WIDTH && !truncate {
+          self.new_line();
+        } else {
+          while self.col_pos < next_stop.min(BUFFER_WIDTH) {
+            self.write_byte(b' ');
+          }
         }
       }
       byte => {
-        if self.col_pos >= BUFFER_WIDTH {
-          self.new_line();
+        if !self.write_cell(byte) {
+          return;
+        }
+      }
+    }
+    self.update_cursor();
+  }
+
+  /// Put `byte` (as is) in the next cell, wrapping first if the row is full
+  ///
+  /// Returns `false` if it was dropped (a full row in `WrapMode::Truncate`).
+  fn write_cell(&mut self, byte: u8) -> bool {
+    if self.col_pos >= BUFFER_WIDTH {
+      if self.wrap_mode == WrapMode::Truncate {
+        return false;
+      }
+      self.new_line();
+    }
+    self.buffer.chars[self.row_pos][self.col_pos].write(ScreenChar {
+      ascii_char: byte,
+      color_code: self.color_code,
+    });
+    self.col_pos += 1;
+    true
+  }
+
+  /// Write `bytes` verbatim as code page 437 glyphs (e.g. shades or box-drawing)
+  ///
+  /// Only `\n` is interpreted, nothing is sanitized or parsed as ANSI escapes.
+  pub fn write_raw_bytes(&mut self, bytes: &[u8]) {
+    self.flush_word();
+    if self.scroll_offset > 0 {
+      self.scroll_to_bottom();
+    }
+    for &byte in bytes {
+      match byte {
+        b'\n' => self.new_line(),
+        byte => {
+          self.write_cell(byte);
         }
-        self.buffer.chars[self.row_pos][self.col_pos].write(ScreenChar {
-          ascii_char: byte,
-          color_code: self.color_code,
-        });
-        self.col_pos += 1;
       }
     }
+    self.update_cursor();
+  }
+
+  /// Move the blinking hardware cursor to (`row_pos`, `col_pos`)
+  ///
+  /// A full row leaves it on the last column.
+  fn update_cursor(&mut self) {
+    let col = self.col_pos.min(BUFFER_WIDTH - 1);
+    let position = (self.row_pos * BUFFER_WIDTH + col) as u16;
+    // SAFETY: only the cursor location registers of the CRT controller change
+    unsafe {
+      crate::io::outb(CRTC_INDEX, CURSOR_LOCATION_LOW);
+      crate::io::outb(CRTC_DATA, position as u8);
+      crate::io::outb(CRTC_INDEX, CURSOR_LOCATION_HIGH);
+      crate::io::outb(CRTC_DATA, (position >> 8) as u8);
+    }
   }
 
-  /// Add a new line on the screen
+  /// Move to the start of the next row, scrolling the scroll region at its bottom
   fn new_line(&mut self) {
-    for row in 1..BUFFER_HEIGHT {
+    let (top, bottom) = (*self.scroll_region.start(), *self.scroll_region.end());
+    if (top..bottom).contains(&self.row_pos) {
+      self.row_pos += 1;
+      self.col_pos = 0;
+      self.update_cursor();
+      return;
+    }
+    if top == 0 {
+      self
+        .history
+        .push(self.buffer.chars[0].each_ref().map(|c| c.read()));
+    }
+    for row in top + 1..=bottom {
       for col in 0..BUFFER_WIDTH {
         let character = self.buffer.chars[row][col].read();
         self.buffer.chars[row - 1][col].write(character);
       }
     }
-    self.clear_row(BUFFER_HEIGHT - 1);
+    self.clear_row(bottom);
     self.col_pos = 0;
+    self.update_cursor();
+  }
+
+  /// Show `lines` older rows from the scrollback history
+  ///
+  /// Output (and backspace) snaps back to the live screen.
+  pub fn scroll_up(&mut self, lines: usize) {
+    if self.scroll_offset == 0 {
+      if self.history.len() == 0 {
+        return;
+      }
+      self.flush_word();
+      for (row, live_row) in self.live_screen.iter_mut().enumerate() {
+        for (col, c) in live_row.iter_mut().enumerate() {
+          *c = self.buffer.chars[row][col].read();
+        }
+      }
+    }
+    self.scroll_offset = self
+      .scroll_offset
+      .saturating_add(lines)
+      .min(self.history.len());
+    self.repaint_scrolled();
+  }
+
+  /// Show `lines` newer rows, back to live output at the bottom
+  pub fn scroll_down(&mut self, lines: usize) {
+    match self.scroll_offset.saturating_sub(lines) {
+      0 => self.scroll_to_bottom(),
+      offset => {
+        self.scroll_offset = offset;
+        self.repaint_scrolled();
+      }
+    }
+  }
+
+  /// Go back to the live screen
+  pub fn scroll_to_bottom(&mut self) {
+    if self.scroll_offset == 0 {
+      return;
+    }
+    self.scroll_offset = 0;
+    for (row, live_row) in self.live_screen.iter().enumerate() {
+      for (col, &c) in live_row.iter().enumerate() {
+        self.buffer.chars[row][col].write(c);
+      }
+    }
+  }
+
+  /// Paint the screen `scroll_offset` rows back into the history
+  fn repaint_scrolled(&mut self) {
+    let first = self.history.len() - self.scroll_offset;
+    for row in 0..BUFFER_HEIGHT {
+      let source = match self.history.get(first + row) {
+        Some(history_row) => history_row,
+        None => &self.live_screen[first + row - self.history.len()],
+      };
+      for (col, &c) in source.iter().enumerate() {
+        self.buffer.chars[row][col].write(c);
+      }
+    }
+  }
+
+  /// Restrict scrolling to `rows`, leaving the other rows untouched by output
+  ///
+  /// Output continues on a fresh line at the bottom of the region.
+  pub fn set_scroll_region(&mut self, rows: RangeInclusive<usize>) {
+    let bottom = (*rows.end()).min(BUFFER_HEIGHT - 1);
+    let top = (*rows.start()).min(bottom);
+    self.scroll_region = top..=bottom;
+    self.row_pos = bottom;
+    self.new_line();
+  }
+
+  /// Let the whole screen scroll again
+  pub fn reset_scroll_region(&mut self) {
+    self.set_scroll_region(0..=BUFFER_HEIGHT - 1);
   }
 
   /// Clear the lowest row (mostly used after called `vga_buffer::Writer::new_line()`)
   fn clear_row(&mut self, row: usize) {
+    self.clear_range(row, 0, BUFFER_WIDTH);
+  }
+
+  /// Blank the columns `start_col..end_col` of `row` with the current color
+  ///
+  /// The range is clamped to the screen, the cursor doesn't move.
+  pub fn clear_range(&mut self, row: usize, start_col: usize, end_col: usize) {
+    self.scroll_to_bottom();
+    if row >= BUFFER_HEIGHT {
+      return;
+    }
     let blank = ScreenChar {
       ascii_char: b' ',
       color_code: self.color_code,
     };
-    for col in 0..BUFFER_WIDTH {
+    for col in start_col..end_col.min(BUFFER_WIDTH) {
       self.buffer.chars[row][col].write(blank);
     }
   }
@@ -210,68 +656,723 @@ impl Writer {
   /// Write all bytes in a string on the screen
   /// (via calling `vga_buffer::Writer::write_byte()`)
   pub fn write_string(&mut self, s: &str) {
+    self.write_string_buffered(s);
+    self.flush_word();
+  }
+
+  /// Like `write_string`, but may keep the last (unfinished) word pending
+  fn write_string_buffered(&mut self, s: &str) {
     for byte in s.bytes() {
+      if self.consume_ansi(byte) {
+        continue;
+      }
       match byte {
-        // ASCII or '\n' => write it
-        0x20..=0x7e | b'\n' => self.write_byte(byte),
+        // ASCII, '\n' or '\t' => write it
+        0x20..=0x7e | b'\n' | b'\t' => self.put_byte(byte),
         // Illegal => write `■`
-        _ => self.write_byte(0xfe),
+        _ => self.put_byte(0xfe),
+      }
+    }
+  }
+
+  /// Feed `byte` to the ANSI escape parser, returning whether it was part of a sequence
+  ///
+  /// SGR sequences (`ESC [ ... m`) change the colors, other escapes are
+  /// dropped. The state survives between calls, so sequences may be split
+  /// across writes.
+  fn consume_ansi(&mut self, byte: u8) -> bool {
+    match self.ansi_state {
+      AnsiState::Ground if byte == 0x1b => self.ansi_state = AnsiState::Escape,
+      AnsiState::Ground => return false,
+      AnsiState::Escape if byte == b'[' => {
+        self.ansi_state = AnsiState::Csi;
+        self.ansi_len = 0;
+      }
+      // a two-byte escape
+      AnsiState::Escape => self.ansi_state = AnsiState::Ground,
+      AnsiState::Csi => match byte {
+        // parameters (extra ones are dropped)
+        0x30..=0x3f => {
+          if self.ansi_len < self.ansi_params.len() {
+            self.ansi_params[self.ansi_len] = byte;
+            self.ansi_len += 1;
+          }
+        }
+        // intermediate bytes
+        0x20..=0x2f => {}
+        b'm' => {
+          self.ansi_state = AnsiState::Ground;
+          self.apply_sgr();
+        }
+        // other final bytes, or garbage aborting the sequence
+        _ => self.ansi_state = AnsiState::Ground,
+      },
+    }
+    true
+  }
+
+  /// Apply the SGR parameters collected by `consume_ansi`
+  fn apply_sgr(&mut self) {
+    self.flush_word();
+    let params = core::mem::take(&mut self.ansi_params);
+    let params = &params[..core::mem::take(&mut self.ansi_len)];
+    for param in params.split(|&b| b == b';') {
+      // an empty parameter means 0
+      let Some(code) = core::str::from_utf8(param)
+        .ok()
+        .and_then(|code| match code {
+          "" => Some(0),
+          code => code.parse::<u8>().ok(),
+        })
+      else {
+        continue;
+      };
+      match code {
+        0 => self.color_code = ColorCode::default(),
+        30..=37 => self.color_code.set_fg(ANSI_COLORS[(code - 30) as usize]),
+        39 => self.color_code.set_fg(ColorCode::default().fg()),
+        40..=47 => self.color_code.set_bg(ANSI_COLORS[(code - 40) as usize]),
+        49 => self.color_code.set_bg(ColorCode::default().bg()),
+        90..=97 => self
+          .color_code
+          .set_fg(ANSI_BRIGHT_COLORS[(code - 90) as usize]),
+        100..=107 => self
+          .color_code
+          .set_bg(ANSI_BRIGHT_COLORS[(code - 100) as usize]),
+        _ => {}
+      }
+    }
+  }
+
+  /// Make further output blink (or stop blinking)
+  pub fn set_blink(&mut self, on: bool) {
+    self.flush_word();
+    self.color_code.set_blink(on);
+  }
+
+  /// Break lines at whitespace instead of mid-word (off by default)
+  ///
+  /// Words longer than a line still break hard.
+  pub fn set_word_wrap(&mut self, word_wrap: bool) {
+    self.flush_word();
+    self.word_wrap = word_wrap;
+  }
+
+  /// Wrap (the default) or truncate lines longer than the screen
+  pub fn set_wrap_mode(&mut self, wrap_mode: WrapMode) {
+    self.flush_word();
+    self.wrap_mode = wrap_mode;
+  }
+
+  /// `write_byte`, honoring `word_wrap`
+  fn put_byte(&mut self, byte: u8) {
+    if !self.word_wrap {
+      return self.write_byte(byte);
+    }
+    match byte {
+      b'\n' | b'\t' => {
+        self.flush_word();
+        self.write_byte(byte);
+      }
+      b' ' => {
+        self.flush_word();
+        // a space at the end of a line becomes the line break
+        if self.col_pos >= BUFFER_WIDTH && self.wrap_mode == WrapMode::Wrap {
+          self.new_line();
+        } else {
+          self.write_byte(byte);
+        }
+      }
+      byte => {
+        if self.pending_len == BUFFER_WIDTH {
+          self.flush_word();
+        }
+        self.pending_word[self.pending_len] = byte;
+        self.pending_len += 1;
+      }
+    }
+  }
+
+  /// Write the pending word, on a fresh line if it doesn't fit on this one
+  fn flush_word(&mut self) {
+    let len = core::mem::take(&mut self.pending_len);
+    if len == 0 {
+      return;
+    }
+    let wraps = self.wrap_mode == WrapMode::Wrap;
+    if wraps && self.col_pos > 0 && self.col_pos + len > BUFFER_WIDTH {
+      self.new_line();
+    }
+    for i in 0..len {
+      self.write_byte(self.pending_word[i]);
+    }
+  }
+}
+
+/// Snapshot of the whole `Writer` (screen, cursor and color), see `Writer::save_state`
+pub struct WriterState {
+  chars: Box<[[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT]>,
+  row_pos: usize,
+  col_pos: usize,
+  color_code: ColorCode,
+}
+
+impl Writer {
+  /// Get the current color combination
+  pub fn color_code(&self) -> ColorCode {
+    self.color_code
+  }
+
+  /// Get the cursor as `(row, col)`
+  pub fn cursor_position(&self) -> (usize, usize) {
+    (self.row_pos, self.col_pos)
+  }
+
+  /// Move the cursor (and the hardware cursor) to (`row`, `col`)
+  ///
+  /// Fails, leaving the cursor alone, if the position is off the screen.
+  #[allow(clippy::result_unit_err)]
+  pub fn set_cursor_position(&mut self, row: usize, col: usize) -> Result<(), ()> {
+    if row >= BUFFER_HEIGHT || col >= BUFFER_WIDTH {
+      return Err(());
+    }
+    self.flush_word();
+    self.row_pos = row;
+    self.col_pos = col;
+    self.update_cursor();
+    Ok(())
+  }
+
+  /// Write `s` starting at (`row`, `col`) with `color_code`, without moving the cursor
+  ///
+  /// Output is clipped at the end of `row`, and (like `write_string`)
+  /// non-printable bytes are written as `■`.
+  pub fn write_str_at(&mut self, row: usize, col: usize, s: &str, color_code: ColorCode) {
+    self.scroll_to_bottom();
+    if row >= BUFFER_HEIGHT {
+      return;
+    }
+    for (col, byte) in (col..BUFFER_WIDTH).zip(s.bytes()) {
+      let ascii_char = match byte {
+        0x20..=0x7e => byte,
+        _ => 0xfe,
+      };
+      self.buffer.chars[row][col].write(ScreenChar {
+        ascii_char,
+        color_code,
+      });
+    }
+  }
+
+  /// Clear `row` and write `s` centered on it, with the current color
+  ///
+  /// Text longer than a row is cut at its end, the cursor doesn't move.
+  pub fn write_centered(&mut self, row: usize, s: &str) {
+    self.clear_range(row, 0, BUFFER_WIDTH);
+    let start = (BUFFER_WIDTH - s.len().min(BUFFER_WIDTH)) / 2;
+    self.write_str_at(row, start, s, self.color_code);
+  }
+
+  /// Fill the screen from `cells`, `(ascii, attribute)` pairs in row-major order
+  ///
+  /// Meant for pre-rendered CP437 art: bytes are written as is (no `■`
+  /// substitution, no scrolling) and the cursor doesn't move. Cells beyond
+  /// `BUFFER_WIDTH * BUFFER_HEIGHT` are ignored, missing ones left untouched.
+  pub fn draw_screen(&mut self, cells: &[(u8, u8)]) {
+    self.scroll_to_bottom();
+    let screen = self.buffer.chars.iter_mut().flatten();
+    for (cell, &(ascii_char, attribute)) in screen.zip(cells) {
+      cell.write(ScreenChar {
+        ascii_char,
+        color_code: ColorCode::from_byte(attribute),
+      });
+    }
+  }
+
+  /// Draw a single-line CP437 border around `height` rows and `width` columns
+  /// from (`top`, `left`), with the current color
+  ///
+  /// The interior is left untouched and the cursor doesn't move. Returns `Err`
+  /// (drawing nothing) if the box is smaller than 2x2 or doesn't fit on screen.
+  #[allow(clippy::result_unit_err)]
+  pub fn draw_box(
+    &mut self,
+    top: usize,
+    left: usize,
+    height: usize,
+    width: usize,
+  ) -> Result<(), ()> {
+    let fits = |start: usize, len: usize, max: usize| len >= 2 && start + len <= max;
+    if !fits(top, height, BUFFER_HEIGHT) || !fits(left, width, BUFFER_WIDTH) {
+      return Err(());
+    }
+    self.scroll_to_bottom();
+    let (bottom, right) = (top + height - 1, left + width - 1);
+    let color_code = self.color_code;
+    let mut put = |row: usize, col: usize, ascii_char: u8| {
+      self.buffer.chars[row][col].write(ScreenChar {
+        ascii_char,
+        color_code,
+      })
+    };
+    for col in left + 1..right {
+      put(top, col, 0xC4);
+      put(bottom, col, 0xC4);
+    }
+    for row in top + 1..bottom {
+      put(row, left, 0xB3);
+      put(row, right, 0xB3);
+    }
+    put(top, left, 0xDA);
+    put(top, right, 0xBF);
+    put(bottom, left, 0xC0);
+    put(bottom, right, 0xD9);
+    Ok(())
+  }
+
+  /// Get the text of every row (trailing blanks included)
+  pub fn rows(&self) -> Vec<String> {
+    self
+      .buffer
+      .chars
+      .iter()
+      .map(|row| {
+        row
+          .iter()
+          .map(|c| char::from(c.read().ascii_char))
+          .collect()
+      })
+      .collect()
+  }
+
+  /// Get the raw `(ascii, attribute)` bytes of every cell
+  pub fn snapshot(&self) -> [[(u8, u8); BUFFER_WIDTH]; BUFFER_HEIGHT] {
+    self.buffer.chars.each_ref().map(|row| {
+      row.each_ref().map(|c| {
+        let c = c.read();
+        (c.ascii_char, c.color_code.as_byte())
+      })
+    })
+  }
+
+  /// Get every cell of every row, with its character and colors
+  pub fn snapshot_with_colors(&self) -> Vec<Vec<(char, ColorCode)>> {
+    self
+      .buffer
+      .chars
+      .iter()
+      .map(|row| {
+        row
+          .iter()
+          .map(|c| {
+            let c = c.read();
+            (char::from(c.ascii_char), c.color_code)
+          })
+          .collect()
+      })
+      .collect()
+  }
+
+  /// Write each `(text, color)` segment with `color` as foreground
+  ///
+  /// The previous color is restored afterwards.
+  pub fn write_colored(&mut self, segments: &[(&str, Color)]) {
+    let color_before = self.color_code;
+    for &(text, color) in segments {
+      self.color_code.set_fg(color);
+      self.write_string(text);
+    }
+    self.color_code = color_before;
+  }
+
+  /// Capture the screen, cursor and color
+  ///
+  /// While scrolled back, the live screen is captured (not the history view).
+  pub fn save_state(&self) -> WriterState {
+    let mut chars = Box::new(self.live_screen);
+    if self.scroll_offset == 0 {
+      for (row, saved_row) in chars.iter_mut().enumerate() {
+        for (col, saved_char) in saved_row.iter_mut().enumerate() {
+          *saved_char = self.buffer.chars[row][col].read();
+        }
+      }
+    }
+    WriterState {
+      chars,
+      row_pos: self.row_pos,
+      col_pos: self.col_pos,
+      color_code: self.color_code,
+    }
+  }
+
+  /// Blank every cell with the current color and move the cursor to the top-left
+  ///
+  /// Output then fills the screen downwards before it starts scrolling.
+  pub fn clear_screen(&mut self) {
+    self.flush_word();
+    self.scroll_to_bottom();
+    for row in 0..BUFFER_HEIGHT {
+      self.clear_row(row);
+    }
+    self.row_pos = 0;
+    self.col_pos = 0;
+    self.update_cursor();
+  }
+
+  /// Repaint the screen, cursor and color captured by `save_state`
+  pub fn restore_state(&mut self, state: &WriterState) {
+    self.scroll_to_bottom();
+    for (row, saved_row) in state.chars.iter().enumerate() {
+      for (col, saved_char) in saved_row.iter().enumerate() {
+        self.buffer.chars[row][col].write(*saved_char);
       }
     }
+    self.row_pos = state.row_pos;
+    self.col_pos = state.col_pos;
+    self.color_code = state.color_code;
+    self.update_cursor();
+  }
+}
+
+impl Writer {
+  /// Write test patterns to the top-left cell and read them back, then restore it
+  fn self_test(&mut self) -> bool {
+    let cell = &mut self.buffer.chars[0][0];
+    let saved = cell.read();
+    let passed = [(0x00, 0x00), (0x55, 0xaa), (0xaa, 0x55), (0xff, 0xff)]
+      .into_iter()
+      .all(|(ascii_char, color)| {
+        let pattern = ScreenChar {
+          ascii_char,
+          color_code: ColorCode::from_byte(color),
+        };
+        cell.write(pattern);
+        cell.read() == pattern
+      });
+    cell.write(saved);
+    passed
+  }
+}
+
+/// Check that the VGA text buffer keeps what is written to it
+pub fn self_test() -> bool {
+  x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().self_test())
+}
+
+#[cfg(feature = "vga_debug")]
+impl Writer {
+  /// Print the raw content of every cell (over serial), as a grid of `ascii:color` hex pairs
+  pub fn debug_dump(&self) {
+    use crate::{serial::SERIAL1, serial_println};
+    use fmt::Write as _;
+
+    // `SERIAL1` is locked per row, keep out interrupt handlers printing over serial
+    let _guard = InterruptGuard::new();
+    serial_println!();
+    for row in 0..BUFFER_HEIGHT {
+      let mut serial = SERIAL1.lock();
+      let _ = self.debug_dump_row(row, &mut *serial);
+      let _ = writeln!(serial);
+    }
+  }
+
+  /// Write the raw content of `row` as `ascii:color` hex pairs
+  fn debug_dump_row(&self, row: usize, out: &mut impl fmt::Write) -> fmt::Result {
+    write!(out, "{:02}|", row)?;
+    for col in 0..BUFFER_WIDTH {
+      let screen_char = self.buffer.chars[row][col].read();
+      write!(
+        out,
+        " {:02x}:{:02x}",
+        screen_char.ascii_char, screen_char.color_code.0
+      )?;
+    }
+    Ok(())
   }
 }
 
 impl fmt::Write for Writer {
   fn write_str(&mut self, s: &str) -> fmt::Result {
-    self.write_string(s);
+    // words may span several pieces of one `write_fmt`
+    self.write_string_buffered(s);
     Ok(())
   }
   fn write_char(&mut self, c: char) -> fmt::Result {
-    self.write_byte(c as u8);
+    self.put_byte(c as u8);
     Ok(())
   }
 }
 
 impl Writer {
   fn write_fmt(mut self: &mut Self, args: fmt::Arguments<'_>) -> fmt::Result {
-    fmt::write(&mut self, args)
+    let result = fmt::write(&mut self, args);
+    self.flush_word();
+    result
   }
 }
 
-pub fn safe_print_with_color(args: fmt::Arguments, color: Color) {
-  use x86_64::instructions::interrupts;
+/// Main screen saved by `enter_alt_screen`
+static ALT_SCREEN_SAVED: Mutex<Option<WriterState>> = Mutex::new(None);
 
-  // access WRITER without being interrupted by signals
-  interrupts::without_interrupts(|| {
-    let mut writer = WRITER.lock();
-    let foreground_before = writer.color_code.get_foreground();
-    writer.color_code.set_foreground(color);
-    writer.write_fmt(args).unwrap();
-    writer.color_code.set_foreground(foreground_before.into());
+/// Switch to a blank "alternate screen" (e.g. for a full-screen app)
+///
+/// The current screen and cursor are kept aside until `leave_alt_screen`.
+/// Only one level is supported: entering again while on the alternate
+/// screen does nothing.
+pub fn enter_alt_screen() {
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    let mut saved = ALT_SCREEN_SAVED.lock();
+    if saved.is_none() {
+      let mut writer = WRITER.lock();
+      *saved = Some(writer.save_state());
+      writer.clear_screen();
+    }
   });
 }
 
-pub fn safe_print(args: fmt::Arguments) {
-  use x86_64::instructions::interrupts;
-
-  // access WRITER without being interrupted by signals
-  interrupts::without_interrupts(|| {
-    WRITER.lock().write_fmt(args).unwrap();
+/// Switch back to the screen (and cursor) saved by `enter_alt_screen`
+pub fn leave_alt_screen() {
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    if let Some(saved) = ALT_SCREEN_SAVED.lock().take() {
+      WRITER.lock().restore_state(&saved);
+    }
   });
 }
 
-pub fn safe_eprint(args: fmt::Arguments) {
-  safe_print_with_color(args, Color::Yellow)
+/// Shape of the blinking hardware cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CursorStyle {
+  /// The bottom scanlines of the cell (the BIOS default)
+  Underline,
+  /// The whole cell
+  Block,
 }
 
-pub fn safe_local_log(args: fmt::Arguments) {
-  safe_print_with_color(args, Color::Cyan)
+/// Current `CursorStyle`, as `u8`
+static CURSOR_STYLE: AtomicU8 = AtomicU8::new(CursorStyle::Underline as u8);
+
+/// Change the shape of the hardware cursor
+pub fn set_cursor_style(style: CursorStyle) {
+  use crate::io::{inb, outb};
+
+  // first and last scanline of the 16 scanline cell
+  let (start, end) = match style {
+    CursorStyle::Underline => (13, 14),
+    CursorStyle::Block => (0, 15),
+  };
+  // SAFETY: only the cursor shape registers of the CRT controller change
+  x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+    // keep the reserved bits (and the "cursor disabled" bit clear)
+    outb(CRTC_INDEX, CURSOR_START);
+    let start_bits = inb(CRTC_DATA) & 0xC0;
+    outb(CRTC_DATA, start_bits | start);
+    outb(CRTC_INDEX, CURSOR_END);
+    let end_bits = inb(CRTC_DATA) & 0xE0;
+    outb(CRTC_DATA, end_bits | end);
+  });
+  CURSOR_STYLE.store(style as u8, Ordering::Relaxed);
 }
 
-#[macro_export]
-macro_rules! print_with_color {
-    () => ($crate::print!());
-    ($color:ident, $($arg:tt)*) => ($crate::vga_buffer::safe_print_with_color(format_args!($($arg)*), $crate::vga_buffer::Color::$color));
+/// Get the style last set by `set_cursor_style`
+pub fn cursor_style() -> CursorStyle {
+  match CURSOR_STYLE.load(Ordering::Relaxed) {
+    0 => CursorStyle::Underline,
+    _ => CursorStyle::Block,
+  }
+}
+
+/// # Safety
+///
+/// Interrupts must be disabled, the index/data flip-flop is shared state.
+unsafe fn read_attribute_register(index: u8) -> u8 {
+  use crate::io::{inb, outb};
+
+  // reading the input status resets the flip-flop to "index"
+  inb(INPUT_STATUS_1);
+  outb(ATTRIBUTE_INDEX, PALETTE_ADDRESS_SOURCE | index);
+  inb(ATTRIBUTE_DATA_READ)
+}
+
+/// # Safety
+///
+/// Same as `read_attribute_register`, and `value` must be valid for `index`.
+unsafe fn write_attribute_register(index: u8, value: u8) {
+  use crate::io::{inb, outb};
+
+  inb(INPUT_STATUS_1);
+  outb(ATTRIBUTE_INDEX, PALETTE_ADDRESS_SOURCE | index);
+  outb(ATTRIBUTE_INDEX, value);
+}
+
+/// Choose what bit 7 of an attribute byte does: blink the cell (`true`), or
+/// select one of the bright colors as background (`false`)
+///
+/// `init` selects blinking, so `ColorCode::set_blink` works. In that mode, a
+/// bright background color shows as its dark variant, blinking.
+pub fn set_blink_enabled(enabled: bool) {
+  // SAFETY: interrupts are disabled, and only the blink bit changes
+  x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+    let mode = read_attribute_register(ATTRIBUTE_MODE_CONTROL);
+    let mode = match enabled {
+      true => mode | BLINK_ENABLE,
+      false => mode & !BLINK_ENABLE,
+    };
+    write_attribute_register(ATTRIBUTE_MODE_CONTROL, mode);
+  });
+}
+
+/// Whether bit 7 of an attribute byte currently means blink
+pub fn blink_enabled() -> bool {
+  // SAFETY: interrupts are disabled
+  x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+    read_attribute_register(ATTRIBUTE_MODE_CONTROL) & BLINK_ENABLE != 0
+  })
+}
+
+/// Write a centered line on the screen of `WRITER`, see `Writer::write_centered`
+pub fn safe_write_centered(row: usize, s: &str) {
+  x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().write_centered(row, s));
+}
+
+/// Write raw glyphs on the screen of `WRITER`, see `Writer::write_raw_bytes`
+pub fn safe_write_raw_bytes(bytes: &[u8]) {
+  x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().write_raw_bytes(bytes));
+}
+
+/// Capture the screen of `WRITER`, see `Writer::snapshot`
+pub fn safe_snapshot() -> [[(u8, u8); BUFFER_WIDTH]; BUFFER_HEIGHT] {
+  x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().snapshot())
+}
+
+/// Blank the screen of `WRITER`, see `Writer::clear_screen`
+pub fn safe_clear_screen() {
+  x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().clear_screen());
+}
+
+/// Move the cursor of `WRITER`, see `Writer::set_cursor_position`
+#[allow(clippy::result_unit_err)]
+pub fn safe_set_cursor_position(row: usize, col: usize) -> Result<(), ()> {
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    WRITER.lock().set_cursor_position(row, col)
+  })
+}
+
+/// Move the hardware cursor to where `WRITER` writes next
+pub fn update_cursor() {
+  x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().update_cursor());
+}
+
+/// Fill the screen from `(ascii, attribute)` cells, see `Writer::draw_screen`
+pub fn draw_screen(cells: &[(u8, u8)]) {
+  x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().draw_screen(cells));
+}
+
+/// Destination of the `print!` family of macros
+pub trait OutputTarget: fmt::Write + Send {
+  /// Write `args` with `color` as foreground
+  ///
+  /// Targets without color support (the default) just write `args`.
+  fn write_fmt_with_color(&mut self, args: fmt::Arguments, _color: Color) -> fmt::Result {
+    self.write_fmt(args)
+  }
+
+  /// Push any buffered output to its destination
+  fn flush(&mut self) {}
+}
+
+/// An `OutputTarget` accumulating everything printed into a shared `String`
+pub struct CaptureTarget {
+  buffer: Arc<Mutex<String>>,
+}
+
+impl CaptureTarget {
+  pub fn new(buffer: Arc<Mutex<String>>) -> Self {
+    Self { buffer }
+  }
+}
+
+impl fmt::Write for CaptureTarget {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.buffer.lock().push_str(s);
+    Ok(())
+  }
+}
+
+impl OutputTarget for CaptureTarget {}
+
+/// Current output target (`None` => `WRITER`)
+static OUTPUT: Mutex<Option<Box<dyn OutputTarget>>> = Mutex::new(None);
+
+/// Route the `print!` family of macros to `target` (`None` => the VGA `WRITER`)
+///
+/// Returns the previously installed target.
+pub fn set_output(target: Option<Box<dyn OutputTarget>>) -> Option<Box<dyn OutputTarget>> {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| core::mem::replace(&mut *OUTPUT.lock(), target))
+}
+
+/// Flush pending output and switch back to the VGA `WRITER`, best-effort
+///
+/// Meant for the panic path (before rendering the panic screen), so it only
+/// uses `try_lock`, and leaks the installed target instead of freeing it.
+pub fn force_flush() {
+  if let Some(mut output) = OUTPUT.try_lock() {
+    if let Some(mut target) = output.take() {
+      target.flush();
+      core::mem::forget(target);
+    }
+  }
+}
+
+/// Print `args` in `foreground` on `background`, then restore the previous colors
+///
+/// Installed output targets (see `set_output`) only get the foreground.
+pub fn safe_print_with_colors(args: fmt::Arguments, foreground: Color, background: Color) {
+  // access WRITER without being interrupted by signals
+  let _guard = InterruptGuard::new();
+  if let Some(target) = OUTPUT.lock().as_mut() {
+    target.write_fmt_with_color(args, foreground).unwrap();
+    return;
+  }
+  let mut writer = WRITER.lock();
+  let color_code_before = writer.color_code;
+  writer.color_code = ColorCode::new(foreground, background);
+  writer.write_fmt(args).unwrap();
+  writer.color_code = color_code_before;
+}
+
+/// Print `args` in `color`, keeping the current background
+pub fn safe_print_with_color(args: fmt::Arguments, color: Color) {
+  let background =
+    x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().color_code.bg());
+  safe_print_with_colors(args, color, background)
+}
+
+pub fn safe_print(args: fmt::Arguments) {
+  // access WRITER without being interrupted by signals
+  let _guard = InterruptGuard::new();
+  if let Some(target) = OUTPUT.lock().as_mut() {
+    target.write_fmt(args).unwrap();
+    return;
+  }
+  WRITER.lock().write_fmt(args).unwrap();
+}
+
+pub fn safe_eprint(args: fmt::Arguments) {
+  crate::sound::bell();
+  safe_print_with_color(args, Color::Yellow)
+}
+
+pub fn safe_local_log(args: fmt::Arguments) {
+  safe_print_with_color(args, Color::Cyan)
+}
+
+#[macro_export]
+macro_rules! print_with_color {
+    () => ($crate::print!());
+    ($color:ident, $($arg:tt)*) => ($crate::vga_buffer::safe_print_with_color(format_args!($($arg)*), $crate::vga_buffer::Color::$color));
     (<$color:ident> $($arg:tt)*) => ($crate::vga_buffer::safe_print_with_color(format_args!($($arg)*), $crate::vga_buffer::Color::$color));
     ([$color:ident] $($arg:tt)*) => ($crate::vga_buffer::safe_print_with_color(format_args!($($arg)*), $crate::vga_buffer::Color::$color));
     ({$color:ident} $($arg:tt)*) => ($crate::vga_buffer::safe_print_with_color(format_args!($($arg)*), $crate::vga_buffer::Color::$color));
@@ -286,6 +1387,18 @@ macro_rules! print_with_color_ln {
     ({$color:ident} $($arg:tt)*) => ($crate::print_with_color!({$color} "{}\n", format_args!($($arg)*)));
 }
 
+#[macro_export]
+macro_rules! print_with_colors {
+    () => ($crate::print!());
+    ($fg:ident on $bg:ident, $($arg:tt)*) => ($crate::vga_buffer::safe_print_with_colors(format_args!($($arg)*), $crate::vga_buffer::Color::$fg, $crate::vga_buffer::Color::$bg));
+}
+
+#[macro_export]
+macro_rules! print_with_colors_ln {
+    () => ($crate::println!());
+    ($fg:ident on $bg:ident, $($arg:tt)*) => ($crate::print_with_colors!($fg on $bg, "{}\n", format_args!($($arg)*)));
+}
+
 #[macro_export]
 macro_rules! print {
     () => ($crate::vga_buffer::safe_print(format_args!("")));
@@ -298,6 +1411,13 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+#[macro_export]
+macro_rules! clear {
+  () => {
+    $crate::vga_buffer::safe_clear_screen()
+  };
+}
+
 #[macro_export]
 macro_rules! eprint {
     () => ($crate::vga_buffer::safe_eprint(format_args!("")));
@@ -339,16 +1459,635 @@ fn test_println_output() {
   use x86_64::instructions::interrupts;
 
   let s = "A testing string which is in one line";
-  interrupts::without_interrupts(|| {
+  let color_code = interrupts::without_interrupts(|| {
     let mut writer = WRITER.lock();
     /*
       `\n` => make sure current line starts with `` instead of `.`
       caused by the timer
     */
     writeln!(writer, "\n{}", s).expect("writeln failed!\n");
-    for (i, c) in s.chars().enumerate() {
-      let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
-      assert_eq!(char::from(screen_char.ascii_char), c);
+    writer.color_code
+  });
+  let screen = safe_snapshot();
+  for (i, c) in s.bytes().enumerate() {
+    assert_eq!(screen[BUFFER_HEIGHT - 2][i], (c, color_code.as_byte()));
+  }
+}
+
+#[test_case]
+fn test_write_raw_bytes() {
+  safe_write_raw_bytes(b"\n\xB0\xC4\x01\n");
+  let screen = safe_snapshot();
+  assert_eq!(screen[BUFFER_HEIGHT - 2][0].0, 0xB0);
+  assert_eq!(screen[BUFFER_HEIGHT - 2][1].0, 0xC4);
+  assert_eq!(screen[BUFFER_HEIGHT - 2][2].0, 0x01);
+}
+
+#[test_case]
+fn test_color_code_round_trip() {
+  let mut color_code = ColorCode::new(Color::LightGreen, Color::Blue);
+  let packed = color_code.as_byte();
+  assert_eq!(packed, 0x1a);
+  assert_eq!(ColorCode::from_byte(packed), color_code);
+  assert_eq!(color_code.fg(), Color::LightGreen);
+  assert_eq!(color_code.bg(), Color::Blue);
+
+  color_code.set_fg(Color::Yellow);
+  color_code.set_bg(Color::Red);
+  assert_eq!(color_code.as_byte(), 0x4e);
+  color_code.invert();
+  assert_eq!(color_code.fg(), Color::Red);
+  assert_eq!(color_code.bg(), Color::Yellow);
+}
+
+#[test_case]
+fn test_nearest_color() {
+  assert_eq!(Color::nearest(0xFF, 0, 0), Color::Red);
+  assert_eq!(Color::nearest(0, 0xFF, 0), Color::Green);
+  assert_eq!(Color::nearest(0, 0, 0xFF), Color::Blue);
+  assert_eq!(Color::nearest(0x80, 0x80, 0x80), Color::LightGray);
+  assert_eq!(Color::nearest(0x60, 0x60, 0x60), Color::DarkGray);
+  // exact palette entries map to themselves
+  for index in 0..16 {
+    let color = Color::from_lossy(index);
+    let (r, g, b) = color.rgb();
+    assert_eq!(Color::nearest(r, g, b), color);
+  }
+}
+
+#[test_case]
+fn test_capture_output() {
+  let buffer = Arc::new(Mutex::new(String::new()));
+  set_output(Some(Box::new(CaptureTarget::new(buffer.clone()))));
+  println!("captured {}", 42);
+  print_with_color!(Red, "in red");
+  set_output(None);
+  assert_eq!(buffer.lock().as_str(), "captured 42\nin red");
+}
+
+#[cfg(feature = "vga_debug")]
+#[test_case]
+fn test_debug_dump() {
+  use alloc::string::String;
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let color_before = writer.color_code;
+    writer.write_byte(b'\n');
+    writer.color_code = ColorCode::new(Color::Red, Color::Blue);
+    writer.write_byte(b'A');
+    writer.color_code = color_before;
+
+    let mut dump = String::new();
+    writer
+      .debug_dump_row(BUFFER_HEIGHT - 1, &mut dump)
+      .expect("dumping failed!\n");
+    assert!(dump.starts_with("24| 41:14 "));
+  });
+}
+
+#[test_case]
+fn test_clear_range() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let color_code = writer.color_code;
+    let row = BUFFER_HEIGHT - 2;
+    writer.write_str_at(row, 0, &"x".repeat(BUFFER_WIDTH), color_code);
+    writer.clear_range(row, 5, 10);
+    // out-of-screen parts are ignored
+    writer.clear_range(row, BUFFER_WIDTH, BUFFER_WIDTH + 5);
+    writer.clear_range(BUFFER_HEIGHT, 0, BUFFER_WIDTH);
+
+    let line = &writer.rows()[row];
+    assert_eq!(&line[..5], "xxxxx");
+    assert_eq!(&line[5..10], "     ");
+    assert!(line[10..].chars().all(|c| c == 'x'));
+  });
+}
+
+#[test_case]
+fn test_write_colored() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let color_before = writer.color_code;
+    writer.write_byte(b'\n');
+    writer.write_colored(&[
+      ("[INFO]", Color::Green),
+      (" ", Color::White),
+      ("message", Color::LightGray),
+    ]);
+    assert_eq!(writer.color_code, color_before);
+
+    let row = &writer.snapshot_with_colors()[BUFFER_HEIGHT - 1];
+    let expected = [
+      (0..6, Color::Green),
+      (6..7, Color::White),
+      (7..14, Color::LightGray),
+    ];
+    for (cols, color) in expected {
+      for (_, color_code) in &row[cols] {
+        assert_eq!(color_code.fg(), color);
+      }
+    }
+    let text: String = row[..14].iter().map(|(c, _)| c).collect();
+    assert_eq!(text, "[INFO] message");
+  });
+}
+
+#[test_case]
+fn test_word_wrap() {
+  use alloc::vec::Vec;
+  use x86_64::instructions::interrupts;
+
+  let paragraph = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod \
+                   tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, \
+                   quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo.";
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    writer.write_byte(b'\n');
+    writer.set_word_wrap(true);
+    writer.write_string(paragraph);
+    writer.set_word_wrap(false);
+
+    // the paragraph takes the last 3 rows, and every row holds whole words
+    let rows = writer.rows();
+    let words = rows[BUFFER_HEIGHT - 3..]
+      .iter()
+      .flat_map(|row| row.split_whitespace())
+      .collect::<Vec<_>>();
+    assert_eq!(words, paragraph.split_whitespace().collect::<Vec<_>>());
+  });
+}
+
+#[test_case]
+fn test_backspace_at_col0() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let row_before = writer.row_pos;
+    writer.write_string("\nkept");
+    writer.write_byte(b'\n');
+
+    // protected: nothing happens
+    writer.set_backspace_stops_at_col0(true);
+    writer.enforce_backspace();
+    assert_eq!((writer.row_pos, writer.col_pos), (row_before, 0));
+    assert!(writer.rows()[BUFFER_HEIGHT - 2].starts_with("kept"));
+
+    // raw: wraps to the end of the previous row
+    writer.set_backspace_stops_at_col0(false);
+    writer.enforce_backspace();
+    assert_eq!(
+      (writer.row_pos, writer.col_pos),
+      (row_before - 1, BUFFER_WIDTH - 1)
+    );
+    writer.row_pos = row_before;
+    writer.col_pos = 0;
+  });
+}
+
+#[test_case]
+fn test_alt_screen() {
+  use x86_64::instructions::interrupts;
+
+  println!("\nmain screen");
+  let (rows_before, cursor_before) = interrupts::without_interrupts(|| {
+    let writer = WRITER.lock();
+    (writer.rows(), (writer.row_pos, writer.col_pos))
+  });
+
+  enter_alt_screen();
+  // nested enters are ignored
+  enter_alt_screen();
+  interrupts::without_interrupts(|| {
+    assert!(WRITER.lock().rows().iter().all(|row| row.trim().is_empty()));
+  });
+  print!("alternate screen");
+  leave_alt_screen();
+
+  interrupts::without_interrupts(|| {
+    let writer = WRITER.lock();
+    assert_eq!(writer.rows(), rows_before);
+    assert_eq!((writer.row_pos, writer.col_pos), cursor_before);
+  });
+}
+
+#[test_case]
+fn test_print_with_colors() {
+  use x86_64::instructions::interrupts;
+
+  let color_code_before = interrupts::without_interrupts(|| WRITER.lock().color_code);
+  println!();
+  print_with_colors!(Red on Blue, "x");
+  interrupts::without_interrupts(|| {
+    let writer = WRITER.lock();
+    let cell = writer.buffer.chars[writer.row_pos][writer.col_pos - 1].read();
+    assert_eq!(cell.ascii_char, b'x');
+    assert_eq!(cell.color_code.get_foreground(), 4);
+    assert_eq!(cell.color_code.get_background(), 1);
+    assert_eq!(writer.color_code, color_code_before);
+  });
+  println!();
+}
+
+#[test_case]
+fn test_blink() {
+  use x86_64::instructions::interrupts;
+
+  let mut color_code = ColorCode::new(Color::Red, Color::Blue);
+  color_code.set_blink(true);
+  assert_eq!(color_code.as_byte(), 0x80 | 0x14);
+  color_code.set_blink(false);
+  assert_eq!(color_code.as_byte(), 0x14);
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    writer.write_byte(b'\n');
+    writer.set_blink(true);
+    writer.write_byte(b'!');
+    writer.set_blink(false);
+    let cell = writer.buffer.chars[writer.row_pos][0].read();
+    assert_eq!(cell.color_code.as_byte() & 0x80, 0x80);
+    assert_eq!(writer.color_code.as_byte() & 0x80, 0);
+  });
+
+  assert!(blink_enabled());
+  set_blink_enabled(false);
+  assert!(!blink_enabled());
+  set_blink_enabled(true);
+  assert!(blink_enabled());
+}
+
+#[test_case]
+fn test_truncate_mode() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    writer.write_byte(b'\n');
+    let rows_before = writer.rows();
+    writer.set_wrap_mode(WrapMode::Truncate);
+    for i in 0..100 {
+      writer.write_byte(b'A' + (i % 26) as u8);
+    }
+    writer.write_byte(b'\t');
+    let rows = writer.rows();
+    assert_eq!(rows[..BUFFER_HEIGHT - 1], rows_before[..BUFFER_HEIGHT - 1]);
+    // the 80th char
+    assert_eq!(rows[BUFFER_HEIGHT - 1].as_bytes()[79], b'A' + 79 % 26);
+    assert_eq!(writer.col_pos, BUFFER_WIDTH);
+
+    writer.write_string("\nnext");
+    assert!(writer.rows()[BUFFER_HEIGHT - 1].starts_with("next "));
+    writer.set_wrap_mode(WrapMode::Wrap);
+  });
+}
+
+#[test_case]
+fn test_draw_box() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let saved = writer.save_state();
+    let color_code = writer.color_code;
+    writer.buffer.chars[1][2].write(ScreenChar {
+      ascii_char: b'x',
+      color_code,
+    });
+    assert_eq!(writer.draw_box(0, 0, 3, 5), Ok(()));
+    let screen = writer.snapshot();
+    let ascii = |row: usize, col: usize| screen[row][col].0;
+    assert_eq!(
+      [ascii(0, 0), ascii(0, 4), ascii(2, 0), ascii(2, 4)],
+      [0xDA, 0xBF, 0xC0, 0xD9]
+    );
+    assert_eq!([ascii(0, 2), ascii(1, 0), ascii(1, 4)], [0xC4, 0xB3, 0xB3]);
+    // the interior is kept
+    assert_eq!(ascii(1, 2), b'x');
+
+    assert_eq!(writer.draw_box(BUFFER_HEIGHT - 2, 0, 3, 5), Err(()));
+    assert_eq!(writer.draw_box(0, BUFFER_WIDTH - 4, 3, 5), Err(()));
+    assert_eq!(writer.draw_box(0, 0, 1, 5), Err(()));
+    writer.restore_state(&saved);
+  });
+}
+
+#[test_case]
+fn test_write_centered() {
+  use alloc::format;
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let saved = writer.save_state();
+    let row = |writer: &Writer| writer.rows()[0].clone();
+
+    writer.write_centered(0, "boot");
+    assert_eq!(row(&writer), format!("{:^80}", "boot"));
+    assert_eq!(&row(&writer)[38..42], "boot");
+
+    writer.write_centered(0, "");
+    assert!(row(&writer).trim().is_empty());
+
+    let full = "x".repeat(BUFFER_WIDTH);
+    writer.write_centered(0, &full);
+    assert_eq!(row(&writer), full);
+
+    let long = format!("{}yz", "w".repeat(BUFFER_WIDTH));
+    writer.write_centered(0, &long);
+    assert_eq!(row(&writer), long[..BUFFER_WIDTH]);
+    writer.restore_state(&saved);
+  });
+}
+
+#[test_case]
+fn test_color_try_from() {
+  for value in 0..=15 {
+    let color = Color::try_from(value).expect("valid color rejected!\n");
+    assert_eq!(color as u8, value);
+    assert_eq!(Color::from_lossy(value), color);
+  }
+  for value in [16, 0x80, 0xff] {
+    assert_eq!(Color::try_from(value), Err(InvalidColor(value)));
+    assert_eq!(Color::from_lossy(value), Color::Black);
+  }
+}
+
+#[test_case]
+fn test_clear_screen() {
+  use x86_64::instructions::interrupts;
+
+  let saved = interrupts::without_interrupts(|| WRITER.lock().save_state());
+  println!("\nto be cleared");
+  clear!();
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let blank = ScreenChar {
+      ascii_char: b' ',
+      color_code: writer.color_code,
+    };
+    assert_eq!(writer.buffer.chars[BUFFER_HEIGHT - 2][0].read(), blank);
+    assert_eq!(writer.cursor_position(), (0, 0));
+
+    // output fills the screen from the top
+    writer.write_string("top\nnext");
+    assert_eq!(writer.cursor_position(), (1, 4));
+    assert!(writer.rows()[0].starts_with("top "));
+    writer.restore_state(&saved);
+  });
+}
+
+#[test_case]
+fn test_save_and_restore_state() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    writer.write_string("\nbefore the overlay");
+    let screen = writer.snapshot();
+    let cursor = writer.cursor_position();
+    let color_code = writer.color_code;
+    let saved = writer.save_state();
+
+    writer.clear_screen();
+    writer.color_code = ColorCode::new(Color::Yellow, Color::Red);
+    writer.write_string("garbage\ngarbage");
+    writer.restore_state(&saved);
+
+    assert_eq!(writer.snapshot(), screen);
+    assert_eq!(writer.cursor_position(), cursor);
+    assert_eq!(writer.color_code, color_code);
+  });
+}
+
+#[test_case]
+fn test_cursor_position() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let saved = writer.cursor_position();
+
+    assert_eq!(writer.set_cursor_position(3, 10), Ok(()));
+    assert_eq!(writer.cursor_position(), (3, 10));
+    writer.write_string("here");
+    assert_eq!(&writer.rows()[3][10..14], "here");
+    assert_eq!(writer.cursor_position(), (3, 14));
+
+    assert_eq!(writer.set_cursor_position(BUFFER_HEIGHT, 0), Err(()));
+    assert_eq!(writer.set_cursor_position(0, BUFFER_WIDTH), Err(()));
+    assert_eq!(writer.cursor_position(), (3, 14));
+    (writer.row_pos, writer.col_pos) = saved;
+  });
+}
+
+#[test_case]
+fn test_safe_set_cursor_position() {
+  use x86_64::instructions::interrupts;
+
+  let saved = interrupts::without_interrupts(|| WRITER.lock().cursor_position());
+  assert_eq!(safe_set_cursor_position(5, 42), Ok(()));
+  print!("Z");
+  let cell = interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let cell = writer.buffer.chars[5][42].read();
+    (writer.row_pos, writer.col_pos) = saved;
+    cell
+  });
+  assert_eq!(cell.ascii_char, b'Z');
+}
+
+#[test_case]
+fn test_print_from_interrupt_after_init() {
+  init();
+  // the breakpoint handler prints through `WRITER`
+  x86_64::instructions::interrupts::int3();
+  x86_64::instructions::interrupts::without_interrupts(|| {
+    assert!(WRITER.try_lock().is_some(), "`WRITER` left locked!\n");
+  });
+  println!("printing still works");
+}
+
+#[test_case]
+fn test_draw_screen() {
+  use alloc::vec::Vec;
+  use x86_64::instructions::interrupts;
+
+  // every byte value (box drawing included), with varying attributes
+  let art = (0..BUFFER_WIDTH * BUFFER_HEIGHT)
+    .map(|i| (i as u8, (i / BUFFER_WIDTH) as u8))
+    .collect::<Vec<_>>();
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let saved = writer.save_state();
+    // the extra cell is dropped
+    let mut oversized = art.clone();
+    oversized.push((b'!', 0x0f));
+    writer.draw_screen(&oversized);
+
+    let expected = art
+      .chunks(BUFFER_WIDTH)
+      .map(|row| row.iter().map(|&(c, _)| char::from(c)).collect::<String>())
+      .collect::<Vec<_>>();
+    assert_eq!(writer.rows(), expected);
+    let colors = writer.snapshot_with_colors();
+    for (row, colors) in colors.iter().enumerate() {
+      assert!(colors.iter().all(|&(_, c)| c.as_byte() == row as u8));
     }
+    writer.restore_state(&saved);
+  });
+}
+
+#[test_case]
+fn test_hardware_cursor_follows_writer() {
+  use crate::io::{inb, outb};
+  use x86_64::instructions::interrupts;
+
+  let read_cursor = || unsafe {
+    outb(CRTC_INDEX, CURSOR_LOCATION_HIGH);
+    let high = inb(CRTC_DATA) as usize;
+    outb(CRTC_INDEX, CURSOR_LOCATION_LOW);
+    (high << 8) | inb(CRTC_DATA) as usize
+  };
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    writer.write_string("\n\tab");
+    assert_eq!(read_cursor(), (BUFFER_HEIGHT - 1) * BUFFER_WIDTH + 10);
+    writer.enforce_backspace();
+    assert_eq!(read_cursor(), (BUFFER_HEIGHT - 1) * BUFFER_WIDTH + 9);
+    writer.write_string("\n");
+    assert_eq!(read_cursor(), (BUFFER_HEIGHT - 1) * BUFFER_WIDTH);
+  });
+}
+
+#[test_case]
+fn test_scrollback() {
+  use alloc::format;
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    for i in 0..100 {
+      writer.write_string(&format!("line {}\n", i));
+    }
+    // the live screen shows lines 76..=99 above the empty cursor row
+    assert!(writer.rows()[0].starts_with("line 76 "));
+
+    writer.scroll_up(30);
+    assert!(writer.rows()[0].starts_with("line 46 "));
+    writer.scroll_down(10);
+    assert!(writer.rows()[0].starts_with("line 56 "));
+    writer.scroll_to_bottom();
+    assert!(writer.rows()[0].starts_with("line 76 "));
+
+    // new output snaps back to the live screen
+    writer.scroll_up(5);
+    writer.write_byte(b'x');
+    let rows = writer.rows();
+    assert!(rows[0].starts_with("line 76 "));
+    assert!(rows[BUFFER_HEIGHT - 1].starts_with("x "));
+  });
+}
+
+#[test_case]
+fn test_scrollback_drops_oldest_rows() {
+  use alloc::format;
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    writer.write_byte(b'\n');
+    let lines = SCROLLBACK_LINES + 50;
+    for i in 0..lines {
+      writer.write_string(&format!("line {}\n", i));
+    }
+    // lines up to `lines - BUFFER_HEIGHT` scrolled off, the last ones are kept
+    writer.scroll_up(usize::MAX);
+    let oldest = lines - (BUFFER_HEIGHT - 1) - SCROLLBACK_LINES;
+    assert!(writer.rows()[0].starts_with(&format!("line {} ", oldest)));
+    writer.scroll_to_bottom();
+  });
+}
+
+#[test_case]
+fn test_clear_screen_while_scrolled_back() {
+  use alloc::format;
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    for i in 0..30 {
+      writer.write_string(&format!("line {}\n", i));
+    }
+    let live = writer.snapshot();
+    // the saved state is the live screen, not the history view
+    writer.scroll_up(5);
+    assert_ne!(writer.snapshot(), live);
+    let state = writer.save_state();
+    let saved = state
+      .chars
+      .map(|row| row.map(|c| (c.ascii_char, c.color_code.as_byte())));
+    assert_eq!(saved, live);
+
+    writer.clear_screen();
+    writer.write_byte(b'x');
+    let rows = writer.rows();
+    assert!(rows[0].starts_with("x "));
+    assert!(rows[1..].iter().all(|row| row.trim_end().is_empty()));
+    writer.restore_state(&state);
+  });
+}
+
+#[test_case]
+fn test_ansi_sgr() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    let color_before = writer.color_code;
+    writer.color_code = ColorCode::default();
+    writer.write_string("\n\x1b[32mok\x1b[0m ");
+    // split sequences, and a non-SGR one (clear screen) which is dropped
+    writer.write_string("\x1b[2J\x1b[1;3");
+    writer.write_string("4;101mx\x1b[m");
+
+    let row = &writer.snapshot_with_colors()[BUFFER_HEIGHT - 1];
+    let text = row.iter().map(|&(c, _)| c).collect::<String>();
+    assert!(text.starts_with("ok x "));
+    assert_eq!(row[0].1.fg(), Color::Green);
+    assert_eq!(row[1].1.fg(), Color::Green);
+    assert_eq!(row[2].1, ColorCode::default());
+    assert_eq!(row[3].1, ColorCode::new(Color::Blue, Color::LightRed));
+    assert_eq!(writer.color_code, ColorCode::default());
+    writer.color_code = color_before;
+  });
+}
+
+#[test_case]
+fn test_tab_stops() {
+  use x86_64::instructions::interrupts;
+
+  interrupts::without_interrupts(|| {
+    let mut writer = WRITER.lock();
+    writer.write_string("\na\tb");
+    assert_eq!(writer.col_pos, 9);
+    assert!(writer.rows()[BUFFER_HEIGHT - 1].starts_with("a       b "));
+
+    // a stop past the end of the row breaks the line
+    writer.set_tab_width(7);
+    writer.write_string("\n");
+    writer.col_pos = 78;
+    writer.write_byte(b'\t');
+    assert_eq!(writer.col_pos, 0);
+    writer.write_string("\tc");
+    assert!(writer.rows()[BUFFER_HEIGHT - 1].starts_with("       c "));
+    writer.set_tab_width(8);
   });
 }
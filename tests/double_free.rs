@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::{fmt::Write, mem::ManuallyDrop, panic::PanicInfo};
+use ember_os::{
+  exit::{exit_qemu, QemuExitCode},
+  serial_print, serial_println,
+  test_framework::MessageBuffer,
+};
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(boot_info: &'static BootInfo) -> ! {
+  serial_print!("double_free::double_free_panics ... ");
+  ember_os::minimum_init(boot_info);
+
+  let value = ManuallyDrop::new(Box::new([0u64; 4]));
+  let ptr = Box::into_raw(ManuallyDrop::into_inner(value));
+  unsafe {
+    drop(Box::from_raw(ptr));
+    drop(Box::from_raw(ptr));
+  }
+
+  serial_println!("[double free not detected]");
+  exit_qemu(QemuExitCode::Failed);
+  ember_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  let mut message = MessageBuffer::<128>::new();
+  let _ = write!(message, "{}", info.message());
+  if message.as_str().starts_with("double free") {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+  } else {
+    serial_println!("[failed]\n{}", info);
+    exit_qemu(QemuExitCode::Failed);
+  }
+  ember_os::hlt_loop()
+}
@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ember_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ember_os::{
+  allocator,
+  interrupts::probe_read,
+  memory::{self, BootInfoFrameAllocator, EmptyFrameAllocator, MapRegionError, UnmapPageError},
+};
+use spin::Mutex;
+use x86_64::{
+  structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags},
+  VirtAddr,
+};
+
+/// The page table and frame allocator set up by `main`
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(boot_info: &'static BootInfo) -> ! {
+  ember_os::gdt::init();
+  ember_os::interrupts::init_idt();
+  unsafe { ember_os::interrupts::PICS.lock().initialize() };
+  x86_64::instructions::interrupts::enable();
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init(phys_mem_offset) };
+  let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed!\n");
+  *MEMORY.lock() = Some((mapper, frame_allocator));
+  test_main();
+  ember_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  ember_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+  let mut memory = MEMORY.lock();
+  let (_, frame_allocator) = memory.as_mut().unwrap();
+  let frame = frame_allocator.allocate_frame().unwrap();
+  unsafe { frame_allocator.free_frame(frame) };
+  assert_eq!(frame_allocator.free_frames(), 1);
+  assert_eq!(frame_allocator.allocate_frame(), Some(frame));
+  assert_eq!(frame_allocator.free_frames(), 0);
+  // then fresh frames again
+  assert_ne!(frame_allocator.allocate_frame(), Some(frame));
+}
+
+#[test_case]
+fn unmapped_page_faults() {
+  let mut memory = MEMORY.lock();
+  let (mapper, frame_allocator) = memory.as_mut().unwrap();
+  let page = Page::containing_address(VirtAddr::new(0x6666_0000_0000));
+  let frame = frame_allocator.allocate_frame().unwrap();
+  let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+  unsafe { mapper.map_to(page, frame, flags, frame_allocator) }
+    .expect("map_to failed!\n")
+    .flush();
+
+  unsafe { page.start_address().as_mut_ptr::<u8>().write_volatile(42) };
+  assert_eq!(probe_read(page.start_address()), Some(42));
+
+  assert_eq!(memory::unmap_page(mapper, page), Ok(frame));
+  assert_eq!(probe_read(page.start_address()), None);
+  assert_eq!(
+    memory::unmap_page(mapper, page),
+    Err(UnmapPageError::NotMapped)
+  );
+  unsafe { frame_allocator.free_frame(frame) };
+}
+
+#[test_case]
+fn mapped_regions_are_usable_and_disjoint() {
+  let mut memory = MEMORY.lock();
+  let (mapper, frame_allocator) = memory.as_mut().unwrap();
+  let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+  let size = 3 * 4096;
+  let start =
+    memory::map_region(mapper, frame_allocator, size, flags).expect("map_region failed!\n");
+  let other = memory::map_region(mapper, frame_allocator, 1, flags).expect("map_region failed!\n");
+  assert!(other >= start + size as u64);
+
+  let region = unsafe { core::slice::from_raw_parts_mut(start.as_mut_ptr::<u8>(), size) };
+  for (i, byte) in region.iter_mut().enumerate() {
+    *byte = (i % 251) as u8;
+  }
+  for (i, &byte) in region.iter().enumerate() {
+    assert_eq!(byte, (i % 251) as u8);
+  }
+}
+
+#[test_case]
+fn failed_regions_are_rolled_back() {
+  let mut memory = MEMORY.lock();
+  let (mapper, frame_allocator) = memory.as_mut().unwrap();
+  let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+  let window = memory::REGION_WINDOW.end - memory::REGION_WINDOW.start;
+  assert!(matches!(
+    memory::map_region(mapper, frame_allocator, window as usize + 1, flags),
+    Err(MapRegionError::WindowFull)
+  ));
+
+  let first = memory::map_region(mapper, frame_allocator, 1, flags).expect("map_region failed!\n");
+  assert!(matches!(
+    memory::map_region(mapper, &mut EmptyFrameAllocator, 1, flags),
+    Err(MapRegionError::Map(_))
+  ));
+  // neither failure used up window space
+  let second = memory::map_region(mapper, frame_allocator, 1, flags).expect("map_region failed!\n");
+  assert_eq!(second, first + 4096u64);
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ember_os::{
+  exit::{exit_qemu, QemuExitCode},
+  serial_print, serial_println,
+  test_framework::ReportChecker,
+  vga_buffer,
+};
+
+/// Selector past the end of the GDT
+const BAD_SELECTOR: u16 = 0x50;
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(boot_info: &'static BootInfo) -> ! {
+  serial_print!("\ngeneral_protection_fault::handler_reports_selector ... ");
+
+  ember_os::minimum_init(boot_info);
+  vga_buffer::set_output(Some(Box::new(ReportChecker::new(&[
+    "EXCEPTION: GENERAL PROTECTION FAULT",
+    "Error Code: 0x50 (GDT[10])",
+  ]))));
+
+  // loading a selector outside of the GDT raises #GP(selector)
+  unsafe { core::arch::asm!("mov ds, {0:x}", in(reg) BAD_SELECTOR) };
+
+  // red
+  serial_print!("\x1b[31m");
+  serial_print!("[execution continued after #GP]");
+  serial_println!("\x1b[0m");
+  exit_qemu(QemuExitCode::Failed);
+  ember_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  ember_os::test_panic_handler(info)
+}
@@ -9,7 +9,7 @@ extern crate alloc;
 use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use ember_os::allocator::HEAP_SIZE;
+use ember_os::allocator::{heap_stats, ALLOCATOR_NAME, HEAP_SIZE};
 
 entry_point!(main);
 
@@ -71,3 +71,18 @@ fn many_boxes_long_lived() {
   });
   assert_eq!(*long_lived, 1);
 }
+
+#[test_case]
+fn heap_stats_track_usage() {
+  let before = heap_stats();
+  assert_eq!(before.total, HEAP_SIZE);
+  let value = Box::new([0u8; 64]);
+  let during = heap_stats();
+  assert!(during.used >= before.used + 64);
+  assert_eq!(during.total, HEAP_SIZE);
+  drop(value);
+  // the bump allocator only reclaims once everything is freed
+  if ALLOCATOR_NAME != "bump" {
+    assert_eq!(heap_stats().used, before.used);
+  }
+}
@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ember_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ember_os::{
+  allocator::{self, HEAP_SIZE},
+  memory::{self, BootInfoFrameAllocator},
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(boot_info: &'static BootInfo) -> ! {
+  ember_os::gdt::init();
+  ember_os::interrupts::init_idt();
+  unsafe { ember_os::interrupts::PICS.lock().initialize() };
+  x86_64::instructions::interrupts::enable();
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init(phys_mem_offset) };
+  let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap_from_map(boot_info, &mut mapper, &mut frame_allocator)
+    .expect("heap initialization failed!\n");
+  test_main();
+  ember_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  ember_os::test_panic_handler(info)
+}
+
+/// QEMU runs with `-m 256M` (see `test-args`)
+#[test_case]
+fn heap_larger_than_fixed_size() {
+  assert!(allocator::heap_size() > HEAP_SIZE);
+}
+
+#[test_case]
+fn allocation_beyond_fixed_size() {
+  let n = HEAP_SIZE;
+  let vec = (0..n).map(|i| i as u8).collect::<Vec<_>>();
+  assert_eq!(vec.len(), n);
+  assert_eq!(vec[n - 1], (n - 1) as u8);
+}
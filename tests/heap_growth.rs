@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ember_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ember_os::{
+  allocator::{self, HEAP_SIZE},
+  memory::{self, BootInfoFrameAllocator},
+};
+use spin::Mutex;
+use x86_64::{structures::paging::OffsetPageTable, VirtAddr};
+
+/// What `grow_heap` needs, set up by `main`
+static MEMORY: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(boot_info: &'static BootInfo) -> ! {
+  ember_os::gdt::init();
+  ember_os::interrupts::init_idt();
+  unsafe { ember_os::interrupts::PICS.lock().initialize() };
+  x86_64::instructions::interrupts::enable();
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init(phys_mem_offset) };
+  let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed!\n");
+  *MEMORY.lock() = Some((mapper, frame_allocator));
+  test_main();
+  ember_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  ember_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn large_vec_fits_after_growing() {
+  const LEN: usize = HEAP_SIZE + HEAP_SIZE / 2;
+  let mut vec = Vec::<u8>::new();
+  assert!(vec.try_reserve_exact(LEN).is_err());
+
+  let mut memory = MEMORY.lock();
+  let (mapper, frame_allocator) = memory.as_mut().unwrap();
+  allocator::grow_heap(mapper, frame_allocator, HEAP_SIZE).expect("growing the heap failed!\n");
+  assert_eq!(allocator::heap_size(), 2 * HEAP_SIZE);
+
+  vec
+    .try_reserve_exact(LEN)
+    .expect("allocation failed after growing!\n");
+  vec.resize(LEN, 0xa5);
+  assert_eq!(vec[LEN - 1], 0xa5);
+}
@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ember_os::{
+  exit::{exit_qemu, QemuExitCode},
+  serial_print, serial_println,
+  test_framework::ReportChecker,
+  vga_buffer,
+};
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(boot_info: &'static BootInfo) -> ! {
+  serial_print!("\ninvalid_opcode::handler_reports_ud2 ... ");
+
+  ember_os::minimum_init(boot_info);
+  vga_buffer::set_output(Some(Box::new(ReportChecker::new(&[
+    "EXCEPTION: INVALID OPCODE",
+  ]))));
+
+  unsafe { core::arch::asm!("ud2") };
+
+  // red
+  serial_print!("\x1b[31m");
+  serial_print!("[execution continued after ud2]");
+  serial_println!("\x1b[0m");
+  exit_qemu(QemuExitCode::Failed);
+  ember_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  ember_os::test_panic_handler(info)
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::{
+  panic::PanicInfo,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+use ember_os::{
+  exit::{exit_qemu, QemuExitCode},
+  interrupts::ist_safe_print,
+  serial_print,
+};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+/// Neither mapped by the bootloader nor by the kernel
+const UNMAPPED: u64 = 0xdead_beef_0000;
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  ember_os::test_panic_handler(info)
+}
+
+lazy_static! {
+  static ref TEST_IDT: InterruptDescriptorTable = {
+    let mut idt = InterruptDescriptorTable::new();
+    // no double fault handler: any fault the page fault handler can't take
+    // ends in a triple fault (a reset)
+    unsafe {
+      idt
+        .page_fault
+        .set_handler_fn(test_page_fault_handler)
+        .set_stack_index(ember_os::gdt::PAGE_FAULT_IST_INDEX);
+    }
+    idt
+  };
+}
+
+static PAGE_FAULTS: AtomicUsize = AtomicUsize::new(0);
+
+extern "x86-interrupt" fn test_page_fault_handler(
+  _stack_frame: InterruptStackFrame,
+  _error_code: PageFaultErrorCode,
+) {
+  if PAGE_FAULTS.fetch_add(1, Ordering::SeqCst) == 0 {
+    // fault again, while handling the first fault
+    unsafe { (UNMAPPED as *const u64).read_volatile() };
+  }
+  // green (the stack of the first fault is gone, so avoid the locked serial path)
+  ist_safe_print("\x1b[32m[ok]\x1b[0m\n\n");
+  exit_qemu(QemuExitCode::Success);
+  ember_os::hlt_loop()
+}
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(_boot_info: &'static BootInfo) -> ! {
+  serial_print!("\npage_fault_ist::nested_page_fault ... ");
+
+  ember_os::gdt::init();
+  TEST_IDT.load();
+
+  // a page fault with an unusable stack, only survivable on an IST stack
+  unsafe {
+    core::arch::asm!("mov rsp, {0}", "push rax", in(reg) UNMAPPED, options(noreturn));
+  }
+}
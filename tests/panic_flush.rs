@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, string::String};
+use bootloader::{entry_point, BootInfo};
+use core::{fmt, panic::PanicInfo};
+use ember_os::{
+  exit::{exit_qemu, QemuExitCode},
+  print, serial_print, serial_println,
+  vga_buffer::{self, OutputTarget, WRITER},
+};
+
+const BUFFERED: &str = "buffered before the panic";
+
+/// Holds everything printed until `flush`
+struct BufferedTarget {
+  pending: String,
+}
+
+impl fmt::Write for BufferedTarget {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.pending.push_str(s);
+    Ok(())
+  }
+}
+
+impl OutputTarget for BufferedTarget {
+  fn flush(&mut self) {
+    if let Some(mut writer) = WRITER.try_lock() {
+      writer.write_string(&self.pending);
+    }
+    self.pending.clear();
+  }
+}
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(boot_info: &'static BootInfo) -> ! {
+  serial_print!("\npanic_flush::buffered_output_survives_panic ... ");
+  ember_os::minimum_init(boot_info);
+
+  vga_buffer::set_output(Some(Box::new(BufferedTarget {
+    pending: String::new(),
+  })));
+  print!("{}\n", BUFFERED);
+  panic!("deliberate panic");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  ember_os::print_panic(info);
+
+  let rows = x86_64::instructions::interrupts::without_interrupts(|| WRITER.lock().rows());
+  let shows = |text: &str| rows.iter().any(|row| row.contains(text));
+  if shows(BUFFERED) && shows("deliberate panic") {
+    // green
+    serial_print!("\x1b[32m");
+    serial_print!("[ok]");
+    serial_print!("\x1b[0m");
+    serial_println!("\n");
+    exit_qemu(QemuExitCode::Success);
+  } else {
+    // red
+    serial_print!("\x1b[31m");
+    serial_print!("[failed]");
+    serial_println!("\x1b[0m");
+    exit_qemu(QemuExitCode::Failed);
+  }
+  ember_os::hlt_loop()
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(ember_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use ember_os::{
+  allocator::{self, HEAP_START},
+  memory::{self, BootInfoFrameAllocator},
+};
+use x86_64::VirtAddr;
+
+const SMALL_HEAP_SIZE: usize = 16 * 1024;
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(boot_info: &'static BootInfo) -> ! {
+  ember_os::gdt::init();
+  ember_os::interrupts::init_idt();
+  unsafe { ember_os::interrupts::PICS.lock().initialize() };
+  x86_64::instructions::interrupts::enable();
+  let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+  let mut mapper = unsafe { memory::init(phys_mem_offset) };
+  let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+  allocator::init_heap_with(
+    HEAP_START,
+    SMALL_HEAP_SIZE,
+    &mut mapper,
+    &mut frame_allocator,
+  )
+  .expect("heap initialization failed!\n");
+  test_main();
+  ember_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  ember_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn heap_has_the_given_size() {
+  assert_eq!(allocator::heap_size(), SMALL_HEAP_SIZE);
+  assert_eq!(allocator::heap_stats().total, SMALL_HEAP_SIZE);
+}
+
+#[test_case]
+fn allocation_beyond_small_heap_fails() {
+  let mut vec = Vec::<u8>::new();
+  assert!(vec.try_reserve_exact(SMALL_HEAP_SIZE).is_err());
+  assert!(vec.try_reserve_exact(SMALL_HEAP_SIZE / 4).is_ok());
+}
+
+#[test_case]
+fn many_boxes_are_reclaimed() {
+  for i in 0..SMALL_HEAP_SIZE {
+    let x = Box::new(i);
+    assert_eq!(*x, i);
+  }
+}
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::{fmt, panic::PanicInfo};
+use ember_os::{
+  exit::{exit_qemu, QemuExitCode},
+  serial_print, serial_println,
+  test_framework::MessageBuffer,
+};
+
+entry_point!(main);
+
+#[no_mangle]
+fn main(_boot_info: &'static BootInfo) -> ! {
+  serial_print!("\nunhandled_exception::catch_all_reports_vector ... ");
+
+  ember_os::gdt::init();
+  ember_os::interrupts::init_idt();
+
+  // bound range exceeded (vector 5), which has no dedicated handler
+  unsafe { core::arch::asm!("int 5") };
+
+  // red
+  serial_print!("\x1b[31m");
+  serial_print!("[execution continued after int 5]");
+  serial_println!("\x1b[0m");
+  exit_qemu(QemuExitCode::Failed);
+  ember_os::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+  use fmt::Write;
+
+  let mut message = MessageBuffer::<128>::new();
+  let _ = write!(message, "{}", info.message());
+  if message
+    .as_str()
+    .starts_with("EXCEPTION: UNHANDLED VECTOR 5 (BOUND RANGE EXCEEDED)")
+  {
+    // green
+    serial_print!("\x1b[32m");
+    serial_print!("[ok]");
+    serial_print!("\x1b[0m");
+    serial_println!("\n");
+    exit_qemu(QemuExitCode::Success);
+  } else {
+    // red
+    serial_print!("\x1b[31m");
+    serial_print!("[failed]");
+    serial_println!("\x1b[0m");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+  }
+  ember_os::hlt_loop()
+}